@@ -0,0 +1,144 @@
+//Writing of scores in ABC notation, a plain text format for sheet music that suits single melodic lines best.
+//The unit note length is the grid of the score, so every length is a whole number of units.
+//Tempo changes are written at the start of the measure that they fall in.
+
+use std::io::{self,Write};
+
+use key::KeySignature;
+use notation::{self,Clef,Event,Measure,Part,Score,Spelling,STEP_NAMES};
+use song::Song;
+use tempo;
+
+///How many measures are written on a line.
+const MEASURES_PER_LINE: usize = 4;
+
+///Writes the score as a tune with an ABC voice for every voice of every part. The voices of a part share a staff.
+pub fn write_abc<W: Write>(song: &Song,score: &Score,out: &mut W) -> io::Result<()>{
+	let first = score.measures.first();
+	let voice_ids: Vec<Vec<String>> = score.parts.iter().enumerate().map(|(i,part)|{
+		let voices = part.voices.iter().map(|voices| voices.len()).max().unwrap_or(0);
+		(0..voices).map(|voice| format!("P{}V{}",i+1,voice+1)).collect()
+	}).collect();
+
+	writeln!(out,"X:1")?;
+	writeln!(out,"T:{}",song.track_name(0).filter(|name| !name.is_empty()).unwrap_or("Untitled"))?;
+	if let Some(measure) = first{
+		writeln!(out,"M:{}/{}",measure.signature.numerator,measure.signature.denominator)?;
+	}
+	writeln!(out,"L:1/{}",score.grid)?;
+	if let Some(change) = score.tempos(song,0).next().filter(|change| change.time == 0){
+		writeln!(out,"Q:1/4={}",tempo::bpm(change.tempo).round())?;
+	}
+	let staves: Vec<String> = voice_ids.iter().filter(|ids| !ids.is_empty()).map(|ids| if ids.len() > 1{format!("({})",ids.join(" "))}else{ids[0].clone()}).collect();
+	writeln!(out,"%%score {}",staves.join(" "))?;
+	for (part,ids) in score.parts.iter().zip(&voice_ids){
+		for (voice,id) in ids.iter().enumerate(){
+			write!(out,"V:{} clef={}",id,clef_name(part.clef))?;
+			if voice == 0{
+				write!(out," name=\"{}\"",part.name.replace('"',"'"))?;
+			}
+			writeln!(out)?;
+		}
+	}
+	writeln!(out,"K:{}",first.map_or("C".to_string(),|measure| key_name(measure.key)))?;
+
+	for (i,(part,ids)) in score.parts.iter().zip(&voice_ids).enumerate(){
+		for (voice,id) in ids.iter().enumerate(){
+			writeln!(out,"V:{}",id)?;
+			if part.percussion{
+				writeln!(out,"%%MIDI channel 10")?;
+				//The notes are written where the drums are on the staff, so no key may alter them
+				write!(out,"[K:C clef=perc] ")?;
+			}else{
+				writeln!(out,"%%MIDI program {}",part.program)?;
+			}
+
+			let mut previous: Option<&Measure> = None;
+			for (m,(measure,events)) in score.measures.iter().zip(&part.voices).enumerate(){
+				if let Some(previous) = previous{
+					if !part.percussion && (previous.key.sharps,previous.key.minor) != (measure.key.sharps,measure.key.minor){
+						write!(out,"[K:{} clef={}] ",key_name(measure.key),clef_name(part.clef))?;
+					}
+					if (previous.signature.numerator,previous.signature.denominator) != (measure.signature.numerator,measure.signature.denominator){
+						write!(out,"[M:{}/{}] ",measure.signature.numerator,measure.signature.denominator)?;
+					}
+				}
+				if i == 0 && voice == 0{
+					for change in score.tempos(song,m).filter(|change| change.time > 0){
+						write!(out,"[Q:1/4={}] ",tempo::bpm(change.tempo).round())?;
+					}
+				}
+				match events.get(voice).filter(|events| !events.is_empty()){
+					Some(events) => for event in events{
+						write!(out,"{} ",event_text(part,measure,event))?;
+					},
+					//Invisible rests keep the voice in time
+					None => write!(out,"x{} ",length(measure.length))?,
+				}
+
+				if m+1 == score.measures.len(){
+					writeln!(out,"|]")?;
+				}else if (m+1) % MEASURES_PER_LINE == 0{
+					writeln!(out,"|")?;
+				}else{
+					write!(out,"| ")?;
+				}
+				previous = Some(measure);
+			}
+		}
+	}
+	Ok(())
+}
+
+fn event_text(part: &Part,measure: &Measure,event: &Event) -> String{
+	let length = length(event.duration.units);
+	let tie = if event.tied_to{"-"}else{""};
+	let name = |pitch: &notation::Pitch|{
+		//Accidentals do not carry across barlines in ABC, even into tied notes
+		let spelling = pitch.spelling;
+		let accidental = !part.percussion && (pitch.accidental || (event.tied_from && event.offset == 0 && spelling.alter != notation::key_alter(measure.key,spelling.step)));
+		pitch_name(spelling,accidental)
+	};
+	match event.pitches.len(){
+		0 => format!("z{}",length),
+		1 => format!("{}{}{}",name(&event.pitches[0]),length,tie),
+		_ => format!("[{}]{}{}",event.pitches.iter().map(name).collect::<String>(),length,tie),
+	}
+}
+
+///A length in unit note lengths, where a single unit is written as nothing.
+fn length(units: u32) -> String{
+	if units == 1{String::new()}else{units.to_string()}
+}
+
+///The note in ABC, where `C` is middle C and `c` is an octave higher.
+fn pitch_name(spelling: Spelling,accidental: bool) -> String{
+	let mut name = String::new();
+	if accidental{
+		name.push_str(match spelling.alter{
+			-1 => "_",
+			 1 => "^",
+			 _ => "=",
+		});
+	}
+	let letter = STEP_NAMES[spelling.step as usize];
+	if spelling.octave >= 5{
+		name.push(letter.to_ascii_lowercase());
+		name.push_str(&"'".repeat((spelling.octave - 5) as usize));
+	}else{
+		name.push(letter);
+		name.push_str(&",".repeat((4 - spelling.octave) as usize));
+	}
+	name
+}
+
+fn key_name(key: KeySignature) -> String{
+	let tonic = notation::tonic(key);
+	format!("{}{}{}",STEP_NAMES[tonic.step as usize],match tonic.alter{-1 => "b",1 => "#",_ => ""},if key.minor{"m"}else{""})
+}
+
+fn clef_name(clef: Clef) -> &'static str{match clef{
+	Clef::Treble     => "treble",
+	Clef::Bass       => "bass",
+	Clef::Percussion => "perc",
+}}
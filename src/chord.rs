@@ -0,0 +1,204 @@
+//Recognition of the chords formed by simultaneously sounding tones.
+
+use core::fmt;
+
+use midi_ext::*;
+use song::{visible_tones,Song};
+
+///Names of the pitch classes.
+pub const PITCH_CLASS_NAMES: [&str; 12] = ["C","C#","D","Eb","E","F","F#","G","Ab","A","Bb","B"];
+
+///A kind of chord by its symbol and its intervals in semitones from the root.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Quality{
+	pub symbol   : &'static str,
+	pub intervals: &'static [u8],
+}
+
+///The chords that can be recognized. When two match equally well, the first one is chosen.
+pub const QUALITIES: &[Quality] = &[
+	//Triads
+	Quality{symbol: ""     ,intervals: &[0,4,7]},
+	Quality{symbol: "m"    ,intervals: &[0,3,7]},
+	Quality{symbol: "dim"  ,intervals: &[0,3,6]},
+	Quality{symbol: "aug"  ,intervals: &[0,4,8]},
+	Quality{symbol: "sus4" ,intervals: &[0,5,7]},
+	Quality{symbol: "sus2" ,intervals: &[0,2,7]},
+	Quality{symbol: "5"    ,intervals: &[0,7]},
+	//Sixths and sevenths
+	Quality{symbol: "7"    ,intervals: &[0,4,7,10]},
+	Quality{symbol: "maj7" ,intervals: &[0,4,7,11]},
+	Quality{symbol: "m7"   ,intervals: &[0,3,7,10]},
+	Quality{symbol: "6"    ,intervals: &[0,4,7,9]},
+	Quality{symbol: "m6"   ,intervals: &[0,3,7,9]},
+	Quality{symbol: "m7b5" ,intervals: &[0,3,6,10]},
+	Quality{symbol: "dim7" ,intervals: &[0,3,6,9]},
+	Quality{symbol: "mMaj7",intervals: &[0,3,7,11]},
+	Quality{symbol: "7sus4",intervals: &[0,5,7,10]},
+	Quality{symbol: "aug7" ,intervals: &[0,4,8,10]},
+	//Extensions
+	Quality{symbol: "add9" ,intervals: &[0,4,7,2]},
+	Quality{symbol: "madd9",intervals: &[0,3,7,2]},
+	Quality{symbol: "9"    ,intervals: &[0,4,7,10,2]},
+	Quality{symbol: "maj9" ,intervals: &[0,4,7,11,2]},
+	Quality{symbol: "m9"   ,intervals: &[0,3,7,10,2]},
+	Quality{symbol: "6/9"  ,intervals: &[0,4,7,9,2]},
+	Quality{symbol: "7b9"  ,intervals: &[0,4,7,10,1]},
+	Quality{symbol: "7#9"  ,intervals: &[0,4,7,10,3]},
+	Quality{symbol: "11"   ,intervals: &[0,4,7,10,2,5]},
+	Quality{symbol: "m11"  ,intervals: &[0,3,7,10,2,5]},
+	Quality{symbol: "13"   ,intervals: &[0,4,7,10,2,9]},
+];
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Chord{
+	///Pitch class of the root, from 0 for C to 11 for B.
+	pub root   : u8,
+	pub quality: Quality,
+	///Pitch class of the lowest tone. Inversions are written as slash chords with it.
+	pub bass   : u8,
+}
+
+impl fmt::Display for Chord{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{
+		write!(f,"{}{}",PITCH_CLASS_NAMES[self.root as usize % 12],self.quality.symbol)?;
+		if self.bass != self.root{
+			write!(f,"/{}",PITCH_CLASS_NAMES[self.bass as usize % 12])?;
+		}
+		Ok(())
+	}
+}
+
+///Names the chord formed by a set of pitch classes, given as bits from bit 0 for C, and the pitch class of the lowest tone.
+///
+///Every interval of a chord must be present, except the fifth of chords with four or more tones, which is often left out.
+///Among the matching chords, the one explaining the most pitch classes is chosen, preferring chords with the root in the bass.
+pub fn recognize(pitch_classes: u16,bass: u8) -> Option<Chord>{
+	let count = pitch_classes.count_ones() as i32;
+	if count < 2{return None}
+
+	let mut best: Option<(i32,Chord)> = None;
+	for root in (0..12).filter(|&root| pitch_classes & (1 << root) != 0){
+		for &quality in QUALITIES{
+			let template = quality.intervals.iter().fold(0u16,|mask,&interval| mask | 1 << ((root + interval) % 12));
+			let missing = template & !pitch_classes;
+			let fifth = 1 << ((root + 7) % 12);
+			if missing != 0 && !(missing == fifth && quality.intervals.len() >= 4){continue}
+
+			let matched = (template & pitch_classes).count_ones() as i32;
+			let extra = count - matched;
+			let score = matched*2 - extra*3 - (missing != 0) as i32 + (root == bass) as i32;
+			if best.map_or(true,|(best_score,_)| score > best_score){
+				best = Some((score,Chord{root,quality,bass}));
+			}
+		}
+	}
+	best.map(|(_,chord)| chord)
+}
+
+///How the song is divided into the time spans that chords are recognized in.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Segmentation{
+	///A chord for every beat.
+	Beat,
+	///A chord whenever a tone starts or ends.
+	Change,
+}
+
+impl Segmentation{
+	pub const ALL: [Segmentation; 2] = [Segmentation::Beat,Segmentation::Change];
+
+	pub fn name(self) -> &'static str{match self{
+		Segmentation::Beat   => "Per beat",
+		Segmentation::Change => "Per change",
+	}}
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct ChordSpan{
+	///Time in ticks.
+	pub start: u32,
+	///Time in ticks.
+	pub end  : u32,
+	pub chord: Chord,
+}
+
+///Recognizes the chords in the range of ticks, using the tones for which `accept`, given the track index and the tone, returns true.
+///The tones on the drum channels have no pitch, so they are left out.
+///Consecutive spans of the same chord are merged, and spans without a chord are left out.
+pub fn detect<F: Fn(usize,&Tone) -> bool>(song: &Song,accept: F,drums: &[u8],segmentation: Segmentation,range: (u32,u32)) -> Vec<ChordSpan>{
+	let accept = &accept;
+	let tones: Vec<&Tone> = song.tracks
+		.iter()
+		.enumerate()
+		.flat_map(|(track,tones)| visible_tones(tones,(range.0 as f64 , range.1 as f64),(0.0 , NOTES as f64)).filter(move |&(_,tone)| accept(track,tone)).map(|(_,tone)| tone))
+		.filter(|tone| !drums.contains(&tone.channel))
+		.collect();
+
+	//Boundaries of the spans
+	let mut times: Vec<u32> = match segmentation{
+		Segmentation::Beat   => song.tempo.beats(song.division,(range.0 as f64 , range.1 as f64)).into_iter().map(|(time,_)| time).collect(),
+		Segmentation::Change => tones.iter().flat_map(|tone| vec![tone.start_time , tone.end_time]).filter(|&time| time > range.0 && time < range.1).collect(),
+	};
+	times.push(range.0);
+	times.push(range.1);
+	times.sort();
+	times.dedup();
+
+	let mut spans: Vec<ChordSpan> = Vec::new();
+	for window in times.windows(2){
+		let (start,end) = (window[0],window[1]);
+		//Pitch classes sounding for at least a quarter of the span
+		let threshold = (end - start) as f64 / 4.0;
+		let mut weights = [0.0; 12];
+		let mut lowest = None;
+		for tone in &tones{
+			let overlap = tone.end_time.min(end) as f64 - tone.start_time.max(start) as f64;
+			if overlap <= 0.0{continue}
+			let note = Into::<u8>::into(tone.note);
+			weights[note as usize % 12]+= overlap;
+			if overlap >= threshold && lowest.map_or(true,|lowest| note < lowest){
+				lowest = Some(note);
+			}
+		}
+		let pitch_classes = weights.iter().enumerate().filter(|&(_,&weight)| weight >= threshold).fold(0u16,|mask,(pitch_class,_)| mask | 1 << pitch_class);
+
+		if let Some(chord) = lowest.and_then(|lowest| recognize(pitch_classes,lowest % 12)){
+			match spans.last_mut(){
+				Some(span) if span.end == start && span.chord == chord => span.end = end,
+				_ => spans.push(ChordSpan{start,end,chord}),
+			}
+		}
+	}
+	spans
+}
+
+///A chord chart with a line of four bars, where each bar lists the chords starting in it.
+///A bar without a new chord continues the previous one, which is written as `%`.
+pub fn chart(song: &Song,spans: &[ChordSpan]) -> String{
+	const BARS_PER_LINE: usize = 4;
+
+	let bars: Vec<u32> = song.tempo.beats(song.division,(0.0 , song.duration as f64))
+		.into_iter()
+		.filter(|&(_,bar)| bar)
+		.map(|(time,_)| time)
+		.collect();
+
+	let mut chart = String::new();
+	for (i,&bar_start) in bars.iter().enumerate(){
+		let bar_end = bars.get(i+1).cloned().unwrap_or(song.duration);
+		let chords: Vec<String> = spans.iter()
+			.filter(|span| span.start >= bar_start && span.start < bar_end)
+			.map(|span| span.chord.to_string())
+			.collect();
+
+		chart.push_str(if i % BARS_PER_LINE == 0{"|"}else{""});
+		chart.push(' ');
+		chart.push_str(&if chords.is_empty(){"%".to_string()}else{chords.join(" ")});
+		chart.push_str(" |");
+		if i % BARS_PER_LINE == BARS_PER_LINE-1 || i == bars.len()-1{
+			chart.push('\n');
+		}
+	}
+	chart
+}
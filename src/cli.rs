@@ -0,0 +1,326 @@
+use core::fmt;
+use std::io;
+
+use chord::Segmentation;
+use load::{LoadError,LoadOptions};
+use midi_ext::PERCUSSION_CHANNEL;
+use note_list::ImportError;
+use song::Filter;
+use transform::Transform;
+
+pub const USAGE: &str = "\
+Usage: midi-notes-display [<command>] [<options>] <MIDI file>
+
+The MIDI file can be `-` for reading from standard input. The import command reads a note list instead.
+
+Commands:
+  view     Display the notes in a window (default)
+  info     Print information about the file
+  export   Export the notes to another format
+  render   Render the notes to a PNG image, or to a video of the notes scrolling past a playhead
+  convert  Transform the notes and write a MIDI file
+  import   Read a list of notes in CSV or JSON like export writes it, and write a MIDI file
+
+Options:
+  -o, --output <file>        Output file (`-` for standard output). The view saves edits to it, or else next
+                             to the input with `-edited.mid` at the end of the name
+  -f, --format <format>      Output format of export: `chords` (chord chart), `csv` (list of the notes),
+                             `json` (list of the notes, meta events and tempo map), `musicxml`, `lilypond`
+                             or `abc` (sheet music quantized to sixteenth notes) or `svg` (image of the notes),
+                             of info: `text` (default) or `json`,
+                             of import: `csv` or `json` (default: from the extension of the input file),
+                             or of render: `png` (default), `frames` (a PNG file for every frame, named by
+                             appending the frame number to the output) or `y4m` (YUV4MPEG2 video)
+      --svg                  Export an SVG image, the same as `--format svg`
+      --fps <rate>           Frames per second of rendered videos (default: 30)
+      --audio <file>         Also write the sound of the rendered video as a WAVE file, which can be combined
+                             with the video using e.g. `ffmpeg -i video.y4m -i audio.wav video.mp4`
+  -t, --transform <t>        Transformation to apply, e.g. `transpose=12`, `velocity=0.8`, `stretch=2`,
+                             `compress-velocity=0.5`, `randomize-velocity=10`, `reverse` or `invert=60`
+      --lenient              Recover as much as possible from damaged files
+      --tracks <list>        Only include the tracks, numbered from 0, e.g. `1,2,5`
+      --channels <list>      Only include the channels, numbered from 1, e.g. `1,10`
+      --drums <list>         Channels of percussion without pitch, numbered from 1, which are shown as drum rows
+                             in the GUI and left out of the analysis of chords and keys (default: `10`)
+      --start <ticks>        Time to start viewing or exporting from
+      --end <ticks>          Time to end exporting or rendering at (default: the end of the song)
+      --font <file>          Font file to use instead of a system font
+      --zoom <width>         Width of a tick in pixels
+      --note-height <height> Height of a note in pixels
+      --size <w>x<h>         Window or image size in pixels
+      --chords <span>        Recognize a chord per `beat` (default) or per `change` of the sounding tones
+  -h, --help                 Print this help
+";
+
+///The largest width and height of windows and images, so that rendering does not try to allocate too much memory.
+pub const MAX_SIZE: u32 = 8192;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Command{
+	View,
+	Info,
+	Export,
+	Render,
+	Convert,
+	Import,
+	Help,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Options{
+	pub input      : String,
+	pub load       : LoadOptions,
+	pub output     : Option<String>,
+	pub format     : Option<String>,
+	pub transforms : Vec<Transform>,
+	pub filter     : Filter,
+	pub start      : u32,
+	pub end        : Option<u32>,
+	pub font       : Option<String>,
+	pub zoom       : f64,
+	pub note_height: f64,
+	pub size       : [u32; 2],
+	pub chords     : Segmentation,
+	pub fps        : u32,
+	pub audio      : Option<String>,
+	///The channels of percussion, which the GUI shows as drum rows.
+	pub drums      : Vec<u8>,
+}
+
+impl Default for Options{
+	fn default() -> Self{
+		Options{
+			input      : String::new(),
+			load       : LoadOptions::default(),
+			output     : None,
+			format     : None,
+			transforms : Vec::new(),
+			filter     : Filter::default(),
+			start      : 0,
+			end        : None,
+			font       : None,
+			zoom       : 1.0,
+			note_height: 16.0,
+			size       : [800,600],
+			chords     : Segmentation::Beat,
+			fps        : 30,
+			audio      : None,
+			drums      : vec![PERCUSSION_CHANNEL],
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum CliError{
+	///Invalid command line arguments.
+	Usage(String),
+	///Reading or writing a file failed.
+	Io(String,io::Error),
+	///The input could not be loaded.
+	Load(LoadError),
+	///The note list to import is invalid.
+	Import(ImportError),
+	///The requested operation is not supported.
+	Unsupported(String),
+	///The GUI could not be started.
+	Gui(String),
+}
+
+impl CliError{
+	///The exit code of the process when this error occurs.
+	pub fn exit_code(&self) -> i32{match *self{
+		CliError::Usage(_)       => 2,
+		CliError::Io(..)         => 3,
+		CliError::Load(_)        => 4,
+		CliError::Import(_)      => 4,
+		CliError::Unsupported(_) => 5,
+		CliError::Gui(_)         => 6,
+	}}
+}
+
+impl fmt::Display for CliError{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{match *self{
+		CliError::Usage(ref message)       => write!(f,"{}\nTry `--help` for more information.",message),
+		CliError::Io(ref path,ref error)   => write!(f,"\"{}\": {}",path,error),
+		CliError::Load(ref error)          => write!(f,"{}",error),
+		CliError::Import(ref error)        => write!(f,"{}",error),
+		CliError::Unsupported(ref message) => write!(f,"{}",message),
+		CliError::Gui(ref message)         => write!(f,"{}",message),
+	}}
+}
+
+///Parses the command line arguments, excluding the program name.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<(Command,Options),CliError>{
+	let mut command = None;
+	let mut input = None;
+	let mut options = Options::default();
+
+	let mut args = args.into_iter();
+	while let Some(arg) = args.next(){
+		let mut value = |name: &str| args.next().ok_or_else(|| CliError::Usage(format!("Expected a value after {}",name)));
+
+		match arg.as_str(){
+			"-h" | "--help"      => command = Some(Command::Help),
+			"-o" | "--output"    => options.output = Some(value(&arg)?),
+			"-f" | "--format"    => options.format = Some(value(&arg)?),
+			"-t" | "--transform" => options.transforms.push(value(&arg)?.parse().map_err(CliError::Usage)?),
+			"--lenient"          => options.load.lenient = true,
+			"--tracks"           => options.filter.tracks = Some(parse_list(&arg,&value(&arg)?)?),
+			"--channels"         => options.filter.channels = Some(parse_channels(&arg,&value(&arg)?)?),
+			"--drums"            => options.drums = parse_channels(&arg,&value(&arg)?)?,
+			"--svg"              => options.format = Some("svg".to_string()),
+			"--start"            => options.start = parse_number(&arg,&value(&arg)?)?,
+			"--end"              => options.end = Some(parse_number(&arg,&value(&arg)?)?),
+			"--font"             => options.font = Some(value(&arg)?),
+			"--zoom"             => options.zoom = match parse_number(&arg,&value(&arg)?)?{
+				zoom if zoom > 0.0 && f64::is_finite(zoom) => zoom,
+				_ => return Err(CliError::Usage("The width of a tick must be positive".to_string())),
+			},
+			"--note-height"      => options.note_height = match parse_number(&arg,&value(&arg)?)?{
+				height if height > 0.0 && f64::is_finite(height) => height,
+				_ => return Err(CliError::Usage("The height of a note must be positive".to_string())),
+			},
+			"--size"             => {
+				let size = value(&arg)?;
+				let mut parts = size.splitn(2,'x');
+				options.size = match (parts.next().map(str::parse),parts.next().map(str::parse)){
+					(Some(Ok(w)),Some(Ok(h))) if w > 0 && h > 0 && w <= MAX_SIZE && h <= MAX_SIZE => [w,h],
+					_ => return Err(CliError::Usage(format!("Invalid size \"{}\". Expected <width>x<height> of at most {}x{}",size,MAX_SIZE,MAX_SIZE))),
+				};
+			},
+			"--chords"           => options.chords = match value(&arg)?.as_str(){
+				"beat"   => Segmentation::Beat,
+				"change" => Segmentation::Change,
+				other    => return Err(CliError::Usage(format!("Invalid chord span \"{}\". Expected `beat` or `change`",other))),
+			},
+			"--fps"              => options.fps = match parse_number(&arg,&value(&arg)?)?{
+				0 => return Err(CliError::Usage("The frame rate must be positive".to_string())),
+				fps => fps,
+			},
+			"--audio"            => options.audio = Some(value(&arg)?),
+			"-" => set_input(&mut input,arg.clone())?,
+			_ if arg.starts_with('-') => return Err(CliError::Usage(format!("Unknown option: {}",arg))),
+			_ if command.is_none() && input.is_none() => command = Some(match arg.as_str(){
+				"view"    => Command::View,
+				"info"    => Command::Info,
+				"export"  => Command::Export,
+				"render"  => Command::Render,
+				"convert" => Command::Convert,
+				"import"  => Command::Import,
+				_ => {
+					//Without a command, the first argument is the file to view.
+					set_input(&mut input,arg.clone())?;
+					Command::View
+				},
+			}),
+			_ => set_input(&mut input,arg.clone())?,
+		}
+	}
+
+	let command = command.unwrap_or(Command::View);
+	if command != Command::Help{
+		options.input = input.ok_or_else(|| CliError::Usage("MIDI file path is unspecified".to_string()))?;
+	}
+	Ok((command,options))
+}
+
+fn set_input(input: &mut Option<String>,arg: String) -> Result<(),CliError>{
+	if input.is_some(){
+		return Err(CliError::Usage(format!("Unexpected argument: {}. Only one input file can be specified",arg)));
+	}
+	*input = Some(arg);
+	Ok(())
+}
+
+fn parse_number<T: ::core::str::FromStr>(name: &str,value: &str) -> Result<T,CliError>{
+	value.parse().map_err(|_| CliError::Usage(format!("Invalid number \"{}\" for {}",value,name)))
+}
+
+fn parse_list<T: ::core::str::FromStr>(name: &str,value: &str) -> Result<Vec<T>,CliError>{
+	value.split(',').map(|item| parse_number(name,item.trim())).collect()
+}
+
+///Parses channels numbered from 1 into channel numbers from 0.
+fn parse_channels(name: &str,value: &str) -> Result<Vec<u8>,CliError>{
+	parse_list::<u8>(name,value)?
+		.into_iter()
+		.map(|channel| if channel >= 1 && channel <= 16{Ok(channel-1)}else{Err(CliError::Usage(format!("Invalid channel {}. Channels are numbered from 1 to 16",channel)))})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests{
+	use super::{parse,CliError,Command,Options};
+
+	fn parse_args(args: &[&str]) -> Result<(Command,Options),CliError>{
+		parse(args.iter().map(|arg| arg.to_string()))
+	}
+
+	fn is_usage_error(args: &[&str]) -> bool{
+		match parse_args(args){
+			Err(CliError::Usage(_)) => true,
+			_ => false,
+		}
+	}
+
+	#[test]
+	fn view_is_the_default_command(){
+		let (command,options) = parse_args(&["song.mid"]).unwrap();
+		assert_eq!((command,options.input.as_str()),(Command::View , "song.mid"));
+
+		let (command,options) = parse_args(&["--zoom" , "2" , "song.mid" , "--lenient"]).unwrap();
+		assert_eq!((command,options.input.as_str(),options.zoom,options.load.lenient),(Command::View , "song.mid" , 2.0 , true));
+
+		let (command,options) = parse_args(&["info" , "song.mid"]).unwrap();
+		assert_eq!((command,options.input.as_str()),(Command::Info , "song.mid"));
+
+		//Only the first argument can be a command, and there is only one input
+		assert!(is_usage_error(&["song.mid" , "info"]));
+		assert!(is_usage_error(&["info"]));
+		assert_eq!(parse_args(&["--help"]).unwrap().0,Command::Help);
+	}
+
+	#[test]
+	fn dash_is_the_standard_input(){
+		let (command,options) = parse_args(&["-"]).unwrap();
+		assert_eq!((command,options.input.as_str()),(Command::View , "-"));
+
+		let (command,options) = parse_args(&["export" , "-f" , "csv" , "-" , "-o" , "-"]).unwrap();
+		assert_eq!((command,options.input.as_str(),options.output.as_ref().map(String::as_str)),(Command::Export , "-" , Some("-")));
+		assert!(is_usage_error(&["-" , "-"]));
+	}
+
+	#[test]
+	fn unknown_options_and_missing_values_are_rejected(){
+		assert!(is_usage_error(&["--frobnicate" , "song.mid"]));
+		assert!(is_usage_error(&["-x" , "song.mid"]));
+		assert!(is_usage_error(&["song.mid" , "--output"]));
+		assert!(is_usage_error(&["song.mid" , "--transform" , "shuffle"]));
+	}
+
+	#[test]
+	fn channels_are_numbered_from_1_to_16(){
+		let (_,options) = parse_args(&["song.mid" , "--channels" , "1, 10,16" , "--drums" , "10,11"]).unwrap();
+		assert_eq!(options.filter.channels,Some(vec![0 , 9 , 15]));
+		assert_eq!(options.drums,vec![9 , 10]);
+		assert_eq!(parse_args(&["song.mid" , "--tracks" , "0,2"]).unwrap().1.filter.tracks,Some(vec![0 , 2]));
+
+		for channels in &["0" , "17" , "1,17" , "" , "1-4" , "-1"]{
+			assert!(is_usage_error(&["song.mid" , "--channels" , channels]),"{}",channels);
+		}
+	}
+
+	#[test]
+	fn sizes_and_scales_are_positive_and_bounded(){
+		assert_eq!(parse_args(&["song.mid" , "--size" , "640x480"]).unwrap().1.size,[640 , 480]);
+		assert_eq!(parse_args(&["song.mid" , "--note-height" , "2.5"]).unwrap().1.note_height,2.5);
+		for &(option,value) in &[
+			("--size" , "0x480") , ("--size" , "640") , ("--size" , "640x-1") , ("--size" , "100000x100000"),
+			("--zoom" , "0") , ("--zoom" , "-1") , ("--zoom" , "NaN") , ("--zoom" , "inf"),
+			("--note-height" , "0") , ("--note-height" , "-3") , ("--note-height" , "NaN"),
+			("--fps" , "0"),
+		]{
+			assert!(is_usage_error(&["song.mid" , option , value]),"{} {}",option,value);
+		}
+	}
+}
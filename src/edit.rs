@@ -0,0 +1,710 @@
+use core::cmp;
+use std::collections::BTreeSet;
+
+use midi_ext::*;
+use quantize::Quantize;
+use song::{Filter,Song,ToneId};
+use tempo::{TempoChange,TempoMap};
+use transform::{self,Context,TransformSettings};
+
+///The shortest length a tone can be resized or drawn to.
+pub const MIN_TONE_LENGTH: u32 = 1;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Tool{
+	///Selecting, moving and resizing existing tones.
+	Select,
+	///Drawing new tones.
+	Pencil,
+}
+
+///Which part of a tone is grabbed when dragging it.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Grab{
+	Start,
+	Body,
+	End,
+}
+
+///A point in the song. Time in ticks and pitch in note numbers, both possibly outside of the song.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct SongPos{
+	pub time: f64,
+	pub note: f64,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Drag{
+	///Moving or resizing the selected tones. `applied` is the (time,note) offset that has been applied to the tones so far.
+	Tones{grab: Grab,origin: SongPos,applied: (i64,i32)},
+	///Selecting the tones inside a rectangle.
+	RubberBand{origin: SongPos,current: SongPos},
+	///Drawing a new tone. Its length follows the pointer.
+	Draw{id: ToneId},
+	///Moving a tempo change by its index.
+	Tempo{index: usize},
+	///Moving the view with the minimap. `offset` is from the pointer to the middle of the view.
+	View{offset: SongPos},
+}
+
+///A set of selected tones.
+#[derive(Clone,Debug,Default)]
+pub struct Selection{
+	tones: BTreeSet<ToneId>,
+}
+
+impl Selection{
+	pub fn contains(&self,id: ToneId) -> bool{self.tones.contains(&id)}
+	pub fn is_empty(&self) -> bool{self.tones.is_empty()}
+	pub fn iter<'s>(&'s self) -> impl Iterator<Item = ToneId> + 's{self.tones.iter().cloned()}
+	pub fn clear(&mut self){self.tones.clear();}
+	pub fn insert(&mut self,id: ToneId){self.tones.insert(id);}
+
+	///Selects only the given tone.
+	pub fn select_only(&mut self,id: ToneId){
+		self.tones.clear();
+		self.tones.insert(id);
+	}
+
+	///Selects the tone if it was not selected, and deselects it otherwise.
+	pub fn toggle(&mut self,id: ToneId){
+		if !self.tones.remove(&id){
+			self.tones.insert(id);
+		}
+	}
+
+	///Selects every tone accepted by the filter that intersects the rectangle spanned by the two positions.
+	pub fn insert_rect(&mut self,song: &Song,filter: &Filter,a: SongPos,b: SongPos){
+		let (time_min,time_max) = (a.time.min(b.time) , a.time.max(b.time));
+		let (note_min,note_max) = (a.note.min(b.note) , a.note.max(b.note));
+		for (track,tones) in song.tracks.iter().enumerate(){
+			for (index,tone) in tones.iter().enumerate(){
+				let note = Into::<u8>::into(tone.note) as f64;
+				if (tone.end_time as f64) >= time_min && (tone.start_time as f64) <= time_max && note+1.0 >= note_min && note <= note_max && filter.accepts(track,tone){
+					self.tones.insert(ToneId{track,index});
+				}
+			}
+		}
+	}
+
+	///Selects every tone in the song accepted by the filter.
+	pub fn select_all(&mut self,song: &Song,filter: &Filter){
+		for (track,tones) in song.tracks.iter().enumerate(){
+			for (index,tone) in tones.iter().enumerate(){
+				if filter.accepts(track,tone){
+					self.tones.insert(ToneId{track,index});
+				}
+			}
+		}
+	}
+
+	///The tracks containing at least one selected tone.
+	pub fn tracks(&self) -> Vec<usize>{
+		let mut tracks: Vec<usize> = self.tones.iter().map(|id| id.track).collect();
+		tracks.dedup();
+		tracks
+	}
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Operation{
+	Quantize,
+	Transform,
+}
+
+///Tones modified by an operation that has not been committed yet.
+///The original tones are kept so that they can be shown as an overlay.
+pub struct Preview{
+	pub operation: Operation,
+	pub originals: Vec<(ToneId,Tone)>,
+}
+
+///A snapshot of some of the tracks in a song before or after an edit.
+struct Snapshot{
+	tracks  : Vec<(usize,Vec<Tone>)>,
+	duration: u32,
+	tempo   : TempoMap,
+}
+
+impl Snapshot{
+	fn take(song: &Song,tracks: &[usize]) -> Self{
+		Snapshot{
+			tracks  : tracks.iter().filter_map(|&track| song.tracks.get(track).map(|tones| (track,tones.clone()))).collect(),
+			duration: song.duration,
+			tempo   : song.tempo.clone(),
+		}
+	}
+
+	///Restores the snapshot into the song, returning a snapshot of what was replaced.
+	fn restore(self,song: &mut Song) -> Self{
+		let mut replaced = Vec::with_capacity(self.tracks.len());
+		for (track,tones) in self.tracks{
+			replaced.push((track,::core::mem::replace(&mut song.tracks[track],tones)));
+		}
+		Snapshot{
+			tracks  : replaced,
+			duration: ::core::mem::replace(&mut song.duration,self.duration),
+			tempo   : ::core::mem::replace(&mut song.tempo,self.tempo),
+		}
+	}
+}
+
+///Undo/redo history of edits.
+///Each entry stores the previous state of the tracks that the edit modified, and of the tempo map.
+#[derive(Default)]
+pub struct History{
+	undo: Vec<Snapshot>,
+	redo: Vec<Snapshot>,
+	///A snapshot taken before an edit that may turn out to change nothing, e.g. a drag.
+	pending: Option<Snapshot>,
+}
+
+impl History{
+	///Maximum number of undo steps kept.
+	pub const LIMIT: usize = 256;
+
+	///Records the state of the given tracks. Should be called before modifying them.
+	pub fn record(&mut self,song: &Song,tracks: &[usize]){
+		self.pending = Some(Snapshot::take(song,tracks));
+		self.commit();
+	}
+
+	///Takes a snapshot of the given tracks that is only recorded by `commit`, when the edit that follows changes something.
+	pub fn prepare(&mut self,song: &Song,tracks: &[usize]){
+		self.pending = Some(Snapshot::take(song,tracks));
+	}
+
+	///Records the snapshot taken by `prepare`, if it has not been already.
+	pub fn commit(&mut self){
+		if let Some(snapshot) = self.pending.take(){
+			if self.undo.len() >= Self::LIMIT{
+				self.undo.remove(0);
+			}
+			self.undo.push(snapshot);
+			self.redo.clear();
+		}
+	}
+
+	///Forgets the snapshot taken by `prepare` when the edit did not change anything.
+	pub fn discard(&mut self){
+		self.pending = None;
+	}
+
+	///Returns false when there is nothing to undo.
+	pub fn undo(&mut self,song: &mut Song) -> bool{
+		self.pending = None;
+		if let Some(snapshot) = self.undo.pop(){
+			self.redo.push(snapshot.restore(song));
+			true
+		}else{
+			false
+		}
+	}
+
+	///Returns false when there is nothing to redo.
+	pub fn redo(&mut self,song: &mut Song) -> bool{
+		self.pending = None;
+		if let Some(snapshot) = self.redo.pop(){
+			self.undo.push(snapshot.restore(song));
+			true
+		}else{
+			false
+		}
+	}
+}
+
+///State of the note editing in the GUI.
+pub struct Editor{
+	pub tool     : Tool,
+	pub selection: Selection,
+	pub history  : History,
+	pub drag     : Option<Drag>,
+	///Whether the song has been modified since it was loaded or saved.
+	pub modified : bool,
+	///Counts the modifications of the song, so that what is computed from it can be kept until it changes.
+	pub revision : u64,
+	///The channel, velocities and instrument that new tones are drawn with.
+	pub template : Tone,
+	///The track that new tones are drawn into.
+	pub draw_track: usize,
+	///Whether operations apply to every tone in the tracks of the selected tones instead of only the selected tones.
+	pub whole_tracks: bool,
+	///The tones that are shown and can be selected.
+	pub filter   : Filter,
+	pub quantize : Quantize,
+	pub transform: TransformSettings,
+	pub preview  : Option<Preview>,
+	///Whether the tones on the drum channels are shown as rows of percussion sounds below the roll.
+	pub drums    : bool,
+	///The channels that are shown as drums.
+	pub drum_channels: Vec<u8>,
+}
+
+impl Editor{
+	pub fn new() -> Self{
+		Editor{
+			tool     : Tool::Select,
+			selection: Selection::default(),
+			history  : History::default(),
+			drag     : None,
+			modified : false,
+			revision : 0,
+			template : Tone{
+				note              : note_from_u8(60),
+				start_time        : 0,
+				end_time          : 0,
+				atk_vel           : 100,
+				rel_vel           : 64,
+				channel           : 0,
+				instrument_program: 0,
+				instrument_bank   : 0,
+			},
+			draw_track: 0,
+			whole_tracks: false,
+			filter   : Filter::default(),
+			quantize : Quantize::default(),
+			transform: TransformSettings::default(),
+			preview  : None,
+			drums    : true,
+			drum_channels: vec![PERCUSSION_CHANNEL],
+		}
+	}
+
+	///Marks the song as modified.
+	pub fn changed(&mut self){
+		self.modified = true;
+		self.revision+= 1;
+	}
+
+	///The filter of the tones in the roll, which leaves out the drum channels when they are shown as drums.
+	pub fn roll_filter(&self) -> Filter{
+		if !self.drums{return self.filter.clone()}
+		Filter{
+			channels: Some((0..16).filter(|channel| self.filter.channels.as_ref().map_or(true,|channels| channels.contains(channel)) && !self.drum_channels.contains(channel)).collect()),
+			..self.filter.clone()
+		}
+	}
+
+	///The tones that operations apply to: The selected tones (or their whole tracks), or every tone shown in the roll when nothing is selected.
+	///Hits on the drum rows are left out, so that e.g. quantizing or transposing does not change them unseen.
+	pub fn scope(&self,song: &Song) -> Vec<ToneId>{
+		let filter = &self.roll_filter();
+		let tracks: Vec<usize> =
+			if self.selection.is_empty(){(0..song.tracks.len()).collect()}
+			else if self.whole_tracks{self.selection.tracks()}
+			else{return self.selection.iter().filter(|&id| song.tone(id).map_or(false,|tone| filter.accepts(id.track,tone))).collect()};
+
+		tracks
+			.into_iter()
+			.flat_map(|track| song.tracks[track].iter().enumerate().filter(move |&(_,tone)| filter.accepts(track,tone)).map(move |(index,_)| ToneId{track,index}))
+			.collect()
+	}
+
+	///Begins a preview of the operation by keeping the original tones in the scope, unless it is already being previewed.
+	///A preview of another operation is committed first.
+	fn begin_preview(&mut self,song: &mut Song,operation: Operation){
+		if self.preview.as_ref().map_or(false,|preview| preview.operation != operation){
+			self.commit_preview(song);
+		}
+		if self.preview.is_none(){
+			let ids = self.scope(song);
+			self.history.record(song,&tracks_of(&ids));
+			self.preview = Some(Preview{
+				operation,
+				originals: ids.into_iter().filter_map(|id| song.tone(id).map(|&tone| (id,tone))).collect(),
+			});
+		}
+	}
+
+	///Transforms the tones in the scope while keeping the original tones until the preview is committed or cancelled.
+	///When already previewing the same operation, it is applied to the original tones instead, so that its parameters can be adjusted during the preview.
+	pub fn preview_with<F: Fn(Tone) -> Tone>(&mut self,song: &mut Song,operation: Operation,f: F){
+		self.begin_preview(song,operation);
+		for &(id,original) in &self.preview.as_ref().unwrap().originals{
+			if let Some(tone) = song.tone_mut(id){
+				*tone = f(original);
+			}
+		}
+		song.update_duration();
+		self.changed();
+	}
+
+	pub fn preview_quantize(&mut self,song: &mut Song){
+		let quantize = self.quantize;
+		let division = song.division;
+		self.preview_with(song,Operation::Quantize,|tone| quantize.tone(tone,division));
+	}
+
+	///Returns the number of tones whose pitches were clamped to the note range.
+	pub fn preview_transform(&mut self,song: &mut Song) -> usize{
+		let transforms = self.transform.transforms();
+
+		//The context is determined by the original tones so that it stays the same while adjusting the preview.
+		self.begin_preview(song,Operation::Transform);
+		let context = Context::of(self.preview.as_ref().unwrap().originals.iter().map(|&(_,ref tone)| tone));
+		self.preview_with(song,Operation::Transform,|tone| transform::transform_tone(&transforms,tone,&context).tone);
+
+		self.preview.as_ref().unwrap().originals.iter().filter(|&&(_,tone)| transform::transform_tone(&transforms,tone,&context).clamped).count()
+	}
+
+	///Whether the current preview is of the given operation.
+	pub fn previewing(&self,operation: Operation) -> bool{
+		self.preview.as_ref().map_or(false,|preview| preview.operation == operation)
+	}
+
+	pub fn commit_preview(&mut self,song: &mut Song){
+		if let Some(preview) = self.preview.take(){
+			let ids: Vec<ToneId> = preview.originals.iter().map(|&(id,_)| id).collect();
+			sort_tracks(song,&tracks_of(&ids),&mut self.selection);
+		}
+	}
+
+	pub fn cancel_preview(&mut self,song: &mut Song){
+		if self.preview.take().is_some(){
+			self.history.undo(song);
+			self.history.redo.clear();
+			self.changed();
+		}
+	}
+
+	///Begins a drag at the given position. `extend` is whether the selection should be extended (e.g. shift is held).
+	///`edge` is the distance in ticks from the start or end of a tone where it is grabbed for resizing.
+	pub fn press(&mut self,song: &mut Song,pos: SongPos,extend: bool,edge: f64){
+		self.commit_preview(song);
+		match self.tool{
+			Tool::Select => match tone_at(song,&self.roll_filter(),pos){
+				Some(id) => {
+					if extend{
+						self.selection.toggle(id);
+						if !self.selection.contains(id){return}
+					}else if !self.selection.contains(id){
+						self.selection.select_only(id);
+					}
+
+					let tone = song.tone(id).unwrap();
+					let length = tone.end_time.saturating_sub(tone.start_time) as f64;
+					let edge = edge.min(length/3.0);
+					let grab =
+						if pos.time >= (tone.end_time as f64)-edge{Grab::End}
+						else if pos.time <= (tone.start_time as f64)+edge{Grab::Start}
+						else{Grab::Body};
+
+					self.history.prepare(song,&self.selection.tracks());
+					self.drag = Some(Drag::Tones{grab,origin: pos,applied: (0,0)});
+				},
+				None => {
+					if !extend{
+						self.selection.clear();
+					}
+					self.drag = Some(Drag::RubberBand{origin: pos,current: pos});
+				},
+			},
+			Tool::Pencil => {
+				if pos.time < 0.0 || pos.note < 0.0 || pos.note >= NOTES as f64{return}
+				if song.tracks.is_empty(){
+					song.tracks.push(Vec::new());
+				}
+				let track = cmp::min(self.draw_track,song.tracks.len()-1);
+				self.history.record(song,&[track]);
+
+				let start = pos.time as u32;
+				let tone = Tone{
+					note      : note_from_u8(pos.note as u8),
+					start_time: start,
+					end_time  : start + MIN_TONE_LENGTH,
+					..self.template
+				};
+				let id = ToneId{track,index: song.tracks[track].len()};
+				song.tracks[track].push(tone);
+				song.update_duration();
+				self.selection.select_only(id);
+				self.changed();
+				self.drag = Some(Drag::Draw{id});
+			},
+		}
+	}
+
+	///Continues a drag to the given position.
+	pub fn drag_to(&mut self,song: &mut Song,pos: SongPos){
+		let changed = match self.drag{
+			Some(Drag::Tones{grab,origin,ref mut applied}) => {
+				let delta_time = (pos.time - origin.time).round() as i64;
+				let delta_note = if grab == Grab::Body{(pos.note - origin.note).round() as i32}else{0};
+				let (delta_time,delta_note) = clamp_delta(song,&self.selection,grab,delta_time,delta_note);
+				if (delta_time,delta_note) != *applied{
+					self.history.commit();
+					for id in self.selection.iter(){
+						if let Some(tone) = song.tone_mut(id){
+							offset_tone(tone,grab,delta_time - applied.0,delta_note - applied.1);
+						}
+					}
+					*applied = (delta_time,delta_note);
+					song.update_duration();
+					true
+				}else{
+					false
+				}
+			},
+			Some(Drag::RubberBand{ref mut current,..}) => {
+				*current = pos;
+				false
+			},
+			Some(Drag::Draw{id}) => {
+				if let Some(tone) = song.tone_mut(id){
+					tone.end_time = cmp::max(tone.start_time + MIN_TONE_LENGTH , pos.time.max(0.0) as u32);
+				}
+				song.update_duration();
+				true
+			},
+			Some(Drag::Tempo{..}) | Some(Drag::View{..}) | None => false,
+		};
+		if changed{
+			self.changed();
+		}
+	}
+
+	///Ends the current drag.
+	pub fn release(&mut self,song: &mut Song){
+		match self.drag.take(){
+			Some(Drag::RubberBand{origin,current}) => {
+				self.selection.insert_rect(song,&self.roll_filter(),origin,current);
+			},
+			Some(Drag::Tones{applied: (0,0),..}) | Some(Drag::Tempo{..}) => {
+				//The snapshot is only recorded when something was changed
+				self.history.discard();
+			},
+			Some(Drag::View{..}) => (),
+			Some(_) => {
+				let tracks = self.selection.tracks();
+				sort_tracks(song,&tracks,&mut self.selection);
+			},
+			None => (),
+		}
+	}
+
+	///Begins dragging the tempo change within `distance` ticks from the time, or adds a tempo change there with the given tempo.
+	///Tempos are in microseconds per quarter note.
+	pub fn press_tempo(&mut self,song: &mut Song,time: f64,tempo: u32,distance: f64){
+		self.commit_preview(song);
+		let index = match tempo_change_at(song,time,distance){
+			//A grabbed change is left as it is until it is dragged
+			Some(index) => {
+				self.history.prepare(song,&[]);
+				index
+			},
+			None => {
+				self.history.record(song,&[]);
+				let time = time.max(0.0).round() as u32;
+				song.tempo.set_tempo(time,tempo);
+				self.changed();
+				song.tempo.tempos.iter().position(|change| change.time == time).unwrap()
+			},
+		};
+		self.drag = Some(Drag::Tempo{index});
+	}
+
+	///Continues dragging a tempo change. It is kept between its neighbours, and a change at the beginning stays there.
+	pub fn drag_tempo(&mut self,song: &mut Song,time: f64,tempo: u32){
+		if let Some(Drag::Tempo{index}) = self.drag{
+			let tempos = &mut song.tempo.tempos;
+			if index >= tempos.len(){return}
+			let mut change = tempos[index];
+			let min = if index > 0{tempos[index-1].time.saturating_add(1)}else{1};
+			let max = tempos.get(index+1).map_or(u32::max_value(),|next| next.time.saturating_sub(1));
+			//The change stays where it is when it has no room between its neighbours
+			if change.time > 0 && min <= max{
+				change.time = cmp::min(cmp::max(time.max(0.0).round() as u32 , min) , max);
+			}
+			change.tempo = tempo;
+			if change != tempos[index]{
+				self.history.commit();
+				tempos[index] = change;
+				self.changed();
+			}
+		}
+	}
+
+	///Removes the tempo change within `distance` ticks from the time.
+	pub fn remove_tempo(&mut self,song: &mut Song,time: f64,distance: f64){
+		if let Some(index) = tempo_change_at(song,time,distance){
+			self.commit_preview(song);
+			self.history.record(song,&[]);
+			song.tempo.tempos.remove(index);
+			self.changed();
+		}
+	}
+
+	///Sets the time signature from the bar containing the time.
+	pub fn set_signature(&mut self,song: &mut Song,time: f64,numerator: u8,denominator: u8){
+		self.commit_preview(song);
+		self.history.record(song,&[]);
+		let time = song.tempo.bar_start(time,song.division);
+		song.tempo.set_signature(time,numerator,denominator);
+		self.changed();
+	}
+
+	///Deletes the selected tones.
+	pub fn delete_selected(&mut self,song: &mut Song){
+		self.commit_preview(song);
+		if self.selection.is_empty(){return}
+		self.history.record(song,&self.selection.tracks());
+		for track in self.selection.tracks(){
+			let mut index = 0;
+			let selection = &self.selection;
+			song.tracks[track].retain(|_|{
+				let keep = !selection.contains(ToneId{track,index});
+				index+= 1;
+				keep
+			});
+		}
+		self.selection.clear();
+		self.changed();
+	}
+
+	pub fn undo(&mut self,song: &mut Song){
+		self.drag = None;
+		if self.preview.is_some(){
+			self.cancel_preview(song);
+			return;
+		}
+		if self.history.undo(song){
+			self.selection.clear();
+			self.changed();
+		}
+	}
+
+	pub fn redo(&mut self,song: &mut Song){
+		self.drag = None;
+		self.commit_preview(song);
+		if self.history.redo(song){
+			self.selection.clear();
+			self.changed();
+		}
+	}
+}
+
+///Finds the topmost tone accepted by the filter at the given position.
+pub fn tone_at(song: &Song,filter: &Filter,pos: SongPos) -> Option<ToneId>{
+	if pos.note < 0.0{return None}
+	let note = pos.note as u32;
+	for (track,tones) in song.tracks.iter().enumerate().rev(){
+		for (index,tone) in tones.iter().enumerate().rev(){
+			if Into::<u8>::into(tone.note) as u32 == note && (tone.start_time as f64) <= pos.time && pos.time < (tone.end_time as f64) && filter.accepts(track,tone){
+				return Some(ToneId{track,index});
+			}
+		}
+	}
+	None
+}
+
+///Index of the tempo change nearest to the time within the distance in ticks.
+pub fn tempo_change_at(song: &Song,time: f64,distance: f64) -> Option<usize>{
+	song.tempo.tempos
+		.iter()
+		.enumerate()
+		.map(|(index,&TempoChange{time: change_time,..})| (index,(change_time as f64 - time).abs()))
+		.filter(|&(_,d)| d <= distance)
+		.min_by(|a,b| a.1.partial_cmp(&b.1).unwrap())
+		.map(|(index,_)| index)
+}
+
+///Limits a drag offset so that no selected tone is moved before the beginning, out of the note range or resized to an invalid length.
+fn clamp_delta(song: &Song,selection: &Selection,grab: Grab,delta_time: i64,delta_note: i32) -> (i64,i32){
+	let (mut delta_time,mut delta_note) = (delta_time,delta_note);
+	for id in selection.iter(){
+		if let Some(tone) = song.tone(id){
+			let (start,end,note) = (tone.start_time as i64 , tone.end_time as i64 , Into::<u8>::into(tone.note) as i32);
+			match grab{
+				Grab::Body  => delta_time = cmp::max(delta_time,-start),
+				Grab::Start => delta_time = cmp::min(cmp::max(delta_time,-start) , end-start-(MIN_TONE_LENGTH as i64)),
+				Grab::End   => delta_time = cmp::max(delta_time,start-end+(MIN_TONE_LENGTH as i64)),
+			}
+			delta_note = cmp::min(cmp::max(delta_note,-note) , (NOTES as i32)-1-note);
+		}
+	}
+	(delta_time,delta_note)
+}
+
+fn offset_tone(tone: &mut Tone,grab: Grab,delta_time: i64,delta_note: i32){
+	match grab{
+		Grab::Body => {
+			tone.start_time = (tone.start_time as i64 + delta_time) as u32;
+			tone.end_time   = (tone.end_time   as i64 + delta_time) as u32;
+			tone.note       = note_from_u8((Into::<u8>::into(tone.note) as i32 + delta_note) as u8);
+		},
+		Grab::Start => tone.start_time = (tone.start_time as i64 + delta_time) as u32,
+		Grab::End   => tone.end_time   = (tone.end_time   as i64 + delta_time) as u32,
+	}
+}
+
+///The distinct tracks of the given tones, which are expected to be sorted.
+pub fn tracks_of(ids: &[ToneId]) -> Vec<usize>{
+	let mut tracks: Vec<usize> = ids.iter().map(|id| id.track).collect();
+	tracks.dedup();
+	tracks
+}
+
+///Sorts the given tracks by `tone_order`, keeping the same tones selected.
+///Required after an edit because the tone rendering relies on the tones being sorted.
+pub fn sort_tracks(song: &mut Song,tracks: &[usize],selection: &mut Selection){
+	for &track in tracks{
+		let mut tones: Vec<(Tone,bool)> = song.tracks[track]
+			.iter()
+			.enumerate()
+			.map(|(index,&tone)| (tone,selection.contains(ToneId{track,index})))
+			.collect();
+		tones.sort_by_key(|&(ref tone,_)| tone_order(tone));
+
+		selection.tones.retain(|id| id.track != track);
+		for (index,&(_,selected)) in tones.iter().enumerate(){
+			if selected{
+				selection.insert(ToneId{track,index});
+			}
+		}
+		song.tracks[track] = tones.into_iter().map(|(tone,_)| tone).collect();
+	}
+}
+
+#[cfg(test)]
+mod tests{
+	use midi_ext::*;
+	use song::{Song,ToneId};
+	use super::Editor;
+
+	fn tone(channel: u8,start_time: u32) -> Tone{
+		Tone{
+			note              : note_from_u8(60),
+			start_time,
+			end_time          : start_time + 10,
+			atk_vel           : 100,
+			rel_vel           : 64,
+			channel,
+			instrument_program: 0,
+			instrument_bank   : 0,
+		}
+	}
+
+	#[test]
+	fn drums_are_out_of_the_scope_while_shown_as_drums(){
+		let mut song = Song::empty();
+		song.tracks = vec![vec![tone(0,0) , tone(9,10) , tone(3,20) , tone(1,30)]];
+		let mut editor = Editor::new();
+		editor.drum_channels = vec![3 , 9];
+		let ids = |indices: &[usize]| indices.iter().map(|&index| ToneId{track: 0,index}).collect::<Vec<_>>();
+
+		assert_eq!(editor.scope(&song),ids(&[0 , 3]));
+		editor.selection.select_all(&song,&editor.roll_filter());
+		assert_eq!(editor.selection.iter().collect::<Vec<_>>(),ids(&[0 , 3]));
+
+		editor.selection.select_only(ToneId{track: 0,index: 3});
+		editor.selection.insert(ToneId{track: 0,index: 1});
+		assert_eq!(editor.scope(&song),ids(&[3]));
+		editor.whole_tracks = true;
+		assert_eq!(editor.scope(&song),ids(&[0 , 3]));
+
+		editor.drums = false;
+		assert_eq!(editor.scope(&song),ids(&[0 , 1 , 2 , 3]));
+		editor.whole_tracks = false;
+		assert_eq!(editor.scope(&song),ids(&[1 , 3]));
+	}
+}
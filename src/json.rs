@@ -0,0 +1,353 @@
+//Writing and parsing of JSON documents.
+
+use core::fmt;
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Value{
+	Null,
+	Bool(bool),
+	Number(f64),
+	String(String),
+	Array(Vec<Value>),
+	///Members in the order they are written.
+	Object(Vec<(String,Value)>),
+}
+
+impl Value{
+	///An object from its members.
+	pub fn object<'k,I: IntoIterator<Item = (&'k str,Value)>>(members: I) -> Self{
+		Value::Object(members.into_iter().map(|(key,value)| (key.to_string(),value)).collect())
+	}
+
+	///The member of an object by its name.
+	pub fn get(&self,key: &str) -> Option<&Value>{
+		match *self{
+			Value::Object(ref members) => members.iter().find(|&&(ref name,_)| name == key).map(|&(_,ref value)| value),
+			_ => None,
+		}
+	}
+
+	fn write(&self,f: &mut fmt::Formatter,indent: usize) -> fmt::Result{
+		//Pretty printing with the alternate flag (`{:#}`), one value per line and indented by tabs.
+		fn newline(f: &mut fmt::Formatter,indent: usize) -> fmt::Result{
+			if f.alternate(){
+				write!(f,"\n")?;
+				for _ in 0..indent{
+					write!(f,"\t")?;
+				}
+			}
+			Ok(())
+		}
+
+		match *self{
+			Value::Null             => write!(f,"null"),
+			Value::Bool(value)      => write!(f,"{}",value),
+			Value::Number(value)    => if value.is_finite(){write!(f,"{}",value)}else{write!(f,"null")},
+			Value::String(ref text) => write_string(f,text),
+			Value::Array(ref values) => {
+				if values.is_empty(){return write!(f,"[]")}
+				write!(f,"[")?;
+				for (i,value) in values.iter().enumerate(){
+					if i > 0{write!(f,",")?;}
+					newline(f,indent+1)?;
+					value.write(f,indent+1)?;
+				}
+				newline(f,indent)?;
+				write!(f,"]")
+			},
+			Value::Object(ref members) => {
+				if members.is_empty(){return write!(f,"{{}}")}
+				write!(f,"{{")?;
+				for (i,&(ref key,ref value)) in members.iter().enumerate(){
+					if i > 0{write!(f,",")?;}
+					newline(f,indent+1)?;
+					write_string(f,key)?;
+					write!(f,"{}",if f.alternate(){": "}else{":"})?;
+					value.write(f,indent+1)?;
+				}
+				newline(f,indent)?;
+				write!(f,"}}")
+			},
+		}
+	}
+}
+
+impl fmt::Display for Value{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{
+		self.write(f,0)
+	}
+}
+
+impl From<bool> for Value{fn from(value: bool) -> Self{Value::Bool(value)}}
+impl From<f64> for Value{fn from(value: f64) -> Self{Value::Number(value)}}
+impl From<u8> for Value{fn from(value: u8) -> Self{Value::Number(value as f64)}}
+impl From<u16> for Value{fn from(value: u16) -> Self{Value::Number(value as f64)}}
+impl From<u32> for Value{fn from(value: u32) -> Self{Value::Number(value as f64)}}
+impl From<usize> for Value{fn from(value: usize) -> Self{Value::Number(value as f64)}}
+impl<'s> From<&'s str> for Value{fn from(value: &'s str) -> Self{Value::String(value.to_string())}}
+impl From<String> for Value{fn from(value: String) -> Self{Value::String(value)}}
+impl<T: Into<Value>> From<Option<T>> for Value{fn from(value: Option<T>) -> Self{value.map_or(Value::Null,Into::into)}}
+impl<T: Into<Value>> From<Vec<T>> for Value{fn from(values: Vec<T>) -> Self{Value::Array(values.into_iter().map(Into::into).collect())}}
+
+fn write_string(f: &mut fmt::Formatter,text: &str) -> fmt::Result{
+	write!(f,"\"")?;
+	for c in text.chars(){
+		match c{
+			'"'  => write!(f,"\\\"")?,
+			'\\' => write!(f,"\\\\")?,
+			'\n' => write!(f,"\\n")?,
+			'\r' => write!(f,"\\r")?,
+			'\t' => write!(f,"\\t")?,
+			c if (c as u32) < 0x20 => write!(f,"\\u{:04x}",c as u32)?,
+			c => write!(f,"{}",c)?,
+		}
+	}
+	write!(f,"\"")
+}
+
+///How deeply arrays and objects can be nested in a parsed document, so that parsing cannot overflow the stack.
+pub const MAX_DEPTH: usize = 128;
+
+///Parses a JSON document. Errors tell the line and column where parsing stopped.
+pub fn parse(text: &str) -> Result<Value,String>{
+	let mut parser = Parser{chars: text.chars().collect(),position: 0,depth: 0};
+	let value = parser.value()?;
+	parser.whitespace();
+	if parser.position < parser.chars.len(){
+		return Err(parser.error("Unexpected data after the end of the document"));
+	}
+	Ok(value)
+}
+
+struct Parser{
+	chars   : Vec<char>,
+	position: usize,
+	///The number of arrays and objects around the current value.
+	depth   : usize,
+}
+
+impl Parser{
+	fn error(&self,message: &str) -> String{
+		let before = &self.chars[..self.position.min(self.chars.len())];
+		let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+		let column = before.iter().rev().take_while(|&&c| c != '\n').count() + 1;
+		format!("{} at line {}, column {}",message,line,column)
+	}
+
+	fn peek(&self) -> Option<char>{
+		self.chars.get(self.position).cloned()
+	}
+
+	fn next(&mut self) -> Option<char>{
+		let c = self.peek();
+		self.position+= 1;
+		c
+	}
+
+	fn whitespace(&mut self){
+		while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.peek(){
+			self.position+= 1;
+		}
+	}
+
+	fn expect(&mut self,c: char) -> Result<(),String>{
+		self.whitespace();
+		if self.peek() == Some(c){
+			self.position+= 1;
+			Ok(())
+		}else{
+			Err(self.error(&format!("Expected '{}'",c)))
+		}
+	}
+
+	fn literal(&mut self,word: &str,value: Value) -> Result<Value,String>{
+		if self.chars[self.position..].iter().take(word.len()).cloned().eq(word.chars()){
+			self.position+= word.len();
+			Ok(value)
+		}else{
+			Err(self.error("Unexpected character"))
+		}
+	}
+
+	fn value(&mut self) -> Result<Value,String>{
+		self.whitespace();
+		if self.depth >= MAX_DEPTH && (self.peek() == Some('[') || self.peek() == Some('{')){
+			return Err(self.error(&format!("Arrays and objects are nested deeper than {} levels",MAX_DEPTH)));
+		}
+		self.depth+= 1;
+		let value = self.unnested_value();
+		self.depth-= 1;
+		value
+	}
+
+	///Parses a value without checking how deeply it is nested.
+	fn unnested_value(&mut self) -> Result<Value,String>{
+		match self.peek(){
+			Some('{') => {
+				self.position+= 1;
+				let mut members = Vec::new();
+				self.whitespace();
+				if self.peek() == Some('}'){
+					self.position+= 1;
+					return Ok(Value::Object(members));
+				}
+				loop{
+					self.whitespace();
+					if self.peek() != Some('"'){
+						return Err(self.error("Expected a member name"));
+					}
+					let key = self.string()?;
+					self.expect(':')?;
+					members.push((key , self.value()?));
+					self.whitespace();
+					match self.next(){
+						Some(',') => (),
+						Some('}') => return Ok(Value::Object(members)),
+						_ => {self.position-= 1; return Err(self.error("Expected ',' or '}'"))},
+					}
+				}
+			},
+			Some('[') => {
+				self.position+= 1;
+				let mut values = Vec::new();
+				self.whitespace();
+				if self.peek() == Some(']'){
+					self.position+= 1;
+					return Ok(Value::Array(values));
+				}
+				loop{
+					values.push(self.value()?);
+					self.whitespace();
+					match self.next(){
+						Some(',') => (),
+						Some(']') => return Ok(Value::Array(values)),
+						_ => {self.position-= 1; return Err(self.error("Expected ',' or ']'"))},
+					}
+				}
+			},
+			Some('"') => self.string().map(Value::String),
+			Some('t') => self.literal("true",Value::Bool(true)),
+			Some('f') => self.literal("false",Value::Bool(false)),
+			Some('n') => self.literal("null",Value::Null),
+			Some(c) if c == '-' || c.is_ascii_digit() => {
+				let start = self.position;
+				while let Some(c) = self.peek(){
+					if !(c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E'){break}
+					self.position+= 1;
+				}
+				let number: String = self.chars[start..self.position].iter().collect();
+				number.parse().map(Value::Number).map_err(|_| {self.position = start; self.error("Invalid number")})
+			},
+			Some(_) => Err(self.error("Unexpected character")),
+			None    => Err(self.error("Unexpected end of the document")),
+		}
+	}
+
+	///Parses a string, beginning at its opening quote.
+	fn string(&mut self) -> Result<String,String>{
+		self.position+= 1;
+		let mut string = String::new();
+		loop{
+			match self.next(){
+				Some('"')  => return Ok(string),
+				Some('\\') => match self.next(){
+					Some('"')  => string.push('"'),
+					Some('\\') => string.push('\\'),
+					Some('/')  => string.push('/'),
+					Some('b')  => string.push('\u{8}'),
+					Some('f')  => string.push('\u{C}'),
+					Some('n')  => string.push('\n'),
+					Some('r')  => string.push('\r'),
+					Some('t')  => string.push('\t'),
+					Some('u')  => {
+						let code = self.hex4()?;
+						//A surrogate pair. A surrogate without its other half is replaced, and an escape following it is read on its own.
+						let pair = if code >= 0xD800 && code < 0xDC00 && self.peek() == Some('\\') && self.chars.get(self.position+1) == Some(&'u'){
+							let start = self.position;
+							self.position+= 2;
+							match self.hex4()?{
+								low if low >= 0xDC00 && low < 0xE000 => Some(0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)),
+								_ => {self.position = start; None},
+							}
+						}else{
+							None
+						};
+						string.push(::core::char::from_u32(pair.unwrap_or(code)).unwrap_or('\u{FFFD}'));
+					},
+					_ => {self.position-= 1; return Err(self.error("Invalid escape sequence"))},
+				},
+				Some(c) if (c as u32) < 0x20 => {self.position-= 1; return Err(self.error("Control character in string"))},
+				Some(c) => string.push(c),
+				None    => return Err(self.error("Unterminated string")),
+			}
+		}
+	}
+
+	fn hex4(&mut self) -> Result<u32,String>{
+		let digits: String = self.chars[self.position..].iter().take(4).collect();
+		match u32::from_str_radix(&digits,16){
+			Ok(code) if digits.len() == 4 => {self.position+= 4; Ok(code)},
+			_ => Err(self.error("Invalid unicode escape")),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests{
+	use super::{parse,Value,MAX_DEPTH};
+
+	#[test]
+	fn values(){
+		assert_eq!(parse(" {\"a\": [1, -2.5e1, true, false, null], \"b\": {}, \"c\": []} "),Ok(Value::object(vec![
+			("a" , Value::Array(vec![1.0.into() , (-25.0).into() , true.into() , false.into() , Value::Null])),
+			("b" , Value::Object(Vec::new())),
+			("c" , Value::Array(Vec::new())),
+		])));
+		let value = Value::object(vec![("text" , "\"quoted\"\n\ttab \u{1} é 𝄞".into()) , ("list" , Value::Array(vec![0.5.into() , Value::Null]))]);
+		assert_eq!(parse(&value.to_string()),Ok(value.clone()));
+		assert_eq!(parse(&format!("{:#}",value)),Ok(value));
+	}
+
+	#[test]
+	fn escapes_and_surrogates(){
+		assert_eq!(parse(r#""\"\\\/\b\f\n\r\t\u00e9""#),Ok(Value::String("\"\\/\u{8}\u{C}\n\r\té".to_string())));
+		assert_eq!(parse(r#""\ud834\uDD1E""#),Ok(Value::String("𝄞".to_string())));
+		//Surrogates without their other half
+		assert_eq!(parse(r#""\ud834""#),Ok(Value::String("\u{FFFD}".to_string())));
+		assert_eq!(parse(r#""\udd1e\ud834""#),Ok(Value::String("\u{FFFD}\u{FFFD}".to_string())));
+		assert_eq!(parse(r#""\ud834\u0041""#),Ok(Value::String("\u{FFFD}A".to_string())));
+		assert_eq!(parse(r#""\ud834\ud834\udd1e""#),Ok(Value::String("\u{FFFD}𝄞".to_string())));
+	}
+
+	#[test]
+	fn errors_are_located(){
+		for &(text,error) in &[
+			(""                      , "Unexpected end of the document at line 1, column 1"),
+			("[1,\n 2,\n x]"         , "Unexpected character at line 3, column 2"),
+			("[1 2]"                 , "Expected ',' or ']' at line 1, column 4"),
+			("{\"a\" 1}"             , "Expected ':' at line 1, column 6"),
+			("{\"a\": 1,}"           , "Expected a member name at line 1, column 9"),
+			("{\"a\": 1 \"b\": 2}"   , "Expected ',' or '}' at line 1, column 9"),
+			("[1.2.3]"               , "Invalid number at line 1, column 2"),
+			("[-]"                   , "Invalid number at line 1, column 2"),
+			("tru"                   , "Unexpected character at line 1, column 1"),
+			("\"a\\qb\""             , "Invalid escape sequence at line 1, column 4"),
+			("\"\\u12G4\""           , "Invalid unicode escape at line 1, column 4"),
+			("\"\\ud834\\u12\""      , "Invalid unicode escape at line 1, column 10"),
+			("\"a\nb\""              , "Control character in string at line 1, column 3"),
+			("\"abc"                 , "Unterminated string at line 1, column 5"),
+			("{} {}"                 , "Unexpected data after the end of the document at line 1, column 4"),
+		]{
+			assert_eq!(parse(text),Err(error.to_string()),"{:?}",text);
+		}
+	}
+
+	#[test]
+	fn nesting_is_limited(){
+		let nested = |depth: usize| format!("{}0{}",(0..depth).map(|i| if i % 2 == 0{"["}else{"{\"a\":"}).collect::<String>(),(0..depth).rev().map(|i| if i % 2 == 0{"]"}else{"}"}).collect::<String>());
+		assert!(parse(&nested(MAX_DEPTH)).is_ok());
+		assert_eq!(parse(&nested(MAX_DEPTH+1)),Err(format!("Arrays and objects are nested deeper than 128 levels at line 1, column {}",MAX_DEPTH/2*6+1)));
+		//Deep enough to overflow the stack without the limit
+		assert!(parse(&"[".repeat(100_000)).is_err());
+	}
+}
@@ -0,0 +1,202 @@
+//Estimation of the key of a song using the Krumhansl-Schmuckler key-finding algorithm.
+//The durations of the pitch classes are correlated with the Krumhansl-Kessler key profiles of every major and minor key.
+
+use core::fmt;
+
+use chord::PITCH_CLASS_NAMES;
+use midi_ext::*;
+use smf::{self,meta,Chunk,ChunkKind,RawEvent};
+use song::{visible_tones,Song};
+
+const MAJOR_PROFILE: [f64; 12] = [6.35,2.23,3.48,2.33,4.38,4.09,2.52,5.19,2.39,3.66,2.29,2.88];
+const MINOR_PROFILE: [f64; 12] = [6.33,2.68,3.52,5.38,2.60,3.53,2.54,4.75,3.98,2.69,3.34,3.17];
+
+static MODES: [Mode; 2] = [Mode::Major,Mode::Minor];
+
+///How much lower the correlation of the key of a key signature can be than the best one for the key signature to be used instead.
+pub const SIGNATURE_TOLERANCE: f64 = 0.1;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Mode{
+	Major,
+	Minor,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Key{
+	///Pitch class of the tonic, from 0 for C to 11 for B.
+	pub tonic: u8,
+	pub mode : Mode,
+}
+
+impl Key{
+	///Every major key followed by every minor key.
+	pub fn all() -> impl Iterator<Item = Key>{
+		MODES.iter().flat_map(|&mode| (0..12).map(move |tonic| Key{tonic,mode}))
+	}
+
+	///The pitch classes of the scale (natural minor for minor keys) as bits from bit 0 for C.
+	pub fn scale(&self) -> u16{
+		let intervals: &[u8] = match self.mode{
+			Mode::Major => &[0,2,4,5,7,9,11],
+			Mode::Minor => &[0,2,3,5,7,8,10],
+		};
+		intervals.iter().fold(0,|mask,&interval| mask | 1 << ((self.tonic + interval) % 12))
+	}
+
+	pub fn contains(&self,note: u8) -> bool{
+		self.scale() & (1 << (note % 12)) != 0
+	}
+
+	fn profile(&self) -> &'static [f64; 12]{match self.mode{
+		Mode::Major => &MAJOR_PROFILE,
+		Mode::Minor => &MINOR_PROFILE,
+	}}
+
+	///Correlation of the pitch class durations with the profile of the key.
+	pub fn correlation(&self,histogram: &[f64; 12]) -> f64{
+		let profile = self.profile();
+		let rotated: Vec<f64> = (0..12).map(|pitch_class| profile[(pitch_class + 12 - self.tonic as usize) % 12]).collect();
+		correlation(histogram,&rotated)
+	}
+}
+
+impl fmt::Display for Key{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{
+		write!(f,"{} {}",PITCH_CLASS_NAMES[self.tonic as usize % 12],match self.mode{Mode::Major => "major",Mode::Minor => "minor"})
+	}
+}
+
+///Pearson correlation coefficient.
+fn correlation(a: &[f64],b: &[f64]) -> f64{
+	let n = a.len() as f64;
+	let (mean_a,mean_b) = (a.iter().sum::<f64>() / n , b.iter().sum::<f64>() / n);
+	let (mut covariance,mut variance_a,mut variance_b) = (0.0,0.0,0.0);
+	for (x,y) in a.iter().zip(b){
+		covariance+= (x - mean_a) * (y - mean_b);
+		variance_a+= (x - mean_a) * (x - mean_a);
+		variance_b+= (y - mean_b) * (y - mean_b);
+	}
+	if variance_a == 0.0 || variance_b == 0.0{0.0}else{covariance / (variance_a * variance_b).sqrt()}
+}
+
+///A Key Signature meta event.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct KeySignature{
+	///Time in ticks.
+	pub time  : u32,
+	///Number of sharps when positive and of flats when negative.
+	pub sharps: i8,
+	pub minor : bool,
+}
+
+impl KeySignature{
+	pub fn key(&self) -> Key{
+		//Every sharp moves the major tonic up a fifth.
+		let major = ((self.sharps as i32 * 7) % 12 + 12) % 12;
+		if self.minor{
+			Key{tonic: ((major + 9) % 12) as u8,mode: Mode::Minor}
+		}else{
+			Key{tonic: major as u8,mode: Mode::Major}
+		}
+	}
+
+	///The major and the relative minor key sharing the key signature.
+	pub fn keys(&self) -> [Key; 2]{
+		let major = KeySignature{minor: false,..*self}.key();
+		[major , Key{tonic: (major.tonic + 9) % 12,mode: Mode::Minor}]
+	}
+}
+
+///The key signatures in the tracks, ordered by time.
+pub fn meta_key_signatures(data: &[u8],chunks: &[Chunk]) -> Vec<KeySignature>{
+	let mut signatures = Vec::new();
+	for chunk in chunks{
+		if let ChunkKind::Track(_) = chunk.kind{
+			for (time,event) in smf::track_events(data,chunk){
+				if let RawEvent::Meta{kind: meta::KEY_SIGNATURE,data} = event{
+					if data.len() >= 2 && (data[0] as i8) >= -7 && (data[0] as i8) <= 7{
+						signatures.push(KeySignature{time,sharps: data[0] as i8,minor: data[1] == 1});
+					}
+				}
+			}
+		}
+	}
+	signatures.sort_by_key(|signature| signature.time);
+	signatures
+}
+
+///The durations in ticks that the pitch classes sound in the range of ticks, using the tones for which `accept`, given the track index and the tone, returns true.
+///The tones on the drum channels have no pitch, so they are left out.
+pub fn histogram<F: Fn(usize,&Tone) -> bool>(song: &Song,accept: F,drums: &[u8],range: (u32,u32)) -> [f64; 12]{
+	let mut histogram = [0.0; 12];
+	for (track,tones) in song.tracks.iter().enumerate(){
+		for (_,tone) in visible_tones(tones,(range.0 as f64 , range.1 as f64),(0.0 , NOTES as f64)){
+			if drums.contains(&tone.channel) || !accept(track,tone){continue}
+			let overlap = tone.end_time.min(range.1) as f64 - tone.start_time.max(range.0) as f64;
+			if overlap > 0.0{
+				histogram[Into::<u8>::into(tone.note) as usize % 12]+= overlap;
+			}
+		}
+	}
+	histogram
+}
+
+///The key correlating best with the histogram, together with its correlation. None when there are no tones.
+///When a key signature is given, its major or minor key is preferred unless the best key correlates clearly better.
+pub fn estimate(histogram: &[f64; 12],signature: Option<KeySignature>) -> Option<(Key,f64)>{
+	if histogram.iter().all(|&duration| duration == 0.0){return None}
+
+	let best = |keys: &mut dyn Iterator<Item = Key>| keys
+		.map(|key| (key,key.correlation(histogram)))
+		.max_by(|a,b| a.1.partial_cmp(&b.1).unwrap());
+	let estimated = best(&mut Key::all())?;
+	match signature.and_then(|signature| best(&mut signature.keys().iter().cloned())){
+		Some(signed) if estimated.1 - signed.1 <= SIGNATURE_TOLERANCE => Some(signed),
+		_ => Some(estimated),
+	}
+}
+
+///The key of the whole song, reconciled with its first key signature.
+pub fn song_key(song: &Song,drums: &[u8]) -> Option<Key>{
+	estimate(&histogram(song,|_,_| true,drums,(0 , song.duration)),song.key_signatures.first().cloned()).map(|(key,_)| key)
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct KeySpan{
+	///Time in ticks.
+	pub start: u32,
+	///Time in ticks.
+	pub end  : u32,
+	pub key  : Key,
+}
+
+///Estimates the key over a sliding window for every `hop` ticks in the range, each reconciled with the key signature in effect.
+///The windows are `window` ticks long and centered on the hops. Consecutive hops in the same key are merged.
+pub fn sliding<F: Fn(usize,&Tone) -> bool>(song: &Song,accept: F,drums: &[u8],range: (u32,u32),window: u32,hop: u32) -> Vec<KeySpan>{
+	let hop = hop.max(1);
+	let mut spans: Vec<KeySpan> = Vec::new();
+	let mut start = range.0 / hop * hop;
+	while start < range.1{
+		let end = start + hop;
+		let center = start + hop/2;
+		let histogram = histogram(song,&accept,drums,(center.saturating_sub(window/2) , center + window/2));
+		let signature = song.key_signatures.iter().rev().find(|signature| signature.time <= center).cloned();
+		if let Some((key,_)) = estimate(&histogram,signature){
+			match spans.last_mut(){
+				Some(span) if span.end == start && span.key == key => span.end = end,
+				_ => spans.push(KeySpan{start,end,key}),
+			}
+		}
+		start = end;
+	}
+	spans
+}
+
+///Which scale is shown in the GUI.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum ScaleMode{
+	///The keys estimated over a sliding window.
+	Detected,
+	Chosen(Key),
+}
@@ -0,0 +1,173 @@
+//Writing of scores as LilyPond input, for engraving sheet music with `lilypond`.
+//The pitches are absolute and LilyPond decides the accidentals from them and the key.
+//Tempo changes are written at the start of the measure that they fall in.
+
+use std::io::{self,Write};
+
+use midi_ext::*;
+use notation::{self,Clef,Duration,Event,Measure,Part,Score,Spelling,STEP_NAMES};
+use song::Song;
+use tempo;
+
+const VERSION: &str = "2.18.2";
+
+///LilyPond's predefined commands for the directions of stems and ties of the first four voices on a staff.
+const VOICE_COMMANDS: [&str; 4] = ["\\voiceOne","\\voiceTwo","\\voiceThree","\\voiceFour"];
+
+///How many measures are written on a line.
+const MEASURES_PER_LINE: usize = 4;
+
+///Writes the score with a staff for every part, and a voice on the staff for every voice of the part.
+pub fn write_lilypond<W: Write>(song: &Song,score: &Score,out: &mut W) -> io::Result<()>{
+	writeln!(out,"\\version \"{}\"",VERSION)?;
+	writeln!(out)?;
+	writeln!(out,"\\score {{")?;
+	writeln!(out,"\t<<")?;
+	for (i,part) in score.parts.iter().enumerate(){
+		let (staff,voice_context,mode) = if part.percussion{("DrumStaff","DrumVoice","\\drummode ")}else{("Staff","Voice","")};
+		writeln!(out,"\t\t\\new {} \\with {{ instrumentName = {} }} {}<<",staff,string(&part.name),mode)?;
+
+		let voices = part.voices.iter().map(|voices| voices.len()).max().unwrap_or(0);
+		for voice in 0..voices{
+			write!(out,"\t\t\t\\new {} {{",voice_context)?;
+			if voices > 1{
+				write!(out," {}",VOICE_COMMANDS[voice.min(VOICE_COMMANDS.len() - 1)])?;
+			}
+			if voice == 0{
+				match part.clef{
+					Clef::Treble     => write!(out," \\clef treble")?,
+					Clef::Bass       => write!(out," \\clef bass")?,
+					Clef::Percussion => (),
+				}
+			}
+
+			let mut previous: Option<&Measure> = None;
+			for (m,(measure,events)) in score.measures.iter().zip(&part.voices).enumerate(){
+				if m % MEASURES_PER_LINE == 0{
+					write!(out,"\n\t\t\t\t")?;
+				}else{
+					write!(out," ")?;
+				}
+				//The signatures and tempos only need to be in one voice
+				if voice == 0{
+					if !part.percussion && previous.map_or(true,|previous| (previous.key.sharps,previous.key.minor) != (measure.key.sharps,measure.key.minor)){
+						let tonic = notation::tonic(measure.key);
+						write!(out,"\\key {} {} ",pitch_name(Spelling{octave: 3,..tonic}),if measure.key.minor{"\\minor"}else{"\\major"})?;
+					}
+					if previous.map_or(true,|previous| (previous.signature.numerator,previous.signature.denominator) != (measure.signature.numerator,measure.signature.denominator)){
+						write!(out,"\\time {}/{} ",measure.signature.numerator,measure.signature.denominator)?;
+					}
+					if i == 0{
+						for change in score.tempos(song,m){
+							write!(out,"\\tempo 4 = {} ",tempo::bpm(change.tempo).round())?;
+						}
+					}
+				}
+				match events.get(voice).filter(|events| !events.is_empty()){
+					Some(events) => for event in events{
+						write!(out,"{} ",event_text(part,event))?;
+					},
+					//Invisible rests keep the voice in time
+					None => write!(out,"s{}*{} ",score.grid,measure.length)?,
+				}
+				write!(out,"|")?;
+				previous = Some(measure);
+			}
+			writeln!(out,"\n\t\t\t}}")?;
+		}
+		writeln!(out,"\t\t>>")?;
+	}
+	writeln!(out,"\t>>")?;
+	writeln!(out,"\t\\layout {{ }}")?;
+	writeln!(out,"}}")
+}
+
+fn event_text(part: &Part,event: &Event) -> String{
+	let duration = duration(event.duration);
+	let tie = if event.tied_to{"~"}else{""};
+	let name = |pitch: &notation::Pitch| if part.percussion{drum_name(pitch.note).to_string()}else{pitch_name(pitch.spelling)};
+	match event.pitches.len(){
+		0 => format!("r{}",duration),
+		1 => format!("{}{}{}",name(&event.pitches[0]),duration,tie),
+		_ => format!("<{}>{}{}",event.pitches.iter().map(name).collect::<Vec<_>>().join(" "),duration,tie),
+	}
+}
+
+fn duration(duration: Duration) -> String{
+	format!("{}{}",duration.value,".".repeat(duration.dots as usize))
+}
+
+///The name of the pitch in LilyPond's Dutch note names with absolute octaves, where `c'` is middle C.
+fn pitch_name(spelling: Spelling) -> String{
+	let mut name = STEP_NAMES[spelling.step as usize].to_ascii_lowercase().to_string();
+	name.push_str(match spelling.alter{
+		-1 => "es",
+		 1 => "is",
+		 _ => "",
+	});
+	let octaves = spelling.octave as i32 - 3;
+	name.push_str(&(if octaves > 0{"'"}else{","}).repeat(octaves.abs() as usize));
+	name
+}
+
+///The name of the sound in LilyPond's drum mode.
+fn drum_name(note: u8) -> &'static str{
+	use midi_ext::GeneralMidiLvl1PercussionNote::*;
+
+	match GeneralMidiLvl1PercussionNote::from_note(note){
+		Some(AcousticBassDrum)   => "bda",
+		Some(BassDrum1)          => "bd",
+		Some(SideStickOrRimshot) => "ss",
+		Some(AcousticSnare)      => "sn",
+		Some(HandClap)           => "hc",
+		Some(ElectricSnare)      => "sne",
+		Some(LowFloorTom)        => "tomfl",
+		Some(ClosedHiHat)        => "hhc",
+		Some(HighFloorTom)       => "tomfh",
+		Some(PedalHiHat)         => "hhp",
+		Some(LowTom)             => "toml",
+		Some(OpenHiHat)          => "hho",
+		Some(LowMidTom)          => "tomml",
+		Some(HiMidTom)           => "tommh",
+		Some(CrashCymbal1)       => "cymca",
+		Some(HighTom)            => "tomh",
+		Some(RideCymbal1)        => "cymra",
+		Some(ChineseCymbal)      => "cymch",
+		Some(RideBell)           => "rb",
+		Some(Tambourine)         => "tamb",
+		Some(SplashCymbal)       => "cyms",
+		Some(Cowbell)            => "cb",
+		Some(CrashCymbal2)       => "cymcb",
+		Some(VibraSlap)          => "vibs",
+		Some(RideCymbal2)        => "cymrb",
+		Some(HighBongo)          => "boh",
+		Some(LowBongo)           => "bol",
+		Some(MuteHighConga)      => "cghm",
+		Some(OpenHighConga)      => "cgho",
+		Some(LowConga)           => "cgl",
+		Some(HighTimbale)        => "timh",
+		Some(LowTimbale)         => "timl",
+		Some(HighAgogo)          => "agh",
+		Some(LowAgogo)           => "agl",
+		Some(Cabasa)             => "cab",
+		Some(Maracas)            => "mar",
+		Some(ShortWhistle)       => "whs",
+		Some(LongWhistle)        => "whl",
+		Some(ShortGuiro)         => "guis",
+		Some(LongGuiro)          => "guil",
+		Some(Claves)             => "cl",
+		Some(HighWoodBlock)      => "wbh",
+		Some(LowWoodBlock)       => "wbl",
+		Some(MuteCuica)          => "cuim",
+		Some(OpenCuica)          => "cuio",
+		Some(MuteTriangle)       => "trim",
+		Some(OpenTriangle)       => "trio",
+		//Sounds outside of General MIDI have no name, so they are written as snares
+		None                     => "sn",
+	}
+}
+
+///A LilyPond string literal.
+fn string(text: &str) -> String{
+	format!("\"{}\"",text.replace('\\',"\\\\").replace('"',"\\\""))
+}
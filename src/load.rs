@@ -0,0 +1,454 @@
+use core::fmt;
+use std::{fs,io};
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use midi;
+use key;
+use lyrics;
+use markers;
+use midi_ext::*;
+use repair::{self,Repair};
+use smf::{self,ChunkKind,SyntaxError};
+use song::Song;
+use tempo::TempoMap;
+
+#[derive(Debug)]
+pub enum LoadError{
+	///The file could not be opened or read.
+	Io(io::Error),
+	///The file is not a valid Standard MIDI File.
+	Parse{
+		///Byte offset of the error from the beginning of the file, if known.
+		offset: Option<usize>,
+		///The chunk in which the error is, if known.
+		chunk: Option<ChunkKind>,
+		message: String,
+	},
+	///The file is valid but uses features that are not supported.
+	UnsupportedFormat(String),
+}
+
+impl fmt::Display for LoadError{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{match *self{
+		LoadError::Io(ref error) => write!(f,"Unable to read the file: {}",error),
+		LoadError::Parse{offset,chunk,ref message} => {
+			write!(f,"Unable to parse the file as MIDI")?;
+			match (offset,chunk){
+				(Some(offset),Some(chunk)) => write!(f," (at byte offset {} in {})",offset,chunk)?,
+				(Some(offset),None)        => write!(f," (at byte offset {})",offset)?,
+				(None,Some(chunk))         => write!(f," (in {})",chunk)?,
+				(None,None)                => (),
+			}
+			write!(f,": {}",message)
+		},
+		LoadError::UnsupportedFormat(ref message) => write!(f,"Unsupported MIDI file: {}",message),
+	}}
+}
+
+impl From<io::Error> for LoadError{
+	fn from(error: io::Error) -> Self{LoadError::Io(error)}
+}
+
+impl From<SyntaxError> for LoadError{
+	fn from(error: SyntaxError) -> Self{
+		LoadError::Parse{offset: Some(error.offset),chunk: Some(error.chunk),message: error.message}
+	}
+}
+
+///Something questionable in a file that was loaded anyway.
+#[derive(Clone,Debug,PartialEq)]
+pub enum LoadWarning{
+	///A note on without a matching note off. The tone is left out.
+	UnterminatedTone{track: usize,channel: u8,note: u8,time: u32},
+	///A note off without a preceding note on.
+	UnmatchedNoteOff{track: usize,channel: u8,note: u8,time: u32},
+	///A track chunk that does not end with an End of Track meta event.
+	MissingEndOfTrack{track: usize},
+	///The number of track chunks differs from the number in the header.
+	TrackCount{expected: u16,found: usize},
+	///Chunks of unknown types were skipped.
+	UnknownChunks(usize),
+	///Data after the End of Track meta event in a track chunk.
+	TrailingTrackData{track: usize,bytes: usize},
+	///Something that was fixed when loading leniently.
+	Repaired(Repair),
+	///The bookmarks file could not be read completely.
+	Bookmarks{path: String,message: String},
+}
+
+impl fmt::Display for LoadWarning{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{match *self{
+		LoadWarning::UnterminatedTone{track,channel,note,time} => write!(f,"Track {}: Note {} on channel {} at tick {} is never released",track,note_name(note_from_u8(note)),channel+1,time),
+		LoadWarning::UnmatchedNoteOff{track,channel,note,time} => write!(f,"Track {}: Note off for {} on channel {} at tick {} without a note on",track,note_name(note_from_u8(note)),channel+1,time),
+		LoadWarning::MissingEndOfTrack{track}                  => write!(f,"Track {}: Missing End of Track event",track),
+		LoadWarning::TrackCount{expected,found}                => write!(f,"The header specifies {} tracks but {} were found",expected,found),
+		LoadWarning::UnknownChunks(count)                      => write!(f,"{} chunks of unknown types were skipped",count),
+		LoadWarning::TrailingTrackData{track,bytes}            => write!(f,"Track {}: {} bytes after the End of Track event were ignored",track,bytes),
+		LoadWarning::Repaired(ref repair)                      => write!(f,"Repaired: {}",repair),
+		LoadWarning::Bookmarks{ref path,ref message}           => write!(f,"Bookmarks \"{}\": {}",path,message),
+	}}
+}
+
+///A container that the Standard MIDI File was wrapped in.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Container{
+	///Compressed with gzip (.mid.gz).
+	Gzip,
+	///A RIFF RMID file (.rmi).
+	Rmid,
+}
+
+impl fmt::Display for Container{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{match *self{
+		Container::Gzip => write!(f,"gzip"),
+		Container::Rmid => write!(f,"RIFF RMID"),
+	}}
+}
+
+pub const GZIP_ID: &[u8; 2] = &[0x1F,0x8B];
+
+///Unwraps the containers of the data, recognized by their magic bytes, until the Standard MIDI File in them.
+///Returns the containers from the outermost and the data inside the innermost one.
+pub fn unwrap_containers(data: &[u8]) -> Result<(Vec<Container>,Vec<u8>),LoadError>{
+	//The containers can be nested, e.g. a gzipped RMID file. The limit prevents endless unwrapping of malicious data.
+	const LIMIT: usize = 4;
+	//Larger than any real MIDI file, but small data can decompress to far more than fits in memory.
+	const DECOMPRESSED_LIMIT: u64 = 256 << 20;
+
+	let mut containers = Vec::new();
+	let mut data = data.to_vec();
+	while containers.len() < LIMIT{
+		if data.starts_with(GZIP_ID){
+			let mut decompressed = Vec::new();
+			GzDecoder::new(&data[..]).take(DECOMPRESSED_LIMIT + 1).read_to_end(&mut decompressed)
+				.map_err(|error| LoadError::Parse{offset: None,chunk: None,message: format!("Unable to decompress the gzip data: {}",error)})?;
+			if decompressed.len() as u64 > DECOMPRESSED_LIMIT{
+				return Err(LoadError::Parse{offset: None,chunk: None,message: format!("The gzip data decompresses to more than {} MiB",DECOMPRESSED_LIMIT >> 20)});
+			}
+			containers.push(Container::Gzip);
+			data = decompressed;
+		}else if is_rmid(&data){
+			let inner = unwrap_rmid(&data)
+				.ok_or_else(|| LoadError::Parse{offset: None,chunk: None,message: "The RIFF RMID file has no \"data\" chunk".to_string()})?
+				.to_vec();
+			containers.push(Container::Rmid);
+			data = inner;
+		}else{
+			break;
+		}
+	}
+	Ok((containers,data))
+}
+
+///Checks whether the data is a RIFF RMID container.
+pub fn is_rmid(data: &[u8]) -> bool{
+	data.get(0..4) == Some(&b"RIFF"[..]) && data.get(8..12) == Some(&b"RMID"[..])
+}
+
+///The Standard MIDI File in the "data" chunk of a RIFF RMID container.
+pub fn unwrap_rmid(data: &[u8]) -> Option<&[u8]>{
+	if !is_rmid(data){return None}
+
+	let mut offset = 12;
+	while offset + 8 <= data.len(){
+		let id = &data[offset..offset+4];
+		let length = data[offset+4] as usize | (data[offset+5] as usize) << 8 | (data[offset+6] as usize) << 16 | (data[offset+7] as usize) << 24; //RIFF is little endian
+		let start = offset + 8;
+		if id == b"data"{
+			return Some(&data[start .. start + length.min(data.len()-start)]);
+		}
+		offset = start + length + (length & 1); //RIFF chunks are padded to even lengths
+	}
+	None
+}
+
+///Reads the whole file, or the standard input when the path is `-`.
+pub fn read_file(path: &str) -> io::Result<Vec<u8>>{
+	let mut contents = Vec::new();
+	if path == "-"{
+		io::stdin().read_to_end(&mut contents)?;
+	}else{
+		fs::File::open(path)?.read_to_end(&mut contents)?;
+	}
+	Ok(contents)
+}
+
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq)]
+pub struct LoadOptions{
+	///Whether to recover as much as possible from damaged files instead of rejecting them.
+	///Everything that had to be fixed is reported in the warnings.
+	pub lenient: bool,
+}
+
+///Loads a MIDI file, or the standard input when the path is `-`, together with its bookmarks, rejecting damaged files.
+#[allow(dead_code)]
+pub fn load_song(path: &str) -> Result<Song,LoadError>{
+	load_song_with(path,LoadOptions::default())
+}
+
+///Loads a MIDI file, or the standard input when the path is `-`, together with its bookmarks.
+pub fn load_song_with(path: &str,options: LoadOptions) -> Result<Song,LoadError>{
+	let mut song = load_song_from_bytes(&read_file(path)?,options)?;
+
+	if path != "-"{
+		let bookmarks_path = markers::bookmarks_path(path);
+		match markers::read_bookmarks(&bookmarks_path){
+			Ok((bookmarks,invalid_lines)) => {
+				song.markers.extend(bookmarks);
+				markers::sort(&mut song.markers);
+				if !invalid_lines.is_empty(){
+					let lines: Vec<String> = invalid_lines.iter().map(ToString::to_string).collect();
+					song.warnings.push(LoadWarning::Bookmarks{path: bookmarks_path,message: format!("Invalid lines {} were skipped",lines.join(", "))});
+				}
+			},
+			Err(error) => song.warnings.push(LoadWarning::Bookmarks{path: bookmarks_path,message: error.to_string()}),
+		}
+	}
+
+	Ok(song)
+}
+
+pub fn load_song_from_bytes(data: &[u8],options: LoadOptions) -> Result<Song,LoadError>{
+	let mut warnings = Vec::new();
+
+	let (containers,data) = unwrap_containers(data)?;
+	let data = &data[..];
+
+	let repaired;
+	let data = if options.lenient{
+		match repair::repair(data){
+			Some((data,repairs)) => {
+				warnings.extend(repairs.into_iter().map(LoadWarning::Repaired));
+				repaired = data;
+				&repaired[..]
+			},
+			None => return Err(LoadError::Parse{offset: None,chunk: Some(ChunkKind::Header),message: "No \"MThd\" header chunk could be found".to_string()}),
+		}
+	}else{
+		data
+	};
+
+	//Check the structure first to be able to tell where an error is
+	let (header,chunks) = smf::chunks(data)?;
+	if header.format > 2{
+		return Err(LoadError::UnsupportedFormat(format!("Format {} is not one of the formats 0, 1 and 2",header.format)));
+	}
+	if header.format == 0 && header.tracks > 1{
+		return Err(LoadError::UnsupportedFormat(format!("Format 0 files have a single track but the header specifies {}",header.tracks)));
+	}
+
+	let mut track_count = 0;
+	let mut unknown_count = 0;
+	for chunk in &chunks{
+		match chunk.kind{
+			ChunkKind::Track(track) => {
+				track_count+= 1;
+				let scan = smf::scan_track(data,chunk)?;
+				match scan.end_of_track{
+					None => warnings.push(LoadWarning::MissingEndOfTrack{track}),
+					Some(end) if end < chunk.data.1 => warnings.push(LoadWarning::TrailingTrackData{track,bytes: chunk.data.1 - end}),
+					Some(_) => (),
+				}
+			},
+			ChunkKind::Unknown(_) => unknown_count+= 1,
+			ChunkKind::Header => (),
+		}
+	}
+	if track_count != header.tracks as usize{
+		warnings.push(LoadWarning::TrackCount{expected: header.tracks,found: track_count});
+	}
+	if unknown_count > 0{
+		warnings.push(LoadWarning::UnknownChunks(unknown_count));
+	}
+
+	let midi_data = midi::parser::parse_smf(data)
+		.map_err(|_| rejection(data,&header,&chunks))?
+		.1;
+
+	for (track,midi_track) in midi_data.tracks.iter().enumerate(){
+		warnings.extend(midi_track_warnings(track,midi_track));
+	}
+
+	let mut song = Song::from_midi(&midi_data);
+	song.format = header.format;
+	song.track_names = track_names(data,&chunks);
+	song.lyrics = lyrics::lyrics(data,&chunks);
+	song.markers = markers::meta_markers(data,&chunks);
+	song.tempo = TempoMap::from_meta(data,&chunks);
+	song.key_signatures = key::meta_key_signatures(data,&chunks);
+	song.other_events = other_events(data,&chunks);
+	song.containers = containers;
+	song.warnings = warnings;
+	Ok(song)
+}
+
+///Locates what the parser rejected in a file whose structure was already checked by `smf`.
+///The parser reads as many chunks after the header as the header specifies tracks, so they must be track chunks, and it does not support running status.
+fn rejection(data: &[u8],header: &smf::Header,chunks: &[smf::Chunk]) -> LoadError{
+	let error = |offset,chunk,message: &str| LoadError::Parse{offset: Some(offset),chunk: Some(chunk),message: message.to_string()};
+
+	for chunk in chunks.iter().skip(1).take(header.tracks as usize){
+		if let ChunkKind::Unknown(_) = chunk.kind{
+			return error(chunk.offset,chunk.kind,"The parser does not support chunks of unknown types between the track chunks. Try --lenient");
+		}
+		let mut events = &data[chunk.data.0..chunk.data.1];
+		while !events.is_empty(){
+			match midi::parser::parse_event(events){
+				Ok((rest,_)) => events = rest,
+				Err(_) => {
+					let offset = chunk.data.1 - events.len();
+					let running_status = smf::read_vlq(data,offset,chunk.data.1).and_then(|(_,status)| data.get(status)).map_or(false,|&status| status < 0x80);
+					return error(offset,chunk.kind,if running_status{"The parser does not support running status. Try --lenient"}else{"The parser rejected the event"});
+				},
+			}
+		}
+	}
+	if chunks.len() <= header.tracks as usize{
+		return error(10,ChunkKind::Header,"The header specifies more tracks than there are chunks");
+	}
+	LoadError::Parse{offset: None,chunk: None,message: "The parser rejected the file".to_string()}
+}
+
+///The first Sequence/Track Name meta event of every track chunk.
+fn track_names(data: &[u8],chunks: &[smf::Chunk]) -> Vec<Option<String>>{
+	chunks.iter()
+		.filter(|chunk| if let ChunkKind::Track(_) = chunk.kind{true}else{false})
+		.map(|chunk| smf::track_events(data,chunk).into_iter().filter_map(|(_,event)| match event{
+			smf::RawEvent::Meta{kind: smf::meta::TRACK_NAME,data} => Some(smf::decode_text(data)),
+			_ => None,
+		}).next())
+		.collect()
+}
+
+///The events of every track chunk that are not written from the other fields of the song when saving, by `midi_write::write_smf`.
+///Those are the notes, program changes, bank selects, the first track name, tempos, signatures, markers and cue points.
+fn other_events(data: &[u8],chunks: &[smf::Chunk]) -> Vec<Vec<(u32,Vec<u8>)>>{
+	use smf::{meta,RawEvent};
+
+	chunks.iter()
+		.filter(|chunk| if let ChunkKind::Track(_) = chunk.kind{true}else{false})
+		.map(|chunk|{
+			let mut named = false;
+			smf::track_events(data,chunk).into_iter().filter(|&(_,event)| match event{
+				RawEvent::Channel{status,data} => match status & 0xF0{
+					0x80 | 0x90 | 0xC0 => false,
+					//Bank selects
+					0xB0 => data[0] != 0 && data[0] != 32,
+					_ => true,
+				},
+				RawEvent::SystemExclusive{..} => true,
+				RawEvent::Meta{kind: meta::TRACK_NAME,..} => ::core::mem::replace(&mut named,true),
+				RawEvent::Meta{kind,..} => match kind{
+					meta::END_OF_TRACK | meta::SET_TEMPO | meta::TIME_SIGNATURE | meta::KEY_SIGNATURE | meta::MARKER | meta::CUE_POINT => false,
+					_ => true,
+				},
+			}).map(|(time,event)| (time,event.bytes())).collect()
+		})
+		.collect()
+}
+
+///Finds note ons and note offs that do not match, pairing them like `midi_track_to_tones` does.
+fn midi_track_warnings(track: usize,midi_track: &midi::Track) -> Vec<LoadWarning>{
+	use midi::{MidiEvent,MidiEventType};
+
+	let mut warnings = Vec::new();
+	let mut notes_on = vec![None; 16*NOTES];
+	let mut time = 0;
+	for &midi::Event{delta_time,ref event,..} in &midi_track.events{
+		time+= delta_time;
+		if let &midi::EventType::Midi(MidiEvent{event: midi_event_type,channel,..}) = event{
+			match note_off_by_velocity(midi_event_type){
+				MidiEventType::NoteOn(note,_) => {
+					let note_on = &mut notes_on[note_index(channel,note)];
+					if note_on.is_none(){
+						*note_on = Some(time);
+					}
+				},
+				MidiEventType::NoteOff(note,_) => if notes_on[note_index(channel,note)].take().is_none(){
+					warnings.push(LoadWarning::UnmatchedNoteOff{track,channel: channel & 0x0F,note: note.into(),time});
+				},
+				_ => (),
+			}
+		}
+	}
+
+	for (index,start) in notes_on.iter().enumerate(){
+		if let Some(time) = *start{
+			warnings.push(LoadWarning::UnterminatedTone{track,channel: (index / NOTES) as u8,note: (index % NOTES) as u8,time});
+		}
+	}
+	warnings
+}
+
+#[cfg(test)]
+mod tests{
+	use midi_write;
+	use smf::ChunkKind;
+	use super::{load_song_from_bytes,LoadError,LoadOptions,LoadWarning};
+
+	///A format 1 file with 96 ticks per quarter note and the track chunks, whose events must end with End of Track.
+	fn smf(tracks: &[&[u8]]) -> Vec<u8>{
+		let mut data = b"MThd\0\0\0\x06\0\x01".to_vec();
+		data.extend_from_slice(&[0 , tracks.len() as u8 , 0 , 96]);
+		for events in tracks{
+			data.extend_from_slice(b"MTrk");
+			data.extend_from_slice(&[0 , 0 , (events.len() >> 8) as u8 , events.len() as u8]);
+			data.extend_from_slice(events);
+		}
+		data
+	}
+
+	#[test]
+	fn parser_rejection_is_located(){
+		let data = smf(&[
+			&[0x00 , 0xFF , 0x2F , 0x00],
+			&[0x00 , 0x90 , 0x3C , 0x64 , 0x60 , 0x3C , 0x00 , 0x00 , 0xFF , 0x2F , 0x00],
+		]);
+		match load_song_from_bytes(&data,LoadOptions::default()){
+			Err(LoadError::Parse{offset: Some(offset),chunk: Some(ChunkKind::Track(1)),..}) => assert_eq!(offset,14 + 12 + 8 + 4),
+			other => panic!("{:?}",other.map(|song| song.tracks)),
+		}
+	}
+
+	#[test]
+	fn note_warnings_pair_notes_like_the_tones(){
+		let data = smf(&[&[
+			0x00 , 0x90 , 0x3C , 0x64,
+			0x00 , 0x91 , 0x3C , 0x64,
+			0x10 , 0x81 , 0x3C , 0x40,        //Ends the note on channel 2 only
+			0x10 , 0x80 , 0x3E , 0x40,        //Without a note on
+			0x00 , 0xFF , 0x2F , 0x00,
+		]]);
+		let song = load_song_from_bytes(&data,LoadOptions::default()).unwrap();
+		assert_eq!(song.tracks[0].len(),1);
+		assert_eq!(song.warnings,vec![
+			LoadWarning::UnmatchedNoteOff{track: 0,channel: 0,note: 62,time: 32},
+			LoadWarning::UnterminatedTone{track: 0,channel: 0,note: 60,time: 0},
+		]);
+	}
+
+	#[test]
+	fn note_on_without_velocity_ends_tones_and_is_saved(){
+		let data = smf(&[&[
+			0x00 , 0xB0 , 0x40 , 0x7F,        //Sustain pedal
+			0x00 , 0x90 , 0x3C , 0x64,
+			0x00 , 0x91 , 0x40 , 0x50,
+			0x60 , 0x90 , 0x3C , 0x00,
+			0x00 , 0xE0 , 0x00 , 0x50,        //Pitch bend
+			0x30 , 0x81 , 0x40 , 0x20,
+			0x00 , 0xFF , 0x2F , 0x00,
+		]]);
+		let song = load_song_from_bytes(&data,LoadOptions::default()).unwrap();
+		let notes = |song: &::song::Song| -> Vec<(u8,u8,u32,u32)>{song.tracks[0].iter().map(|tone| (tone.channel,Into::<u8>::into(tone.note),tone.start_time,tone.end_time)).collect()};
+		assert_eq!(notes(&song),vec![(0,60,0,96) , (1,64,0,144)]);
+		assert!(song.warnings.is_empty(),"{:?}",song.warnings);
+
+		let mut saved = Vec::new();
+		midi_write::write_smf(&song,&mut saved).unwrap();
+		let loaded = load_song_from_bytes(&saved,LoadOptions::default()).unwrap();
+		assert_eq!(notes(&loaded),notes(&song));
+		assert_eq!(loaded.other_events,song.other_events);
+		assert_eq!(loaded.other_events[0],vec![(0 , vec![0xB0 , 0x40 , 0x7F]) , (96 , vec![0xE0 , 0x00 , 0x50])]);
+	}
+}
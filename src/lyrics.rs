@@ -0,0 +1,119 @@
+//Extraction of lyrics from the text meta events of a file.
+
+use core::ops::Range;
+
+use smf::{self,meta,Chunk,ChunkKind,RawEvent};
+
+///A syllable, word or line of text to be sung.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Lyric{
+	///Time in ticks.
+	pub time : u32,
+	pub track: usize,
+	///The text without the formatting characters.
+	pub text : String,
+	///Whether the text begins a new line or paragraph.
+	pub break_before: LineBreak,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum LineBreak{
+	None,
+	Line,
+	Paragraph,
+}
+
+///The lyrics of a file, ordered by time.
+///These are the text events of a Karaoke (.kar) file, and the lyric events otherwise.
+pub fn lyrics(data: &[u8],chunks: &[Chunk]) -> Vec<Lyric>{
+	if is_karaoke(data,chunks){
+		karaoke_lyrics(data,chunks)
+	}else{
+		lyric_events(data,chunks)
+	}
+}
+
+///Marks a Karaoke (.kar) file. It is the text of a text event in the first track.
+pub const KARAOKE_ID: &[u8] = b"@KMIDI KARAOKE FILE";
+
+///Checks whether the file follows the Karaoke (.kar) conventions. Such a file is an ordinary Standard MIDI File, so it can only be recognized by its text events.
+pub fn is_karaoke(data: &[u8],chunks: &[Chunk]) -> bool{
+	chunks.iter()
+		.filter(|chunk| if let ChunkKind::Track(_) = chunk.kind{true}else{false})
+		.flat_map(|chunk| smf::track_events(data,chunk))
+		.any(|(_,event)| match event{
+			RawEvent::Meta{kind: meta::TEXT,data} => data.starts_with(KARAOKE_ID),
+			_ => false,
+		})
+}
+
+///The lyrics of a Karaoke (.kar) file, ordered by time.
+///
+///The lyrics are the text events that do not start with `@`, which are used for information about the song.
+///A syllable starting with `/` begins a new line and a syllable starting with `\` begins a new paragraph.
+pub fn karaoke_lyrics(data: &[u8],chunks: &[Chunk]) -> Vec<Lyric>{
+	meta_lyrics(data,chunks,meta::TEXT,|track,time,data|{
+		if data.first() == Some(&b'@'){return None}
+		let (break_before,data) = match data.first().cloned(){
+			Some(b'\\') => (LineBreak::Paragraph,&data[1..]),
+			Some(b'/')  => (LineBreak::Line,&data[1..]),
+			_           => (LineBreak::None,data),
+		};
+		Some(Lyric{time,track,text: smf::decode_text(data),break_before})
+	})
+}
+
+///The lyrics from the lyric meta events, ordered by time.
+///
+///There is no standard for line breaks in lyric events, but a carriage return or line feed at the end of a syllable is common.
+///Such a syllable ends the line, so the following one begins a new line.
+pub fn lyric_events(data: &[u8],chunks: &[Chunk]) -> Vec<Lyric>{
+	let mut line_ended = false;
+	let mut previous_track = None;
+	meta_lyrics(data,chunks,meta::LYRIC,|track,time,data|{
+		if previous_track != Some(track){
+			previous_track = Some(track);
+			line_ended = false;
+		}
+		let text = smf::decode_text(data);
+		let break_before = if line_ended{LineBreak::Line}else{LineBreak::None};
+		line_ended = text.ends_with(|c| c == '\r' || c == '\n');
+		let text = text.trim_matches(|c| c == '\r' || c == '\n').to_string();
+		Some(Lyric{time,track,text,break_before})
+	})
+}
+
+///Collects the meta events of the kind from every track using the function, ordered by time.
+fn meta_lyrics<F: FnMut(usize,u32,&[u8]) -> Option<Lyric>>(data: &[u8],chunks: &[Chunk],kind: u8,mut f: F) -> Vec<Lyric>{
+	let mut lyrics = Vec::new();
+	for chunk in chunks{
+		let track = match chunk.kind{
+			ChunkKind::Track(track) => track,
+			_ => continue,
+		};
+		for (time,event) in smf::track_events(data,chunk){
+			match event{
+				RawEvent::Meta{kind: event_kind,data} if event_kind == kind => lyrics.extend(f(track,time,data)),
+				_ => (),
+			}
+		}
+	}
+	lyrics.sort_by_key(|lyric| lyric.time); //Stable, so the order within a track is kept.
+	lyrics
+}
+
+///Index of the syllable that is sung at the time, which is the last one starting at or before it.
+pub fn current(lyrics: &[Lyric],time: f64) -> Option<usize>{
+	match lyrics.iter().position(|lyric| (lyric.time as f64) > time){
+		Some(0) => None,
+		Some(i) => Some(i-1),
+		None    => if lyrics.is_empty(){None}else{Some(lyrics.len()-1)},
+	}
+}
+
+///The range of syllables in the line containing the syllable at the index.
+pub fn line(lyrics: &[Lyric],index: usize) -> Range<usize>{
+	let start = lyrics[..=index].iter().rposition(|lyric| lyric.break_before != LineBreak::None).unwrap_or(0);
+	let end = lyrics[index+1..].iter().position(|lyric| lyric.break_before != LineBreak::None).map_or(lyrics.len(),|i| index+1+i);
+	start..end
+}
@@ -1,6 +1,7 @@
 #![feature(slice_patterns,type_alias_enum_variants)]
 
 extern crate core;
+extern crate flate2;
 extern crate font_loader;
 extern crate nom_midi as midi;
 extern crate rusttype;
@@ -9,11 +10,45 @@ extern crate rusttype;
 use conrod::widget;
 use conrod::backend::glium::glium::{self,Surface};
 
+mod abc;
+mod chord;
+mod cli;
+mod edit;
 mod filtered_scan_iter;
+mod json;
+mod key;
+mod lilypond;
+mod load;
+mod lyrics;
+mod markers;
 mod midi_ext;
+mod midi_write;
+mod musicxml;
+mod notation;
+mod note_list;
 mod pair_iter;
+mod png;
+mod playback;
+mod quantize;
+mod raster;
+mod render;
+mod repair;
+mod smf;
+mod song;
+mod stats;
+mod svg;
+mod synth;
+mod tempo;
+mod transform;
+mod video;
 
+use cli::CliError;
+use edit::*;
+use markers::{Marker,MarkerKind};
 use midi_ext::*;
+use playback::Playback;
+use song::*;
+use tempo::BPM_RANGE;
 
 //TODO: Drag with mouse middle click to move view.
 //TODO: Drag with CTRL+ left click to move view.
@@ -21,7 +56,6 @@ use midi_ext::*;
 //TODO: Draw note names to the left.
 //TODO: Have border/box radius/margin to avoid mistaking multiple adjecent notes for a single one.
 //TODO: Keyboard controls.
-//TODO: Command line flags (e.g. for specifying font or reading from stdin).
 //TODO: Playback with soundfont or a single sound file.
 //TODO: Draw times on the bottom/top.
 //TODO: Draw vertical lines separating beats, bars and stuff like that.
@@ -95,79 +129,479 @@ widget_ids!(struct Ids{
 	tones_wrapper_canvas,
 	tones_canvas,
 	tones_grid,
+	tones_rubber_band,
+	tones_playhead,
+	ruler_canvas,
+	ruler_jump_list,
+	ruler_bookmark_button,
+	ruler_chords_toggle,
+	ruler_chords_list,
+	ruler_scale_list,
+	ruler_svg_button,
+	ruler_staff_toggle,
+	ruler_drums_toggle,
+	chords_lane_canvas,
+	staff_canvas,
+	drums_canvas,
+	tempo_lane_canvas,
+	tempo_curve,
+	overview_canvas,
+	overview_curve,
+	overview_limit_line,
+	overview_limit_dialer,
+	minimap_canvas,
+	minimap_viewport,
 	tones_scrollbar_x,
 	tones_scrollbar_y,
 	settings_canvas,
 	settings_barwidth_slider,
 	settings_barheight_slider,
+	settings_signature_numerator_dialer,
+	settings_signature_denominator_list,
+	settings_tool_select_toggle,
+	settings_tool_pencil_toggle,
+	settings_whole_tracks_toggle,
+	settings_play_button,
+	settings_quantize_grid_list,
+	settings_quantize_kind_list,
+	settings_quantize_target_list,
+	settings_quantize_strength_slider,
+	settings_quantize_swing_slider,
+	settings_quantize_button,
+	settings_preview_apply_button,
+	settings_preview_cancel_button,
+	settings_transpose_dialer,
+	settings_stretch_dialer,
+	settings_velocity_scale_dialer,
+	settings_velocity_compress_dialer,
+	settings_velocity_random_dialer,
+	settings_reverse_toggle,
+	settings_invert_toggle,
+	settings_transform_button,
+	status_text,
+	lyrics_lane_canvas,
+	karaoke_canvas,
+	karaoke_sung_text,
+	karaoke_current_text,
+	karaoke_rest_text,
+	karaoke_next_text,
+	dialog_canvas,
+	dialog_text,
+	dialog_button,
 	setting1_wrapper_canvas,
 	setting2_wrapper_canvas,
+	setting3_wrapper_canvas,
+	setting4_wrapper_canvas,
+	setting5_wrapper_canvas,
 });
 
-struct Song{
-	tracks: Vec<Vec<Tone>>,
-	duration: u32,
+///Identifiers of the widgets that there is a varying number of, e.g. one for each tone.
+///They are generated when more are needed.
+#[derive(Default)]
+struct WidgetLists{
+	tones     : Vec<[widget::Id; 2]>,
+	previews  : Vec<widget::Id>,
+	lyrics    : Vec<widget::Id>,
+	chords    : Vec<widget::Id>,
+	markers   : Vec<[widget::Id; 2]>,
+	beats     : Vec<widget::Id>,
+	tempos    : Vec<widget::Id>,
+	signatures: Vec<widget::Id>,
+	scale_rows: Vec<widget::Id>,
+	density   : Vec<widget::Id>,
+	overloads : Vec<widget::Id>,
+	minimap   : Vec<widget::Id>,
+	staff_lines: Vec<widget::Id>,
+	staff_heads: Vec<widget::Id>,
+	staff_texts: Vec<widget::Id>,
+	staff_rests: Vec<widget::Id>,
+	drum_lines: Vec<widget::Id>,
+	drum_names: Vec<widget::Id>,
+	drum_hits : Vec<widget::Id>,
+}
+
+impl WidgetLists{
+	///The identifier at the index, generating more when there are not enough.
+	fn get(ids: &mut Vec<widget::Id>,index: usize,generator: &mut widget::id::Generator) -> widget::Id{
+		while ids.len() <= index{
+			ids.push(generator.next());
+		}
+		ids[index]
+	}
+}
+
+///What the views compute from the song, kept until the song or what else it was computed from changes.
+///Each is stored with what it was computed from: the revision of the song, the filter and the part of the song it is about.
+#[derive(Default)]
+struct Caches{
+	keys   : Option<((u64,Filter,(u32,u32)),Vec<key::KeySpan>)>,
+	minimap: Option<((u64,Filter,usize),Vec<Option<stats::Slice>>)>,
+	staff  : Option<((u64,Filter,(u32,u32)),notation::Score)>,
+	chords : Option<((u64,Filter,Vec<usize>,chord::Segmentation),Vec<chord::ChordSpan>)>,
+}
+
+///The polyphony that General MIDI Level 1 requires of synthesizers.
+const VOICE_LIMIT: u32 = 24;
+
+///What the GUI shows besides the tones, which is not part of the editing.
+struct ViewSettings{
+	///How chords are recognized for the chord symbols, which are only shown when this is set.
+	chords     : Option<chord::Segmentation>,
+	///Which scale the note rows are shaded with, which is only done when this is set.
+	scale      : Option<key::ScaleMode>,
+	///Whether the tones are also shown as sheet music on a grand staff above the roll.
+	staff      : bool,
+	///The number of notes that can sound at once before a synthesizer drops some, which is marked in the overview strip.
+	voice_limit: u32,
+	///A message about the last operation, e.g. a warning.
+	status     : Option<String>,
+}
+
+impl Default for ViewSettings{
+	fn default() -> Self{
+		ViewSettings{
+			chords     : None,
+			scale      : None,
+			staff      : false,
+			voice_limit: VOICE_LIMIT,
+			status     : None,
+		}
+	}
+}
+
+impl Caches{
+	///The cached value when it was computed from the same inputs, computing it anew otherwise.
+	fn get<K: PartialEq,V,F: FnOnce() -> V>(cache: &mut Option<(K,V)>,inputs: K,compute: F) -> &V{
+		if cache.as_ref().map_or(true,|&(ref cached,_)| *cached != inputs){
+			*cache = Some((inputs,compute()));
+		}
+		&cache.as_ref().unwrap().1
+	}
 }
 
 fn main(){
-	use std::{env,fs};
-	use std::io::Read;
-
-	//Constants
-	const INITIAL_WIDTH: u32 = 800;
-	const INITIAL_HEIGHT: u32 = 600;
-
-	//MIDI file import
-	let mut midi_file_contents = Vec::new();
-	let midi_data = {
-		let mut midi_file = fs::File::open(env::args().nth(1).expect("MIDI file path is unspecified. Expected a 1st command line argument.")).expect("Unable to open file specified from 1st command line argument.");
-		midi_file.read_to_end(&mut midi_file_contents).expect("Unable to read file specified from 1st command line argument.");
-		midi::parser::parse_smf(midi_file_contents.as_slice()).expect("Unable to parse file specified from 1st command line argument as MIDI file.").1
+	use std::{env,process};
+
+	if let Err(error) = cli::parse(env::args().skip(1)).and_then(|(command,options)| run(command,&options)){
+		eprintln!("Error: {}",error);
+		process::exit(error.exit_code());
+	}
+}
+
+fn run(command: cli::Command,options: &cli::Options) -> Result<(),CliError>{
+	use cli::Command;
+
+	match command{
+		Command::Help    => {print!("{}",cli::USAGE); Ok(())},
+		//Errors are shown in the GUI instead when viewing
+		Command::View    => match load::load_song_with(&options.input,options.load){
+			Ok(song)   => view(song,None,options),
+			Err(error) => {
+				eprintln!("Error: {}",error);
+				view(Song::empty(),Some(error.to_string()),options)
+			},
+		},
+		Command::Info    => info(&filtered(load(options)?,&options.filter),options),
+		Command::Convert => convert(filtered(load(options)?,&options.filter),options),
+		Command::Export  => export(&filtered(load(options)?,&options.filter),options),
+		Command::Render  => render(&filtered(load(options)?,&options.filter),options),
+		Command::Import  => convert(filtered(import(options)?,&options.filter),options),
+	}
+}
+
+///Creates the output file, or uses the standard output when the path is `-`.
+fn create_output(path: &str) -> Result<Box<dyn std::io::Write>,CliError>{
+	use std::{fs,io};
+
+	if path == "-"{
+		Ok(Box::new(io::stdout()))
+	}else{
+		fs::File::create(path)
+			.map(|file| Box::new(io::BufWriter::new(file)) as Box<dyn io::Write>)
+			.map_err(|e| CliError::Io(path.to_string(),e))
+	}
+}
+
+fn load(options: &cli::Options) -> Result<Song,CliError>{
+	let song = load::load_song_with(&options.input,options.load).map_err(CliError::Load)?;
+	for warning in &song.warnings{
+		eprintln!("Warning: {}",warning);
+	}
+	Ok(song)
+}
+
+///Reads a note list in the format from `--format` or the extension of the input file.
+fn import(options: &cli::Options) -> Result<Song,CliError>{
+	let format = match options.format{
+		Some(ref format) => format.clone(),
+		None => std::path::Path::new(&options.input).extension()
+			.and_then(|extension| extension.to_str())
+			.map(str::to_lowercase)
+			.ok_or_else(|| CliError::Usage("Import format is unspecified. Expected --format or an input file ending in .csv or .json".to_string()))?,
+	};
+	let data = load::read_file(&options.input).map_err(|e| CliError::Io(options.input.clone(),e))?;
+	let text = String::from_utf8(data).map_err(|_| CliError::Import(note_list::ImportError(vec!["The note list is not UTF-8 text".to_string()])))?;
+
+	let (song,warnings) = match format.as_str(){
+		"csv"  => note_list::read_csv(&text),
+		"json" => note_list::read_json(&text),
+		format => return Err(CliError::Unsupported(format!("Unknown import format \"{}\". Expected `csv` or `json`",format))),
+	}.map_err(CliError::Import)?;
+	for warning in &warnings{
+		eprintln!("Warning: {}",warning);
+	}
+	Ok(song)
+}
+
+fn filtered(mut song: Song,filter: &Filter) -> Song{
+	if !filter.is_empty(){
+		song.retain_tones(|track,tone| filter.accepts(track,tone));
+		for (track,events) in song.other_events.iter_mut().enumerate(){
+			//Channel messages have their channel in the low bits of the status byte
+			events.retain(|&(_,ref bytes)| filter.accepts_event(track,if bytes[0] < 0xF0{Some(bytes[0] & 0x0F)}else{None}));
+		}
+	}
+	song
+}
+
+fn info(song: &Song,options: &cli::Options) -> Result<(),CliError>{
+	use std::io::Write;
+
+	let statistics = stats::Statistics::of(song,&options.drums);
+	let report = match options.format.as_ref().map(String::as_str){
+		None | Some("text") => statistics.to_string(),
+		Some("json")        => format!("{:#}\n",statistics.json()),
+		Some(format)        => return Err(CliError::Unsupported(format!("Unknown info format \"{}\". Expected `text` or `json`",format))),
+	};
+	let output_path = options.output.as_ref().map_or("-",String::as_str);
+	let mut output = create_output(output_path)?;
+	output.write_all(report.as_bytes()).and_then(|()| output.flush()).map_err(|e| CliError::Io(output_path.to_string(),e))
+}
+
+fn export(song: &Song,options: &cli::Options) -> Result<(),CliError>{
+	use std::io::Write;
+
+	let output_path = options.output.as_ref().map_or("-",String::as_str);
+	let result = match options.format.as_ref().map(String::as_str){
+		Some("abc") => {
+			let score = notation::Score::of(song,&options.drums,notation::DEFAULT_GRID);
+			let mut output = create_output(output_path)?;
+			abc::write_abc(song,&score,&mut output).and_then(|()| output.flush())
+		},
+		Some("chords") => {
+			let spans = chord::detect(song,|_,_| true,&options.drums,options.chords,(0 , song.duration));
+			let mut output = create_output(output_path)?;
+			output.write_all(chord::chart(song,&spans).as_bytes()).and_then(|()| output.flush())
+		},
+		Some("csv") => {
+			let mut output = create_output(output_path)?;
+			note_list::write_csv(song,&mut output).and_then(|()| output.flush())
+		},
+		Some("json") => {
+			let mut output = create_output(output_path)?;
+			writeln!(output,"{:#}",note_list::json(song)).and_then(|()| output.flush())
+		},
+		Some("lilypond") => {
+			let score = notation::Score::of(song,&options.drums,notation::DEFAULT_GRID);
+			let mut output = create_output(output_path)?;
+			lilypond::write_lilypond(song,&score,&mut output).and_then(|()| output.flush())
+		},
+		Some("musicxml") => {
+			let score = notation::Score::of(song,&options.drums,notation::DEFAULT_GRID);
+			let mut output = create_output(output_path)?;
+			musicxml::write_musicxml(song,&score,&mut output).and_then(|()| output.flush())
+		},
+		Some("svg") => {
+			let view = render::View::of_song(song,export_range(song,options)?,[options.zoom , options.note_height]);
+			let mut output = create_output(output_path)?;
+			svg::write_svg(&render::draw(song,&Filter::default(),&view,None),&mut output).and_then(|()| output.flush())
+		},
+		Some(format) => return Err(CliError::Unsupported(format!("Unknown export format \"{}\"",format))),
+		None => return Err(CliError::Usage("Export format is unspecified. Expected --format".to_string())),
 	};
-	let song = Song{
-		tracks  : midi_data.tracks.iter().map(midi_track_to_tones).collect(),
-		duration: midi_duration(&midi_data),
+	result.map_err(|e| CliError::Io(output_path.to_string(),e))
+}
+
+///The range of ticks to export images of, from `--start` to `--end` or the end of the song.
+fn export_range(song: &Song,options: &cli::Options) -> Result<(f64,f64),CliError>{
+	let end = options.end.unwrap_or(song.duration);
+	if end <= options.start{
+		return Err(CliError::Usage(format!("The end {} is not after the start {}",end,options.start)));
+	}
+	Ok((options.start as f64 , end as f64))
+}
+
+fn convert(mut song: Song,options: &cli::Options) -> Result<(),CliError>{
+	use std::io::Write;
+
+	let output_path = options.output.as_ref().ok_or_else(|| CliError::Usage("Output file path is unspecified. Expected --output".to_string()))?;
+
+	let clamped = transform::transform_song(&options.transforms,&mut song);
+	if clamped > 0{
+		eprintln!("Warning: The pitches of {} tones were clamped to the note range.",clamped);
+	}
+
+	let mut output = create_output(output_path)?;
+	midi_write::write_smf(&song,&mut output)
+		.and_then(|()| output.flush())
+		.map_err(|e| CliError::Io(output_path.clone(),e))
+}
+
+///The file that the GUI saves the edited song to: The output file, or a new file next to the input so that the input, which may contain more than is saved, is not overwritten.
+fn edited_path(options: &cli::Options) -> Option<String>{
+	if let Some(output) = options.output.as_ref().filter(|output| *output != "-"){
+		return Some(output.clone());
+	}
+	if options.input == "-"{return None}
+	let mut name = options.input.as_str();
+	//In this order, `.mid.gz` loses both extensions
+	for extension in &[".gz",".mid",".midi",".kar",".rmi"]{
+		if name.len() > extension.len() && name.get(name.len() - extension.len()..).map_or(false,|end| end.eq_ignore_ascii_case(extension)){
+			name = &name[..name.len() - extension.len()];
+		}
+	}
+	Some(format!("{}-edited.mid",name))
+}
+
+///Adds a bookmark at the time, or removes the bookmarks there, and saves the bookmarks next to the MIDI file.
+///The warning about the tones whose pitches a transformation clamped.
+fn clamped_status(clamped: usize) -> Option<String>{
+	if clamped > 0{
+		Some(format!("Warning: The pitches of {} tones were clamped to the note range",clamped))
+	}else{
+		None
+	}
+}
+
+fn toggle_bookmark(song: &mut Song,time: u32,save_path: Option<&str>,status: &mut Option<String>){
+	let count = song.markers.len();
+	song.markers.retain(|marker| !(marker.kind == MarkerKind::Bookmark && marker.time == time));
+	if song.markers.len() == count{
+		let number = song.markers.iter().filter(|marker| marker.kind == MarkerKind::Bookmark).count() + 1;
+		song.markers.push(Marker{time,kind: MarkerKind::Bookmark,text: format!("Bookmark {}",number)});
+		markers::sort(&mut song.markers);
+	}
+
+	*status = Some(match save_path{
+		Some(path) => {
+			let path = markers::bookmarks_path(path);
+			match markers::write_bookmarks(&path,&song.markers){
+				Ok(()) => format!("Saved the bookmarks to \"{}\"",path),
+				Err(e) => format!("Unable to save the bookmarks to \"{}\": {}",path,e),
+			}
+		},
+		None => "Unable to save the bookmarks: The file was read from standard input".to_string(),
+	});
+}
+
+///Loads the font of `--font`, or a system font. Problems other than reading the file are reported using `error`.
+fn load_font(options: &cli::Options,error: fn(String) -> CliError) -> Result<rusttype::Font<'static>,CliError>{
+	use std::fs;
+
+	let font_data = match options.font{
+		Some(ref path) => fs::read(path).map_err(|e| CliError::Io(path.clone(),e))?,
+		None => font_loader::system_fonts::get(&font_loader::system_fonts::FontPropertyBuilder::new().family("DejaVu Sans").build())
+			.or_else(|| font_loader::system_fonts::get(&font_loader::system_fonts::FontPropertyBuilder::new().family("Tahoma").build()))
+			.or_else(|| font_loader::system_fonts::get(&font_loader::system_fonts::FontPropertyBuilder::new().family("sans-serif").build()))
+			.ok_or_else(|| error("Unable to find a system font. Specify one using --font".to_string()))?
+			.0,
 	};
+	rusttype::FontCollection::from_bytes(font_data)
+		.and_then(|fonts| fonts.font_at(0))
+		.map_err(|e| error(format!("Unable to load font: {}",e)))
+}
+
+///Renders the notes from `--start` to `--end` into a PNG image of `--size`, or a video with frames of that size, without a window.
+fn render(song: &Song,options: &cli::Options) -> Result<(),CliError>{
+	use std::io::{self,Write};
+
+	fn io_error(path: &str) -> impl FnOnce(io::Error) -> CliError{
+		let path = path.to_string();
+		move |e| CliError::Io(path,e)
+	}
+
+	let range = export_range(song,options)?;
+	let font = load_font(options,CliError::Unsupported)?;
+	let output_path = options.output.as_ref().ok_or_else(|| CliError::Usage("Output file path is unspecified. Expected --output".to_string()))?;
+	let settings = video::VideoSettings{size: options.size,fps: options.fps,zoom: options.zoom,range};
+
+	match options.format.as_ref().map(String::as_str){
+		None | Some("png") => {
+			let view = render::View::fit(song,range,options.size);
+			let image = raster::rasterize(&render::draw(song,&Filter::default(),&view,None),&font,options.size);
+			let mut output = create_output(output_path)?;
+			png::write_png(&image,&mut output).and_then(|()| output.flush()).map_err(io_error(output_path))?;
+		},
+		Some("frames") => for (frame,&time) in settings.frame_times(song).iter().enumerate(){
+			let path = format!("{}{:05}.png",output_path,frame);
+			let mut output = create_output(&path)?;
+			png::write_png(&settings.frame(song,&font,time),&mut output).and_then(|()| output.flush()).map_err(io_error(&path))?;
+		},
+		Some("y4m") => {
+			let mut output = create_output(output_path)?;
+			video::write_y4m_header(&settings,&mut output).map_err(io_error(output_path))?;
+			for &time in &settings.frame_times(song){
+				video::write_y4m_frame(&settings.frame(song,&font,time),&mut output).map_err(io_error(output_path))?;
+			}
+			output.flush().map_err(io_error(output_path))?;
+		},
+		Some(format) => return Err(CliError::Unsupported(format!("Unknown render format \"{}\"",format))),
+	}
+
+	if let Some(ref audio_path) = options.audio{
+		let mut output = create_output(audio_path)?;
+		synth::write_wav(&synth::synthesize(song,&options.drums,range),&mut output).and_then(|()| output.flush()).map_err(io_error(audio_path))?;
+	}
+	Ok(())
+}
+
+///Opens the GUI, optionally showing a message in a dialog.
+fn view(mut song: Song,mut dialog: Option<String>,options: &cli::Options) -> Result<(),CliError>{
+	use std::fs;
+
+	let mut editor = Editor::new();
+	let mut view = ViewSettings::default();
+	if !song.warnings.is_empty(){
+		view.status = Some(format!("{} warnings when loading the file. Use the info command for details",song.warnings.len()));
+	}
+	editor.filter = options.filter.clone();
+	editor.drum_channels = options.drums.clone();
+	//Standard input has no path for the bookmarks or exported views.
+	let save_path = if options.input == "-"{None}else{Some(options.input.clone())};
+	let edited_path = edited_path(options);
 
 	//Build window
 	let mut events_loop = glium::glutin::EventsLoop::new();
 	let window = glium::glutin::WindowBuilder::new()
 		.with_title("MIDI Notes Display")
-		.with_dimensions(glium::glutin::dpi::LogicalSize::new(INITIAL_WIDTH as f64,INITIAL_HEIGHT as f64));
+		.with_dimensions(glium::glutin::dpi::LogicalSize::new(options.size[0] as f64,options.size[1] as f64));
 	let context = glium::glutin::ContextBuilder::new()
 		.with_vsync(true);
-	let display = glium::Display::new(window,context,&events_loop).expect("Unable to open/create display/window");
+	let display = glium::Display::new(window,context,&events_loop).map_err(|e| CliError::Gui(format!("Unable to open/create display/window: {}",e)))?;
 
 	//Construct UI object
-	let mut ui = conrod::UiBuilder::new([INITIAL_WIDTH as f64,INITIAL_HEIGHT as f64]).build();
+	let mut ui = conrod::UiBuilder::new([options.size[0] as f64,options.size[1] as f64]).build();
 
 	//Generate unique widget identifiers
 	let ids = Ids::new(ui.widget_id_generator());
 
 	//Add a font to the UI's `font::Map`
-	ui.fonts.insert(
-		rusttype::FontCollection::from_bytes(
-			font_loader::system_fonts::get(&font_loader::system_fonts::FontPropertyBuilder::new().family("DejaVu Sans").build())
-			.or_else(|| font_loader::system_fonts::get(&font_loader::system_fonts::FontPropertyBuilder::new().family("Tahoma").build()))
-			.or_else(|| font_loader::system_fonts::get(&font_loader::system_fonts::FontPropertyBuilder::new().family("sans-serif").build()))
-			.unwrap().0
-		)
-			.unwrap()
-			.font_at(0)
-			.unwrap()
-	);
+	ui.fonts.insert(load_font(options,CliError::Gui)?);
 
 	//Used for converting `conrod::render::Primitives` into `Command`s that can be used for drawing to the glium `Surface`
-	let mut renderer = conrod::backend::glium::Renderer::new(&display).unwrap();
+	let mut renderer = conrod::backend::glium::Renderer::new(&display).map_err(|e| CliError::Gui(format!("Unable to create renderer: {:?}",e)))?;
 
 	//Image map describing every widget->image mapping.
 	//There are none here.
 	let image_map = conrod::image::Map::<glium::texture::Texture2d>::new();
 
 	//Poll events from the window.
-	let mut tone_widget_ids = Vec::new();
-	let mut tone_widget_size = [1.0,16.0];
+	let mut widget_lists = WidgetLists::default();
+	let mut caches = Caches::default();
+	let mut playback = Playback::new(options.start as f64);
+	let mut tone_widget_size = [options.zoom,options.note_height];
 	let mut event_loop = EventLoop::new();
+	let mut first_frame = true;
 	'main: loop{
 		//Handle all events
 		for event in event_loop.next(&mut events_loop){
@@ -180,22 +614,89 @@ fn main(){
 			match event{
 				glium::glutin::Event::WindowEvent{event,..} => match event{
 					//Closing application
-					glium::glutin::WindowEvent::CloseRequested |
+					glium::glutin::WindowEvent::CloseRequested => break 'main,
+
+					//Editing shortcuts
 					glium::glutin::WindowEvent::KeyboardInput {
 						input: glium::glutin::KeyboardInput {
-							virtual_keycode: Some(glium::glutin::VirtualKeyCode::Escape),
+							state: glium::glutin::ElementState::Pressed,
+							virtual_keycode: Some(key),
+							modifiers,
 							..
 						},
 						..
-					} => break 'main,
+					} => {
+						use glium::glutin::VirtualKeyCode;
+						match key{
+							//Escape closes the dialog, cancels a previewed transformation or closes the application
+							VirtualKeyCode::Escape => if dialog.is_some(){
+								dialog = None;
+							}else if editor.preview.is_some(){
+								editor.cancel_preview(&mut song);
+								view.status = None;
+							}else{
+								break 'main;
+							},
+							VirtualKeyCode::Return                 => editor.commit_preview(&mut song),
+							VirtualKeyCode::Q                      => editor.preview_quantize(&mut song),
+							VirtualKeyCode::Delete |
+							VirtualKeyCode::Back                   => editor.delete_selected(&mut song),
+							VirtualKeyCode::Z if modifiers.ctrl && modifiers.shift => editor.redo(&mut song),
+							VirtualKeyCode::Z if modifiers.ctrl    => editor.undo(&mut song),
+							VirtualKeyCode::Y if modifiers.ctrl    => editor.redo(&mut song),
+							VirtualKeyCode::A if modifiers.ctrl    => editor.selection.select_all(&song,&editor.roll_filter()),
+							VirtualKeyCode::S if modifiers.ctrl    => match edited_path{
+								Some(ref path) => match fs::File::create(path).and_then(|mut file| midi_write::write_smf(&song,&mut file)){
+									Ok(()) => {
+										editor.modified = false;
+										view.status = Some(if song.containers.is_empty(){
+											format!("Saved to \"{}\"",path)
+										}else{
+											format!("Saved to \"{}\" as a Standard MIDI File without the {} container",path,song.containers.iter().map(ToString::to_string).collect::<Vec<_>>().join(" and "))
+										});
+									},
+									Err(e) => view.status = Some(format!("Unable to save MIDI file to \"{}\": {}",path,e)),
+								},
+								None => view.status = Some("Unable to save: The file was read from standard input. Specify a file to save to with --output".to_string()),
+							},
+							VirtualKeyCode::S                      => editor.tool = Tool::Select,
+							VirtualKeyCode::P                      => editor.tool = Tool::Pencil,
+							VirtualKeyCode::Space                  => playback.toggle(&song),
+							VirtualKeyCode::Home                   => playback.seek(0.0),
+							VirtualKeyCode::PageDown               => if let Some(marker) = markers::next(&song.markers,playback.position(&song)){
+								playback.seek(marker.time as f64);
+							},
+							VirtualKeyCode::PageUp                 => if let Some(marker) = markers::previous(&song.markers,playback.position(&song)){
+								playback.seek(marker.time as f64);
+							},
+							VirtualKeyCode::B                      => {
+								let time = playback.position(&song).round() as u32;
+								toggle_bookmark(&mut song,time,save_path.as_ref().map(String::as_str),&mut view.status);
+							},
+							_ => (),
+						}
+						event_loop.needs_update();
+					},
 					_ => (),
 				},
 				_ => (),
 			}
 		}
 
+		//Keep redrawing while playing to move the playhead
+		if playback.update(&song){
+			event_loop.needs_update();
+		}
+
 		//Initiate widgets
-		set_ui(&mut ui.set_widgets(),&ids,&song,&mut tone_widget_ids,&mut tone_widget_size);
+		set_ui(&mut ui.set_widgets(),&ids,&mut song,&mut editor,&mut view,&mut playback,save_path.as_ref().map(String::as_str),&mut dialog,&mut widget_lists,&mut caches,&mut tone_widget_size);
+
+		//Scroll to the start time when the tones canvas has been laid out
+		if first_frame{
+			first_frame = false;
+			ui.scroll_widget(ids.tones_wrapper_canvas,[-(options.start as f64) * tone_widget_size[0] , 0.0]);
+			event_loop.needs_update();
+		}
 
 		//Render GUI when something has changed
 		if let Some(primitives) = ui.draw_if_changed(){
@@ -206,37 +707,113 @@ fn main(){
 			target.finish().unwrap();
 		}
 	}
+
+	Ok(())
 }
 
 //Set the widgets
-fn set_ui(ui: &mut conrod::UiCell,ids: &Ids,song: &Song,tone_widget_ids: &mut Vec<[widget::Id; 2]>,tone_widget_size: &mut [f64; 2]){
+fn set_ui(ui: &mut conrod::UiCell,ids: &Ids,song: &mut Song,editor: &mut Editor,view: &mut ViewSettings,playback: &mut Playback,save_path: Option<&str>,dialog: &mut Option<String>,lists: &mut WidgetLists,caches: &mut Caches,tone_widget_size: &mut [f64; 2]){
 	use conrod::{color,Borderable,Color,Colorable,Labelable,Positionable,Sizeable,Widget};
 	use conrod::position::Scalar;
 	use conrod::widget::grid;
 	use core::iter;
 
+	let position = playback.position(song);
+
 	//Canvas widget
 	//Contains everything.
+	let settings_layout = [
+		(ids.setting1_wrapper_canvas , widget::Canvas::new().color(color::TRANSPARENT)),
+		(ids.setting2_wrapper_canvas , widget::Canvas::new().color(color::TRANSPARENT)),
+		(ids.setting3_wrapper_canvas , widget::Canvas::new().color(color::TRANSPARENT)),
+		(ids.setting4_wrapper_canvas , widget::Canvas::new().color(color::TRANSPARENT).length_weight(2.0)),
+		(ids.setting5_wrapper_canvas , widget::Canvas::new().color(color::TRANSPARENT).length_weight(2.0)),
+	];
+	let mut layout = vec![
+		//Time ruler canvas widget
+		(ids.ruler_canvas , widget::Canvas::new()
+			.color(color::CHARCOAL)
+			.length(20.0)
+		),
+		//Tempo lane canvas widget
+		(ids.tempo_lane_canvas , widget::Canvas::new()
+			.color(Color::Rgba(0.12 , 0.12 , 0.12 , 1.0))
+			.length(48.0)
+		),
+	];
+	//Chord symbols lane widget
+	if view.chords.is_some(){
+		layout.push((ids.chords_lane_canvas , widget::Canvas::new()
+			.color(Color::Rgba(0.1 , 0.1 , 0.15 , 1.0))
+			.length(20.0)
+		));
+	}
+	//Overview strip widget
+	layout.push((ids.overview_canvas , widget::Canvas::new()
+		.color(Color::Rgba(0.08 , 0.08 , 0.08 , 1.0))
+		.length(40.0)
+	));
+	//Grand staff widget
+	if view.staff{
+		layout.push((ids.staff_canvas , widget::Canvas::new()
+			.color(Color::Rgba(0.92 , 0.91 , 0.86 , 1.0))
+			.length(160.0)
+		));
+	}
+	//Tones canvas wrapper widget
+	layout.push((ids.tones_wrapper_canvas , widget::Canvas::new()
+		.scroll_kids()
+		.color(color::DARK_CHARCOAL)
+	));
+	//Drum rows widget
+	//Only shown when there are tones on the drum channels, with a row for each percussion sound that they use.
+	let drum_notes: Vec<u8> = if editor.drums{
+		let mut used = [false; NOTES];
+		for (track,tones) in song.tracks.iter().enumerate(){
+			for tone in tones.iter().filter(|tone| editor.drum_channels.contains(&tone.channel) && editor.filter.accepts(track,tone)){
+				used[Into::<u8>::into(tone.note) as usize] = true;
+			}
+		}
+		//Bass drums at the bottom and cymbals at the top
+		(0..NOTES as u8).rev().filter(|&note| used[note as usize]).collect()
+	}else{
+		Vec::new()
+	};
+	const DRUM_ROW_HEIGHT: Scalar = 14.0;
+	if !drum_notes.is_empty(){
+		layout.push((ids.drums_canvas , widget::Canvas::new()
+			.color(Color::Rgba(0.1 , 0.1 , 0.1 , 1.0))
+			.length(drum_notes.len() as Scalar * DRUM_ROW_HEIGHT + 4.0)
+		));
+	}
+	//Minimap widget
+	layout.push((ids.minimap_canvas , widget::Canvas::new()
+		.color(Color::Rgba(0.06 , 0.06 , 0.06 , 1.0))
+		.length(48.0)
+	));
+	//Lyrics lane and karaoke panel widgets
+	//Only shown when there are lyrics.
+	if !song.lyrics.is_empty(){
+		layout.push((ids.lyrics_lane_canvas , widget::Canvas::new()
+			.color(Color::Rgba(0.15 , 0.15 , 0.2 , 1.0))
+			.length(24.0)
+		));
+		layout.push((ids.karaoke_canvas , widget::Canvas::new()
+			.color(color::BLACK)
+			.length(52.0)
+			.pad(4.0)
+		));
+	}
+	//Settings canvas widget
+	layout.push((ids.settings_canvas , widget::Canvas::new()
+		.color(color::DARK_GRAY)
+		.length(96.0)
+		.border(6.0)
+		.border_color(Color::Rgba(0.3 , 0.3 , 0.3 , 1.0))
+		.flow_right(&settings_layout)
+	));
 	widget::Canvas::new()
-		.flow_down(&[
-			//Tones canvas wrapper widget
-			(ids.tones_wrapper_canvas , widget::Canvas::new()
-				.scroll_kids()
-				.color(color::DARK_CHARCOAL)
-			),
-
-			//Tones canvas wrapper widget
-			(ids.settings_canvas , widget::Canvas::new()
-				.color(color::DARK_GRAY)
-				.length(96.0)
-				.border(6.0)
-				.border_color(Color::Rgba(0.3 , 0.3 , 0.3 , 1.0))
-				.flow_right(&[
-					(ids.setting1_wrapper_canvas , widget::Canvas::new().color(color::TRANSPARENT)),
-					(ids.setting2_wrapper_canvas , widget::Canvas::new().color(color::TRANSPARENT)),
-				])
-			),
-		])
+		.flow_down(&layout)
 		.color(color::BLACK)
 		.set(ids.canvas,ui);
 
@@ -255,12 +832,37 @@ fn set_ui(ui: &mut conrod::UiCell,ids: &Ids,song: &Song,tone_widget_ids: &mut Ve
 		])
 		.set(ids.tones_canvas,ui);
 
+	//Tone editing by mouse
+	//The tone bars are graphics for the tones canvas, so all mouse input on the tones goes to the canvas.
+	if let (Some(canvas_rect),Some(kid_rect)) = (ui.rect_of(ids.tones_canvas) , ui.kid_area_of(ids.tones_canvas)){
+		use conrod::input::ModifierKey;
+
+		//Input coordinates are relative to the middle of the widget.
+		let to_song_pos = |xy: [Scalar; 2]| SongPos{
+			time: (canvas_rect.x() + xy[0] - kid_rect.x.start) / tone_widget_size[0],
+			note: (kid_rect.y.end - canvas_rect.y() - xy[1]) / tone_widget_size[1],
+		};
+		let edge = 6.0 / tone_widget_size[0];
+		let input = ui.widget_input(ids.tones_canvas);
+
+		//The modifiers are the ones held at the press, which may differ from the current ones when events are queued
+		for (xy,modifiers) in input.presses().mouse().left(){
+			editor.press(song,to_song_pos(xy),modifiers.contains(ModifierKey::SHIFT),edge);
+		}
+		for drag in input.drags().left(){
+			editor.drag_to(song,to_song_pos(drag.to));
+		}
+		for _ in input.releases().mouse().left(){
+			editor.release(song);
+		}
+	}
+
 	//Generate more tone bar widget ids if not enough
 	for tones in &song.tracks{
-		if tone_widget_ids.len() < tones.len(){
-			tone_widget_ids.reserve(tones.len());
-			for _ in tone_widget_ids.len()..tones.len(){
-				tone_widget_ids.push([
+		if lists.tones.len() < tones.len(){
+			lists.tones.reserve(tones.len());
+			for _ in lists.tones.len()..tones.len(){
+				lists.tones.push([
 					ui.widget_id_generator().next(),
 					ui.widget_id_generator().next(),
 				]);
@@ -287,23 +889,74 @@ fn set_ui(ui: &mut conrod::UiCell,ids: &Ids,song: &Song,tone_widget_ids: &mut Ve
 			.top_left_of(ids.tones_canvas)
 			.set(ids.tones_grid,ui);
 
-		for tones in &song.tracks{
-			for (tone,[bar_id,text_id]) in tones.iter().zip(tone_widget_ids.iter().cloned()){
+		//Hide invisible tone bars (those that are not inside the scrolled view)
+		let view_times = (view_x / tone_widget_size[0] , (view_x+view_w) / tone_widget_size[0]);
+		let view_notes = (view_y / tone_widget_size[1] , (view_y+view_h) / tone_widget_size[1]);
+
+		//Rows of the notes in the scale
+		let scale_range = (view_times.0.max(0.0) as u32 , view_times.1.max(0.0).min(song.duration as f64) as u32);
+		let scale_spans = match view.scale{
+			None                                => Vec::new(),
+			Some(key::ScaleMode::Detected)      => {
+				//The keys are estimated for every hop from a multiple of it, so the range is extended to whole hops to estimate them again less often
+				let (window,hop) = (song.division as u32 * 8 , (song.division as u32 * 2).max(1));
+				let range = (scale_range.0 / hop * hop , (scale_range.1 / hop + 1) * hop);
+				Caches::get(&mut caches.keys,(editor.revision,editor.filter.clone(),range),|| key::sliding(song,|track,tone| editor.filter.accepts(track,tone),&editor.drum_channels,range,window,hop)).clone()
+			},
+			Some(key::ScaleMode::Chosen(chosen)) => vec![key::KeySpan{start: 0,end: song.duration.max(scale_range.1),key: chosen}],
+		};
+		let key_at = |time: u32| scale_spans.iter().find(|span| span.start <= time && time < span.end).map(|span| span.key);
+		let mut row_count = 0;
+		for span in &scale_spans{
+			let (start,end) = (span.start.max(scale_range.0) , span.end.min(scale_range.1));
+			if end <= start{continue}
+			for note in (view_notes.0.max(0.0) as u8)..(view_notes.1.ceil().max(0.0).min(NOTES as f64) as u8){
+				if !span.key.contains(note){continue}
+				widget::Rectangle::fill_with([((end - start) as Scalar) * tone_widget_size[0] , tone_widget_size[1]],if note % 12 == span.key.tonic{Color::Rgba(0.4,0.6,1.0,0.14)}else{Color::Rgba(0.4,0.6,1.0,0.07)})
+					.parent(ids.tones_canvas)
+					.graphics_for(ids.tones_canvas)
+					.place_on_kid_area(true)
+					.top_left_with_margins_on(ids.tones_canvas,(note as Scalar) * tone_widget_size[1],(start as Scalar) * tone_widget_size[0])
+					.set(WidgetLists::get(&mut lists.scale_rows,row_count,&mut ui.widget_id_generator()),ui);
+				row_count+= 1;
+			}
+		}
+
+		//Beat and bar lines
+		//Beats are left out when they are too close to each other to be distinguished, and bars likewise.
+		for (i,&(time,bar)) in song.tempo.beats(song.division,view_times).iter().enumerate(){
+			let signature = song.tempo.signature_at(time as f64);
+			let spacing = (if bar{signature.bar_length(song.division)}else{signature.beat_length(song.division)}) as Scalar * tone_widget_size[0];
+			if spacing < if bar{4.0}else{8.0}{continue}
+
+			widget::Rectangle::fill_with([1.0 , view_rect.h() - 32.0],if bar{Color::Rgba(0.6,0.6,0.6,0.35)}else{Color::Rgba(0.5,0.5,0.5,0.12)})
+				.parent(ids.tones_canvas)
+				.graphics_for(ids.tones_canvas)
+				.place_on_kid_area(true)
+				.top_left_with_margins_on(ids.tones_canvas,0.0,(time as Scalar) * tone_widget_size[0])
+				.set(WidgetLists::get(&mut lists.beats,i,&mut ui.widget_id_generator()),ui);
+		}
+
+		let roll_filter = editor.roll_filter();
+		for (track,tones) in song.tracks.iter().enumerate(){
+			for (index,tone) in visible_tones(tones,view_times,view_notes).filter(|&(_,tone)| roll_filter.accepts(track,tone)){
+				let [bar_id,text_id] = lists.tones[index];
 				let x = (tone.start_time as Scalar) * tone_widget_size[0];
 				let y = (Into::<u8>::into(tone.note) as f64) * tone_widget_size[1];
 				let w = ((tone.end_time as Scalar)-(tone.start_time as Scalar)) * tone_widget_size[0];
 				let h = tone_widget_size[1];
 
-				//Hide invisible tone bars (those that are not inside the scrolled view)
-				if x+w < view_x{continue} //Skip to the left.
-				if x > view_x+view_w{break} //Skip to the right. Break is okay because `tones` is guaranteed to be sorted.
-
-				if y+h < view_y{continue} //Skip above.
-				if y > view_y+view_h{continue} //Skip below.
-
 				//Bars widgets
-				widget::Rectangle::fill_with([w,h],Color::Rgba(1.0,1.0,1.0,0.5))
+				widget::Rectangle::fill_with([w,h],if editor.selection.contains(ToneId{track,index}){
+					Color::Rgba(1.0,0.8,0.3,0.8)
+				}else if !editor.drum_channels.contains(&tone.channel) && key_at(tone.start_time).map_or(false,|key| !key.contains(tone.note.into())){
+					//Out of the scale
+					Color::Rgba(1.0,0.45,0.45,0.7)
+				}else{
+					Color::Rgba(1.0,1.0,1.0,0.5)
+				})
 					.parent(ids.tones_canvas)
+					.graphics_for(ids.tones_canvas)
 					.place_on_kid_area(true)
 					.top_left_with_margins_on(ids.tones_canvas,y,x)
 					.set(bar_id,ui);
@@ -321,18 +974,829 @@ fn set_ui(ui: &mut conrod::UiCell,ids: &Ids,song: &Song,tone_widget_ids: &mut Ve
 				}
 			}
 		}
+
+		//Original positions of the tones in a previewed transformation
+		if let Some(ref preview) = editor.preview{
+			while lists.previews.len() < preview.originals.len(){
+				lists.previews.push(ui.widget_id_generator().next());
+			}
+			for (&(_,tone),&outline_id) in preview.originals.iter().zip(lists.previews.iter()){
+				let x = (tone.start_time as Scalar) * tone_widget_size[0];
+				let y = (Into::<u8>::into(tone.note) as f64) * tone_widget_size[1];
+				let w = ((tone.end_time as Scalar)-(tone.start_time as Scalar)) * tone_widget_size[0];
+				let h = tone_widget_size[1];
+				if x+w < view_x || x > view_x+view_w || y+h < view_y || y > view_y+view_h{continue}
+
+				widget::Rectangle::outline([w,h])
+					.color(Color::Rgba(0.4,0.9,1.0,0.7))
+					.parent(ids.tones_canvas)
+					.graphics_for(ids.tones_canvas)
+					.place_on_kid_area(true)
+					.top_left_with_margins_on(ids.tones_canvas,y,x)
+					.set(outline_id,ui);
+			}
+		}
+
+		//Rubber band selection rectangle
+		if let Some(Drag::RubberBand{origin,current}) = editor.drag{
+			widget::Rectangle::outline([
+				(origin.time - current.time).abs() * tone_widget_size[0],
+				(origin.note - current.note).abs() * tone_widget_size[1],
+			])
+				.color(Color::Rgba(0.5,0.7,1.0,0.9))
+				.parent(ids.tones_canvas)
+				.graphics_for(ids.tones_canvas)
+				.place_on_kid_area(true)
+				.top_left_with_margins_on(ids.tones_canvas,origin.note.min(current.note) * tone_widget_size[1],origin.time.min(current.time) * tone_widget_size[0])
+				.set(ids.tones_rubber_band,ui);
+		}
+
+		//Playhead
+		let x = position * tone_widget_size[0];
+		widget::Rectangle::fill_with([2.0 , view_rect.h() - 32.0],Color::Rgba(0.3,1.0,0.4,0.8))
+			.parent(ids.tones_canvas)
+			.graphics_for(ids.tones_canvas)
+			.place_on_kid_area(true)
+			.top_left_with_margins_on(ids.tones_canvas,0.0,x)
+			.set(ids.tones_playhead,ui);
+
+		//Follow the playhead by turning the page when it leaves the view, or after jumping outside of the view
+		if (playback.take_seeked() || playback.is_playing()) && (x < view_x || x > view_x+view_w){
+			ui.scroll_widget(ids.tones_wrapper_canvas,[-(x - view_x) , 0.0]);
+		}
+	}
+
+	//Marker flags on the time ruler
+	if let (Some(kid_rect),Some(ruler_rect)) = (ui.kid_area_of(ids.tones_canvas) , ui.rect_of(ids.ruler_canvas)){
+		while lists.markers.len() < song.markers.len(){
+			lists.markers.push([
+				ui.widget_id_generator().next(),
+				ui.widget_id_generator().next(),
+			]);
+		}
+		for (marker,&[flag_id,text_id]) in song.markers.iter().zip(lists.markers.iter()){
+			let x = kid_rect.x.start + (marker.time as Scalar) * tone_widget_size[0];
+			if x < ruler_rect.x.start || x > ruler_rect.x.end{continue}
+			let color = match marker.kind{
+				MarkerKind::Marker   => color::LIGHT_ORANGE,
+				MarkerKind::CuePoint => color::LIGHT_PURPLE,
+				MarkerKind::Bookmark => color::LIGHT_GREEN,
+			};
+
+			widget::Rectangle::fill_with([2.0 , ruler_rect.h()],color)
+				.parent(ids.ruler_canvas)
+				.graphics_for(ids.ruler_canvas)
+				.mid_left_with_margin_on(ids.ruler_canvas,x - ruler_rect.x.start)
+				.set(flag_id,ui);
+			widget::Text::new(&marker.text)
+				.parent(ids.ruler_canvas)
+				.graphics_for(ids.ruler_canvas)
+				.right_from(flag_id,3.0)
+				.font_size(10)
+				.color(color)
+				.set(text_id,ui);
+		}
+	}
+
+	//Jumping to markers
+	//The current section is selected.
+	let marker_names: Vec<String> = song.markers.iter().map(ToString::to_string).collect();
+	let current_marker = song.markers.iter().rposition(|marker| (marker.time as f64) <= position);
+	if let Some(i) = widget::DropDownList::new(&marker_names[..],current_marker)
+		.parent(ids.ruler_canvas)
+		.top_right_of(ids.ruler_canvas)
+		.wh([200.0 , 20.0])
+		.label(if song.markers.is_empty(){"No markers"}else{"Jump to"})
+		.label_font_size(9)
+		.max_visible_items(12)
+		.scrollbar_on_top()
+		.set(ids.ruler_jump_list,ui)
+	{
+		playback.seek(song.markers[i].time as f64);
+	}
+	for _ in widget::Button::new()
+		.parent(ids.ruler_canvas)
+		.left_from(ids.ruler_jump_list,4.0)
+		.wh([72.0 , 20.0])
+		.label("Bookmark")
+		.label_font_size(9)
+		.set(ids.ruler_bookmark_button,ui)
+	{
+		toggle_bookmark(song,position.round() as u32,save_path,&mut view.status);
+	}
+
+	//Chord symbols settings
+	for show in widget::Toggle::new(view.chords.is_some())
+		.parent(ids.ruler_canvas)
+		.left_from(ids.ruler_bookmark_button,4.0)
+		.wh([56.0 , 20.0])
+		.label("Chords")
+		.label_font_size(9)
+		.set(ids.ruler_chords_toggle,ui)
+	{
+		view.chords = if show{Some(chord::Segmentation::Beat)}else{None};
+	}
+	if let Some(segmentation) = view.chords{
+		let segmentation_names: Vec<&str> = chord::Segmentation::ALL.iter().map(|segmentation| segmentation.name()).collect();
+		if let Some(i) = widget::DropDownList::new(&segmentation_names[..],chord::Segmentation::ALL.iter().position(|&s| s == segmentation))
+			.parent(ids.ruler_canvas)
+			.left_from(ids.ruler_chords_toggle,4.0)
+			.wh([80.0 , 20.0])
+			.label_font_size(9)
+			.set(ids.ruler_chords_list,ui)
+		{
+			view.chords = Some(chord::Segmentation::ALL[i]);
+		}
+	}
+
+	//Scale shading settings
+	//The first two items are no scale and the detected keys, followed by every key.
+	let keys: Vec<key::Key> = key::Key::all().collect();
+	let scale_names: Vec<String> = ["No scale".to_string() , "Detected key".to_string()].iter().cloned().chain(keys.iter().map(|key| key.to_string())).collect();
+	let scale_index = match view.scale{
+		None                                 => 0,
+		Some(key::ScaleMode::Detected)       => 1,
+		Some(key::ScaleMode::Chosen(chosen)) => 2 + keys.iter().position(|&key| key == chosen).unwrap_or(0),
+	};
+	if let Some(i) = widget::DropDownList::new(&scale_names[..],Some(scale_index))
+		.parent(ids.ruler_canvas)
+		.left_from(if view.chords.is_some(){ids.ruler_chords_list}else{ids.ruler_chords_toggle},4.0)
+		.wh([96.0 , 20.0])
+		.label_font_size(9)
+		.max_visible_items(12)
+		.scrollbar_on_top()
+		.set(ids.ruler_scale_list,ui)
+	{
+		view.scale = match i{
+			0 => None,
+			1 => Some(key::ScaleMode::Detected),
+			_ => Some(key::ScaleMode::Chosen(keys[i-2])),
+		};
+	}
+
+	//Exporting the visible part as an SVG image next to the MIDI file
+	for _ in widget::Button::new()
+		.parent(ids.ruler_canvas)
+		.left_from(ids.ruler_scale_list,4.0)
+		.wh([64.0 , 20.0])
+		.label("SVG")
+		.label_font_size(9)
+		.set(ids.ruler_svg_button,ui)
+	{
+		if let (Some(kid_rect),Some(wrapper_rect)) = (ui.kid_area_of(ids.tones_canvas) , ui.rect_of(ids.tones_wrapper_canvas)){
+			let visible = render::View{
+				times    : (((wrapper_rect.x.start - kid_rect.x.start) / tone_widget_size[0]).max(0.0) , (wrapper_rect.x.end - kid_rect.x.start) / tone_widget_size[0]),
+				notes    : (((kid_rect.y.end - wrapper_rect.y.end) / tone_widget_size[1]).max(0.0) , ((kid_rect.y.end - wrapper_rect.y.start) / tone_widget_size[1]).min(NOTES as f64)),
+				tone_size: *tone_widget_size,
+			};
+			view.status = Some(match save_path{
+				Some(path) => {
+					let path = format!("{}.svg",path);
+					match std::fs::File::create(&path).map(std::io::BufWriter::new).and_then(|mut out| svg::write_svg(&render::draw(song,&editor.filter,&visible,None),&mut out).and_then(|()| std::io::Write::flush(&mut out))){
+						Ok(()) => format!("Exported the view to \"{}\"",path),
+						Err(e) => format!("Unable to export the view to \"{}\": {}",path,e),
+					}
+				},
+				None => "Unable to export the view: The file was read from standard input".to_string(),
+			});
+		}
+	}
+
+	//Grand staff setting
+	for show in widget::Toggle::new(view.staff)
+		.parent(ids.ruler_canvas)
+		.left_from(ids.ruler_svg_button,4.0)
+		.wh([56.0 , 20.0])
+		.label("Staff")
+		.label_font_size(9)
+		.set(ids.ruler_staff_toggle,ui)
+	{
+		view.staff = show;
+	}
+
+	//Drum rows setting
+	for show in widget::Toggle::new(editor.drums)
+		.parent(ids.ruler_canvas)
+		.left_from(ids.ruler_staff_toggle,4.0)
+		.wh([56.0 , 20.0])
+		.label("Drums")
+		.label_font_size(9)
+		.set(ids.ruler_drums_toggle,ui)
+	{
+		editor.drums = show;
+	}
+
+	//Chord symbols lane
+	//Recognizes the chords of the song, using the tracks of the selected tones when there is a selection, and shows those of the visible part.
+	if let (Some(segmentation),Some(kid_rect),Some(lane_rect)) = (view.chords , ui.kid_area_of(ids.tones_canvas) , ui.rect_of(ids.chords_lane_canvas)){
+		let zoom = tone_widget_size[0];
+		let range = (
+			((lane_rect.x.start - kid_rect.x.start) / zoom).max(0.0) as u32,
+			((lane_rect.x.end - kid_rect.x.start) / zoom).max(0.0).min(song.duration as f64) as u32,
+		);
+		let tracks = editor.selection.tracks();
+		let spans = Caches::get(&mut caches.chords,(editor.revision,editor.filter.clone(),tracks.clone(),segmentation),|| chord::detect(song,|track,tone| editor.filter.accepts(track,tone) && (tracks.is_empty() || tracks.contains(&track)),&editor.drum_channels,segmentation,(0 , song.duration)));
+		for (i,span) in spans.iter().filter(|span| span.end > range.0 && span.start < range.1).enumerate(){
+			widget::Text::new(&span.chord.to_string())
+				.parent(ids.chords_lane_canvas)
+				.graphics_for(ids.chords_lane_canvas)
+				.mid_left_with_margin_on(ids.chords_lane_canvas,kid_rect.x.start + (span.start.max(range.0) as Scalar) * zoom - lane_rect.x.start)
+				.font_size(11)
+				.color(color::LIGHT_GREEN)
+				.set(WidgetLists::get(&mut lists.chords,i,&mut ui.widget_id_generator()),ui);
+		}
+	}
+
+	//Grand staff
+	//Shows the visible tones as sheet music quantized to sixteenth notes, aligned with the roll. The notes from middle C are on the upper staff and the lower notes on the lower staff.
+	//Only exists when enabled. Clicking on it moves the playhead.
+	if let (true,Some(kid_rect),Some(lane_rect)) = (view.staff , ui.kid_area_of(ids.tones_canvas) , ui.rect_of(ids.staff_canvas)){
+		//Distance between the lines of a staff
+		const SPACE: Scalar = 8.0;
+		const INK: Color = Color::Rgba(0.1 , 0.1 , 0.1 , 1.0);
+		//Positions on the staves count letters from C₋₁: The lines of the upper staff are from E4 to F5, of the lower from G2 to A3, and middle C is between them.
+		const MIDDLE_C: i32 = 35;
+		const TREBLE_LINES: (i32,i32) = (37 , 45);
+		const BASS_LINES: (i32,i32) = (25 , 33);
+		//Where the sharps and flats of a key signature are on the upper staff. They are two octaves lower on the lower staff.
+		const SHARP_POSITIONS: [i32; 7] = [45,42,46,43,40,44,41];
+		const FLAT_POSITIONS: [i32; 7] = [41,44,40,43,39,42,38];
+
+		let zoom = tone_widget_size[0];
+		let x_of = |time: f64| kid_rect.x.start + time * zoom;
+		let y_of = |place: i32| lane_rect.y() + ((place - MIDDLE_C) as Scalar * SPACE / 2.0).max(4.0 - lane_rect.h() / 2.0).min(lane_rect.h() / 2.0 - 4.0);
+
+		for (xy,_) in ui.widget_input(ids.staff_canvas).presses().mouse().left(){
+			playback.seek((lane_rect.x() + xy[0] - kid_rect.x.start) / zoom);
+		}
+
+		let range = (
+			((lane_rect.x.start - kid_rect.x.start) / zoom).max(0.0) as u32,
+			((lane_rect.x.end - kid_rect.x.start) / zoom).max(0.0).min(song.duration as f64) as u32,
+		);
+		//The range is extended to whole chunks of four 4/4 bars, so that the score is made again less often when scrolling
+		let chunk = (song.division as u32 * 16).max(1);
+		let score_range = (range.0 / chunk * chunk , (range.1 / chunk + 1) * chunk);
+		let score = Caches::get(&mut caches.staff,(editor.revision,editor.filter.clone(),score_range),|| notation::Score::grand_staff(song,|track,tone| editor.filter.accepts(track,tone),&editor.drum_channels,score_range,notation::DEFAULT_GRID));
+		let key = score.measures.iter().rev().find(|measure| measure.time <= range.0).map(|measure| measure.key);
+		//The key signature is drawn at the left edge, where no notes are drawn
+		let left = lane_rect.x.start + SPACE * (2.0 + key.map_or(0,|key| key.sharps.abs()) as Scalar);
+
+		let mut lines: Vec<([Scalar; 2],[Scalar; 2],Color)> = Vec::new();
+		//Note heads and dots, whether they are filled and their widths
+		let mut heads: Vec<([Scalar; 2],bool,Scalar)> = Vec::new();
+		let mut texts: Vec<([Scalar; 2],&str)> = Vec::new();
+		let mut rests: Vec<[Scalar; 4]> = Vec::new();
+
+		for &(bottom,top) in &[TREBLE_LINES,BASS_LINES]{
+			for line in (bottom..=top).step_by(2){
+				lines.push(([lane_rect.x.start , y_of(line)],[lane_rect.x.end , y_of(line)],INK));
+			}
+		}
+		if let Some(key) = key{
+			let positions = if key.sharps >= 0{&SHARP_POSITIONS}else{&FLAT_POSITIONS};
+			for (i,&place) in positions.iter().take(key.sharps.abs() as usize).enumerate(){
+				let x = lane_rect.x.start + SPACE * (1.0 + i as Scalar);
+				texts.push(([x , y_of(place)],if key.sharps >= 0{"♯"}else{"♭"}));
+				texts.push(([x , y_of(place - 14)],if key.sharps >= 0{"♯"}else{"♭"}));
+			}
+		}
+
+		let visible = |measure: &&notation::Measure| x_of((measure.start + measure.length) as f64 * score.unit) >= left && x_of(measure.start as f64 * score.unit) <= lane_rect.x.end;
+		for measure in score.measures.iter().filter(&visible){
+			let x = x_of(measure.time as f64);
+			if x >= left{
+				lines.push(([x , y_of(TREBLE_LINES.1)],[x , y_of(BASS_LINES.0)],INK));
+			}
+		}
+
+		for part in &score.parts{
+			let middle = if part.track == 0{(TREBLE_LINES.0 + TREBLE_LINES.1) / 2}else{(BASS_LINES.0 + BASS_LINES.1) / 2};
+			for (measure,voices) in score.measures.iter().zip(&part.voices).filter(|&(measure,_)| visible(&measure)){
+				let voice_count = voices.iter().filter(|events| !events.is_empty()).count();
+				for (voice,events) in voices.iter().enumerate(){
+					for event in events{
+						let x = x_of((measure.start + event.offset) as f64 * score.unit) + SPACE;
+						if x < left || x > lane_rect.x.end{continue}
+						let value = event.duration.value;
+						//Flags of eighth notes and shorter
+						let flags = (value.trailing_zeros() as i32 - 2).max(0);
+
+						if event.pitches.is_empty(){
+							//Rests are moved apart when voices share the staff
+							let y = y_of(middle) + if voice_count > 1{if voice % 2 == 0{SPACE}else{-SPACE}}else{0.0};
+							match value{
+								//Hanging from the line above the middle line
+								1 => rests.push([x , y + SPACE*0.75 , SPACE*1.2 , SPACE*0.5]),
+								//Sitting on the middle line
+								2 => rests.push([x , y + SPACE*0.25 , SPACE*1.2 , SPACE*0.5]),
+								_ => {
+									rests.push([x , y , SPACE*0.4 , SPACE*2.5]);
+									for flag in 0..flags{
+										let flag_y = y + SPACE*1.25 - flag as Scalar * SPACE*0.7;
+										lines.push(([x , flag_y],[x + SPACE*0.8 , flag_y + SPACE*0.4],INK));
+									}
+								},
+							}
+							for dot in 0..event.duration.dots{
+								heads.push(([x + SPACE*(1.2 + dot as Scalar * 0.6) , y + SPACE*0.5],true,SPACE*0.3));
+							}
+							continue;
+						}
+
+						let places: Vec<i32> = event.pitches.iter().map(|pitch| pitch.spelling.position()).collect();
+						let (lowest,highest) = (places.iter().cloned().min().unwrap_or(middle) , places.iter().cloned().max().unwrap_or(middle));
+						let up = if voice_count > 1{voice % 2 == 0}else{lowest + highest < middle * 2};
+
+						for (pitch,&place) in event.pitches.iter().zip(&places){
+							let y = y_of(place);
+							heads.push(([x , y],value >= 4,SPACE*1.3));
+							for dot in 0..event.duration.dots{
+								heads.push(([x + SPACE*(1.2 + dot as Scalar * 0.6) , y + if place % 2 == 1{SPACE*0.5}else{0.0}],true,SPACE*0.3));
+							}
+							if pitch.accidental{
+								texts.push(([x - SPACE*1.6 , y],match pitch.spelling.alter{-1 => "♭",1 => "♯",_ => "♮"}));
+							}
+
+							//Ledger lines between the note and the staff
+							let ledgers: Vec<i32> = if place > TREBLE_LINES.1{
+								(TREBLE_LINES.1 + 2..=place).step_by(2).collect()
+							}else if place < BASS_LINES.0{
+								//From the first line at or above the note
+								((place | 1)..BASS_LINES.0).step_by(2).collect()
+							}else if place == MIDDLE_C{
+								vec![MIDDLE_C]
+							}else{
+								Vec::new()
+							};
+							for ledger in ledgers{
+								lines.push(([x - SPACE , y_of(ledger)],[x + SPACE , y_of(ledger)],INK));
+							}
+						}
+
+						//Stems of half notes and shorter, on the right going up or on the left going down
+						if value >= 2{
+							let (stem_x,from,to) = if up{
+								(x + SPACE*0.6 , y_of(lowest) , y_of(highest) + SPACE*3.5)
+							}else{
+								(x - SPACE*0.6 , y_of(highest) , y_of(lowest) - SPACE*3.5)
+							};
+							lines.push(([stem_x , from],[stem_x , to],INK));
+							for flag in 0..flags{
+								let flag_y = to + if up{-(flag as Scalar) * SPACE*0.8}else{flag as Scalar * SPACE*0.8};
+								lines.push(([stem_x , flag_y],[stem_x + SPACE , flag_y + if up{-SPACE*1.2}else{SPACE*1.2}],INK));
+							}
+						}
+					}
+				}
+			}
+		}
+
+		//Playhead
+		let x = x_of(position);
+		if x >= left && x <= lane_rect.x.end{
+			lines.push(([x , lane_rect.y.start],[x , lane_rect.y.end],Color::Rgba(0.1,0.6,0.2,0.8)));
+		}
+
+		for (i,&(start,end,color)) in lines.iter().enumerate(){
+			widget::Line::abs(start,end)
+				.parent(ids.staff_canvas)
+				.graphics_for(ids.staff_canvas)
+				.color(color)
+				.thickness(1.0)
+				.set(WidgetLists::get(&mut lists.staff_lines,i,&mut ui.widget_id_generator()),ui);
+		}
+		for (i,&([x,y],filled,width)) in heads.iter().enumerate(){
+			let id = WidgetLists::get(&mut lists.staff_heads,i,&mut ui.widget_id_generator());
+			//Note heads are slanted ovals in engraving, but upright ones are close enough
+			let dimensions = [width , if width > SPACE{SPACE}else{width}];
+			if filled{
+				widget::Oval::fill(dimensions).color(INK).parent(ids.staff_canvas).graphics_for(ids.staff_canvas).x_y(x,y).set(id,ui);
+			}else{
+				widget::Oval::outline(dimensions).color(INK).parent(ids.staff_canvas).graphics_for(ids.staff_canvas).x_y(x,y).set(id,ui);
+			}
+		}
+		for (i,&([x,y],text)) in texts.iter().enumerate(){
+			widget::Text::new(text)
+				.parent(ids.staff_canvas)
+				.graphics_for(ids.staff_canvas)
+				.x_y(x,y)
+				.font_size(14)
+				.color(INK)
+				.set(WidgetLists::get(&mut lists.staff_texts,i,&mut ui.widget_id_generator()),ui);
+		}
+		for (i,&[x,y,w,h]) in rests.iter().enumerate(){
+			widget::Rectangle::fill_with([w,h],INK)
+				.parent(ids.staff_canvas)
+				.graphics_for(ids.staff_canvas)
+				.x_y(x,y)
+				.set(WidgetLists::get(&mut lists.staff_rests,i,&mut ui.widget_id_generator()),ui);
+		}
+	}
+
+	//Drum rows
+	//Shows the tones on the drum channels as hits in a row for each percussion sound, named after General MIDI, instead of in the roll.
+	//The louder a hit, the larger its diamond. Clicking on it moves the playhead.
+	if let (false,Some(kid_rect),Some(lane_rect)) = (drum_notes.is_empty() , ui.kid_area_of(ids.tones_canvas) , ui.rect_of(ids.drums_canvas)){
+		//The names are on the left, where no hits are drawn
+		const NAMES_WIDTH: Scalar = 120.0;
+
+		let zoom = tone_widget_size[0];
+		let x_of = |time: f64| kid_rect.x.start + time * zoom;
+		let y_of = |row: usize| lane_rect.y.end - 2.0 - (row as Scalar + 0.5) * DRUM_ROW_HEIGHT;
+		let left = lane_rect.x.start + NAMES_WIDTH;
+
+		for (xy,_) in ui.widget_input(ids.drums_canvas).presses().mouse().left(){
+			playback.seek((lane_rect.x() + xy[0] - kid_rect.x.start) / zoom);
+		}
+
+		let times = ((left - kid_rect.x.start) / zoom , (lane_rect.x.end - kid_rect.x.start) / zoom);
+		let mut lines: Vec<([Scalar; 2],[Scalar; 2],Color)> = Vec::new();
+		for row in 1..drum_notes.len(){
+			let y = y_of(row) + DRUM_ROW_HEIGHT / 2.0;
+			lines.push(([lane_rect.x.start , y],[lane_rect.x.end , y],Color::Rgba(0.5,0.5,0.5,0.15)));
+		}
+		for (time,bar) in song.tempo.beats(song.division,times){
+			let signature = song.tempo.signature_at(time as f64);
+			let spacing = (if bar{signature.bar_length(song.division)}else{signature.beat_length(song.division)}) as Scalar * zoom;
+			if spacing < if bar{4.0}else{8.0}{continue}
+			let x = x_of(time as f64);
+			lines.push(([x , lane_rect.y.start],[x , lane_rect.y.end],if bar{Color::Rgba(0.6,0.6,0.6,0.35)}else{Color::Rgba(0.5,0.5,0.5,0.12)}));
+		}
+		let x = x_of(position);
+		if x >= left && x <= lane_rect.x.end{
+			lines.push(([x , lane_rect.y.start],[x , lane_rect.y.end],Color::Rgba(0.1,0.6,0.2,0.8)));
+		}
+		for (i,&(start,end,color)) in lines.iter().enumerate(){
+			widget::Line::abs(start,end)
+				.parent(ids.drums_canvas)
+				.graphics_for(ids.drums_canvas)
+				.color(color)
+				.thickness(1.0)
+				.set(WidgetLists::get(&mut lists.drum_lines,i,&mut ui.widget_id_generator()),ui);
+		}
+
+		for (row,&note) in drum_notes.iter().enumerate(){
+			widget::Text::new(&GeneralMidiLvl1PercussionNote::name_of(note))
+				.parent(ids.drums_canvas)
+				.graphics_for(ids.drums_canvas)
+				.mid_left_with_margin_on(ids.drums_canvas,4.0)
+				.y(y_of(row))
+				.font_size(9)
+				.color(color::LIGHT_GRAY)
+				.set(WidgetLists::get(&mut lists.drum_names,row,&mut ui.widget_id_generator()),ui);
+		}
+
+		let mut hit_count = 0;
+		for (track,tones) in song.tracks.iter().enumerate(){
+			for (index,tone) in visible_tones(tones,times,(0.0 , NOTES as f64)).filter(|&(_,tone)| editor.drum_channels.contains(&tone.channel) && editor.filter.accepts(track,tone)){
+				let x = x_of(tone.start_time as f64);
+				if x < left{continue}
+				let row = match drum_notes.iter().position(|&note| note == tone.note.into()){
+					Some(row) => row,
+					None => continue,
+				};
+				let y = y_of(row);
+				let radius = DRUM_ROW_HEIGHT * (0.15 + 0.35 * tone.atk_vel as Scalar / 127.0);
+				widget::Polygon::abs_fill_with(vec![[x , y + radius],[x + radius , y],[x , y - radius],[x - radius , y]],if editor.selection.contains(ToneId{track,index}){
+					Color::Rgba(1.0,0.8,0.3,0.9)
+				}else{
+					Color::Rgba(1.0,1.0,1.0,0.7)
+				})
+					.parent(ids.drums_canvas)
+					.graphics_for(ids.drums_canvas)
+					.set(WidgetLists::get(&mut lists.drum_hits,hit_count,&mut ui.widget_id_generator()),ui);
+				hit_count+= 1;
+			}
+		}
+	}
+
+	//Overview strip
+	//Shows how densely the notes fill the visible part of the song as a heatmap of pitch bands over time, and the number of sounding notes as a curve.
+	//Where more notes sound than the voice limit, a synthesizer with that many voices would drop some, which is marked red.
+	if let (Some(kid_rect),Some(strip_rect)) = (ui.kid_area_of(ids.tones_canvas) , ui.rect_of(ids.overview_canvas)){
+		const COLUMN_WIDTH: Scalar = 8.0;
+		const BANDS: usize = 8;
+
+		let zoom = tone_widget_size[0];
+		let x_of = |time: f64| kid_rect.x.start + time * zoom;
+		let range = (
+			((strip_rect.x.start - kid_rect.x.start) / zoom).max(0.0) as u32,
+			((strip_rect.x.end - kid_rect.x.start) / zoom).max(0.0).min(song.duration as f64) as u32,
+		);
+		let accept = |track: usize,tone: &Tone| editor.filter.accepts(track,tone);
+
+		if range.1 > range.0{
+			//Density heatmap, with the low notes at the top like in the roll
+			let columns = (((range.1 - range.0) as Scalar * zoom) / COLUMN_WIDTH).ceil() as usize;
+			let cells = stats::density(song,&accept,range,columns,BANDS);
+			let peak = cells.iter().flat_map(|band| band.iter()).cloned().fold(0.0,f64::max);
+			let (column_width,band_height) = ((range.1 - range.0) as Scalar * zoom / columns as Scalar , strip_rect.h() / BANDS as Scalar);
+			let mut count = 0;
+			for (band,values) in cells.iter().enumerate(){
+				for (column,&value) in values.iter().enumerate().filter(|&(_,&value)| value > 0.0){
+					widget::Rectangle::fill_with([column_width , band_height],Color::Rgba(1.0,0.6,0.2,((value / peak).sqrt() * 0.7) as f32))
+						.parent(ids.overview_canvas)
+						.graphics_for(ids.overview_canvas)
+						.x_y(x_of(range.0 as f64) + (column as Scalar + 0.5) * column_width , strip_rect.y.end - (band as Scalar + 0.5) * band_height)
+						.set(WidgetLists::get(&mut lists.density,count,&mut ui.widget_id_generator()),ui);
+					count+= 1;
+				}
+			}
+
+			//Polyphony curve as steps
+			let curve = stats::polyphony(song,&accept,range);
+			let limit = view.voice_limit as usize;
+			let scale = curve.iter().map(|&(_,count)| count).max().unwrap_or(0).max(limit).max(1) as Scalar;
+			let y_of = |count: usize| strip_rect.y.start + 2.0 + count as Scalar / scale * (strip_rect.h() - 4.0);
+			let mut sounding = curve.iter().rev().find(|&&(time,_)| time <= range.0).map_or(0,|&(_,count)| count);
+			let mut points = vec![[x_of(range.0 as f64) , y_of(sounding)]];
+			let mut overloads = Vec::new();
+			let mut overload_start = if sounding > limit{Some(range.0)}else{None};
+			for &(time,count) in curve.iter().filter(|&&(time,_)| time > range.0 && time < range.1){
+				points.push([x_of(time as f64) , y_of(sounding)]);
+				points.push([x_of(time as f64) , y_of(count)]);
+				match (overload_start,count > limit){
+					(None,true)         => overload_start = Some(time),
+					(Some(start),false) => {overloads.push((start,time)); overload_start = None;},
+					_ => (),
+				}
+				sounding = count;
+			}
+			points.push([x_of(range.1 as f64) , y_of(sounding)]);
+			overloads.extend(overload_start.map(|start| (start , range.1)));
+
+			for (i,&(start,end)) in overloads.iter().enumerate(){
+				widget::Rectangle::fill_with([((end - start) as Scalar * zoom).max(1.0) , strip_rect.h()],Color::Rgba(1.0,0.2,0.2,0.25))
+					.parent(ids.overview_canvas)
+					.graphics_for(ids.overview_canvas)
+					.x_y(x_of((start as f64 + end as f64) / 2.0) , strip_rect.y())
+					.set(WidgetLists::get(&mut lists.overloads,i,&mut ui.widget_id_generator()),ui);
+			}
+			widget::PointPath::abs(points)
+				.parent(ids.overview_canvas)
+				.graphics_for(ids.overview_canvas)
+				.color(color::LIGHT_GREEN)
+				.thickness(1.5)
+				.set(ids.overview_curve,ui);
+			widget::Line::abs([x_of(range.0 as f64) , y_of(limit)],[x_of(range.1 as f64) , y_of(limit)])
+				.parent(ids.overview_canvas)
+				.graphics_for(ids.overview_canvas)
+				.color(Color::Rgba(1.0,0.3,0.3,0.6))
+				.dashed()
+				.set(ids.overview_limit_line,ui);
+		}
+
+		if let Some(value) = widget::NumberDialer::new(view.voice_limit as f64 , 1.0 , 256.0 , 0)
+			.parent(ids.overview_canvas)
+			.top_right_with_margins_on(ids.overview_canvas,2.0,2.0)
+			.wh([72.0 , 16.0])
+			.label("Voices")
+			.label_font_size(9)
+			.set(ids.overview_limit_dialer,ui)
+		{
+			view.voice_limit = value as u32;
+		}
+	}
+
+	//Minimap
+	//Shows the whole song scaled down, with a column for every slice of time from its lowest to its highest sounding note, and the visible part as a rectangle.
+	//Pressing outside of the rectangle moves the view there, and dragging moves the view along.
+	if let (Some(kid_rect),Some(wrapper_rect),Some(minimap_rect)) = (ui.kid_area_of(ids.tones_canvas) , ui.rect_of(ids.tones_wrapper_canvas) , ui.rect_of(ids.minimap_canvas)){
+		let duration = (song.duration as f64).max(1.0);
+		let x_of = |time: f64| minimap_rect.x.start + time / duration * minimap_rect.w();
+		let y_of = |note: f64| minimap_rect.y.end - note / NOTES as f64 * minimap_rect.h();
+		//Input coordinates are relative to the middle of the widget.
+		let pointer_of = |xy: [Scalar; 2]| SongPos{
+			time: (minimap_rect.x() + xy[0] - minimap_rect.x.start) / minimap_rect.w() * duration,
+			note: (minimap_rect.y.end - minimap_rect.y() - xy[1]) / minimap_rect.h() * NOTES as f64,
+		};
+
+		//The visible part of the song
+		let view_start = SongPos{time: (wrapper_rect.x.start - kid_rect.x.start) / tone_widget_size[0],note: (kid_rect.y.end - wrapper_rect.y.end) / tone_widget_size[1]};
+		let view_end   = SongPos{time: (wrapper_rect.x.end   - kid_rect.x.start) / tone_widget_size[0],note: (kid_rect.y.end - wrapper_rect.y.start) / tone_widget_size[1]};
+		let center = SongPos{time: (view_start.time + view_end.time) / 2.0,note: (view_start.note + view_end.note) / 2.0};
+
+		//Where to move the middle of the view
+		let mut target = None;
+		{
+			let input = ui.widget_input(ids.minimap_canvas);
+			for (xy,_) in input.presses().mouse().left(){
+				let pointer = pointer_of(xy);
+				let inside = pointer.time >= view_start.time && pointer.time <= view_end.time && pointer.note >= view_start.note && pointer.note <= view_end.note;
+				let offset = if inside{SongPos{time: center.time - pointer.time,note: center.note - pointer.note}}else{SongPos{time: 0.0,note: 0.0}};
+				editor.drag = Some(Drag::View{offset});
+				target = Some(SongPos{time: pointer.time + offset.time,note: pointer.note + offset.note});
+			}
+			if let Some(Drag::View{offset}) = editor.drag{
+				for drag in input.drags().left(){
+					let pointer = pointer_of(drag.to);
+					target = Some(SongPos{time: pointer.time + offset.time,note: pointer.note + offset.note});
+				}
+				for _ in input.releases().mouse().left(){
+					editor.release(song);
+				}
+			}
+		}
+		if let Some(target) = target{
+			ui.scroll_widget(ids.tones_wrapper_canvas,[-(target.time - center.time) * tone_widget_size[0] , (target.note - center.note) * tone_widget_size[1]]);
+		}
+
+		//Slices of the song, one for every pixel
+		let width = minimap_rect.w().max(0.0) as usize;
+		let slices = Caches::get(&mut caches.minimap,(editor.revision,editor.filter.clone(),width),|| stats::slices(song,|track,tone| editor.filter.accepts(track,tone),width));
+		for (i,(column,slice)) in slices.iter().enumerate().filter_map(|(column,slice)| slice.map(|slice| (column,slice))).enumerate(){
+			let top = y_of(slice.lowest as f64);
+			let bottom = y_of(slice.highest as f64 + 1.0);
+			widget::Rectangle::fill_with([1.0 , (top - bottom).max(1.0)],Color::Rgba(0.9,0.9,0.9,(0.3 + 0.1 * slice.tones as f32).min(0.9)))
+				.parent(ids.minimap_canvas)
+				.graphics_for(ids.minimap_canvas)
+				.x_y(minimap_rect.x.start + column as Scalar + 0.5 , (top + bottom) / 2.0)
+				.set(WidgetLists::get(&mut lists.minimap,i,&mut ui.widget_id_generator()),ui);
+		}
+
+		//Visible part
+		let (left,right) = (x_of(view_start.time.max(0.0)) , x_of(view_end.time.min(duration)));
+		let (top,bottom) = (y_of(view_start.note.max(0.0)) , y_of(view_end.note.min(NOTES as f64)));
+		widget::Rectangle::outline([(right - left).max(2.0) , (top - bottom).max(2.0)])
+			.color(color::LIGHT_YELLOW)
+			.parent(ids.minimap_canvas)
+			.graphics_for(ids.minimap_canvas)
+			.x_y((left + right) / 2.0 , (top + bottom) / 2.0)
+			.set(ids.minimap_viewport,ui);
+	}
+
+	//Tempo lane
+	//Shows the tempo curve and the time signature changes.
+	//Pressing grabs a tempo change or adds one, dragging moves it and right clicking removes it.
+	if let (Some(kid_rect),Some(lane_rect)) = (ui.kid_area_of(ids.tones_canvas) , ui.rect_of(ids.tempo_lane_canvas)){
+		let zoom = tone_widget_size[0];
+		let x_of = |time: f64| kid_rect.x.start + time * zoom;
+		let y_of = |tempo: u32| lane_rect.y.start + 4.0 + (tempo::bpm(tempo).max(BPM_RANGE.0).min(BPM_RANGE.1) - BPM_RANGE.0) / (BPM_RANGE.1 - BPM_RANGE.0) * (lane_rect.h() - 8.0);
+		//Input coordinates are relative to the middle of the widget.
+		let time_of = |xy: [Scalar; 2]| (lane_rect.x() + xy[0] - kid_rect.x.start) / zoom;
+		let tempo_of = |xy: [Scalar; 2]| tempo::tempo_from_bpm(
+			(BPM_RANGE.0 + (lane_rect.y() + xy[1] - lane_rect.y.start - 4.0) / (lane_rect.h() - 8.0) * (BPM_RANGE.1 - BPM_RANGE.0)).max(BPM_RANGE.0).min(BPM_RANGE.1).round()
+		);
+		let distance = 6.0 / zoom;
+
+		let input = ui.widget_input(ids.tempo_lane_canvas);
+		for (xy,_) in input.presses().mouse().left(){
+			editor.press_tempo(song,time_of(xy),tempo_of(xy),distance);
+		}
+		for (xy,_) in input.presses().mouse().right(){
+			editor.remove_tempo(song,time_of(xy),distance);
+		}
+		if let Some(Drag::Tempo{..}) = editor.drag{
+			for drag in input.drags().left(){
+				editor.drag_tempo(song,time_of(drag.to),tempo_of(drag.to));
+			}
+			for _ in input.releases().mouse().left(){
+				editor.release(song);
+			}
+		}
+
+		//Tempo curve as steps
+		let view = ((lane_rect.x.start - kid_rect.x.start) / zoom , (lane_rect.x.end - kid_rect.x.start) / zoom);
+		let mut tempo = song.tempo.tempo_at(view.0);
+		let mut points = vec![[lane_rect.x.start , y_of(tempo)]];
+		for change in song.tempo.tempos.iter().filter(|change| (change.time as f64) > view.0 && (change.time as f64) < view.1){
+			points.push([x_of(change.time as f64) , y_of(tempo)]);
+			points.push([x_of(change.time as f64) , y_of(change.tempo)]);
+			tempo = change.tempo;
+		}
+		points.push([lane_rect.x.end , y_of(tempo)]);
+		widget::PointPath::abs(points)
+			.parent(ids.tempo_lane_canvas)
+			.graphics_for(ids.tempo_lane_canvas)
+			.color(color::LIGHT_BLUE)
+			.thickness(1.5)
+			.set(ids.tempo_curve,ui);
+
+		//Tempo changes with their tempos in beats per minute
+		let mut count = 0;
+		for change in song.tempo.tempos.iter().filter(|change| (change.time as f64) >= view.0 && (change.time as f64) <= view.1){
+			let point_id = WidgetLists::get(&mut lists.tempos,count*2,&mut ui.widget_id_generator());
+			let text_id = WidgetLists::get(&mut lists.tempos,count*2+1,&mut ui.widget_id_generator());
+			widget::Circle::fill(3.0)
+				.parent(ids.tempo_lane_canvas)
+				.graphics_for(ids.tempo_lane_canvas)
+				.x_y(x_of(change.time as f64) , y_of(change.tempo))
+				.color(color::WHITE)
+				.set(point_id,ui);
+			widget::Text::new(&format!("{:.0}",tempo::bpm(change.tempo)))
+				.parent(ids.tempo_lane_canvas)
+				.graphics_for(ids.tempo_lane_canvas)
+				.right_from(point_id,3.0)
+				.font_size(9)
+				.color(color::LIGHT_BLUE)
+				.set(text_id,ui);
+			count+= 1;
+		}
+
+		//Time signature changes
+		let mut count = 0;
+		for signature in song.tempo.signatures.iter().filter(|signature| (signature.time as f64) >= view.0 && (signature.time as f64) <= view.1){
+			widget::Text::new(&format!("{}/{}",signature.numerator,signature.denominator))
+				.parent(ids.tempo_lane_canvas)
+				.graphics_for(ids.tempo_lane_canvas)
+				.top_left_with_margins_on(ids.tempo_lane_canvas,2.0,x_of(signature.time as f64) - lane_rect.x.start + 3.0)
+				.font_size(10)
+				.color(color::LIGHT_ORANGE)
+				.set(WidgetLists::get(&mut lists.signatures,count,&mut ui.widget_id_generator()),ui);
+			count+= 1;
+		}
+	}
+
+	//Lyrics lane
+	//The syllables are placed at their times, aligned with the tones above.
+	//Only exists when there are lyrics.
+	if let (Some(kid_rect),Some(lane_rect)) = (ui.kid_area_of(ids.tones_canvas) , ui.rect_of(ids.lyrics_lane_canvas)){
+		const FONT_SIZE: u32 = 11;
+
+		//Clicking in the lane moves the playhead
+		for (xy,_) in ui.widget_input(ids.lyrics_lane_canvas).presses().mouse().left(){
+			playback.seek((lane_rect.x() + xy[0] - kid_rect.x.start) / tone_widget_size[0]);
+		}
+
+		let current = lyrics::current(&song.lyrics,position);
+		let mut drawn_end = lane_rect.x.start;
+		let mut count = 0;
+		for (index,lyric) in song.lyrics.iter().enumerate(){
+			let x = kid_rect.x.start + (lyric.time as Scalar) * tone_widget_size[0];
+			if x > lane_rect.x.end{break}
+			//Syllables that would overlap the previous one are left out. Roughly estimates the width of the text.
+			if x < drawn_end{continue}
+			drawn_end = x + (lyric.text.chars().count() as Scalar + 1.0) * (FONT_SIZE as Scalar) * 0.6;
+
+			let text_id = WidgetLists::get(&mut lists.lyrics,count,&mut ui.widget_id_generator());
+			widget::Text::new(&lyric.text)
+				.parent(ids.lyrics_lane_canvas)
+				.graphics_for(ids.lyrics_lane_canvas)
+				.mid_left_with_margin_on(ids.lyrics_lane_canvas,x - lane_rect.x.start)
+				.font_size(FONT_SIZE)
+				.color(if Some(index) == current{color::LIGHT_YELLOW}else{color::WHITE})
+				.set(text_id,ui);
+			count+= 1;
+		}
+	}
+
+	//Karaoke panel
+	//Shows the line being sung with the sung syllables highlighted, and the next line below it.
+	if !song.lyrics.is_empty(){
+		let current = lyrics::current(&song.lyrics,position);
+		let line = lyrics::line(&song.lyrics,current.unwrap_or(0));
+		let (sung,current_text,rest): (String,&str,String) = match current{
+			Some(current) => (
+				song.lyrics[line.start..current].iter().map(|lyric| lyric.text.as_str()).collect(),
+				song.lyrics[current].text.as_str(),
+				song.lyrics[current+1..line.end].iter().map(|lyric| lyric.text.as_str()).collect(),
+			),
+			None => (String::new() , "" , song.lyrics[line.clone()].iter().map(|lyric| lyric.text.as_str()).collect()),
+		};
+		let next: String = if line.end < song.lyrics.len(){
+			song.lyrics[lyrics::line(&song.lyrics,line.end)].iter().map(|lyric| lyric.text.as_str()).collect()
+		}else{
+			String::new()
+		};
+
+		widget::Text::new(&sung)
+			.parent(ids.karaoke_canvas)
+			.top_left_of(ids.karaoke_canvas)
+			.font_size(18)
+			.color(color::LIGHT_BLUE)
+			.set(ids.karaoke_sung_text,ui);
+		widget::Text::new(current_text)
+			.parent(ids.karaoke_canvas)
+			.right_from(ids.karaoke_sung_text,0.0)
+			.font_size(18)
+			.color(color::LIGHT_YELLOW)
+			.set(ids.karaoke_current_text,ui);
+		widget::Text::new(&rest)
+			.parent(ids.karaoke_canvas)
+			.right_from(ids.karaoke_current_text,0.0)
+			.font_size(18)
+			.color(color::WHITE)
+			.set(ids.karaoke_rest_text,ui);
+		widget::Text::new(&next)
+			.parent(ids.karaoke_canvas)
+			.bottom_left_of(ids.karaoke_canvas)
+			.font_size(12)
+			.color(color::GRAY)
+			.set(ids.karaoke_next_text,ui);
 	}
 
 	//Tones horizontal scrollbar
+	//The minimap is the main way of navigating, so the scrollbars are only shown when in use.
 	widget::Scrollbar::x_axis(ids.tones_wrapper_canvas)
-		.thickness(20.0)
-		.auto_hide(false)
+		.thickness(10.0)
+		.auto_hide(true)
 		.set(ids.tones_scrollbar_x,ui);
 
 	//Tones vertical scrollbar
 	widget::Scrollbar::y_axis(ids.tones_wrapper_canvas)
-		.thickness(20.0)
-		.auto_hide(false)
+		.thickness(10.0)
+		.auto_hide(true)
 		.set(ids.tones_scrollbar_y,ui);
 
 	//Settings bar width slider
@@ -364,4 +1828,320 @@ fn set_ui(ui: &mut conrod::UiCell,ids: &Ids,song: &Song,tone_widget_ids: &mut Ve
 	{
 		tone_widget_size[1] = value;
 	}
+
+	//Settings time signature at the playhead
+	let signature = song.tempo.signature_at(position);
+	if let Some(value) = widget::NumberDialer::new(signature.numerator as f64 , 1.0 , 32.0 , 0)
+		.parent(ids.setting2_wrapper_canvas)
+		.place_on_kid_area(true)
+		.down_from(ids.settings_barheight_slider,6.0)
+		.align_left_of(ids.settings_barheight_slider)
+		.wh([64.0 , 16.0])
+		.label("Beats")
+		.label_font_size(9)
+		.set(ids.settings_signature_numerator_dialer,ui)
+	{
+		editor.set_signature(song,position,value as u8,signature.denominator);
+	}
+	let denominator_names = ["1","2","4","8","16","32"];
+	if let Some(i) = widget::DropDownList::new(&denominator_names[..],Some((signature.denominator.max(1) as u32).trailing_zeros() as usize))
+		.parent(ids.setting2_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_signature_numerator_dialer,4.0)
+		.wh([48.0 , 16.0])
+		.label_font_size(9)
+		.set(ids.settings_signature_denominator_list,ui)
+	{
+		editor.set_signature(song,position,signature.numerator,1 << i);
+	}
+
+	//Settings tool toggles
+	for _ in widget::Toggle::new(editor.tool == Tool::Select)
+		.parent(ids.setting3_wrapper_canvas)
+		.place_on_kid_area(true)
+		.mid_left_of(ids.setting3_wrapper_canvas)
+		.wh([64.0 , 16.0])
+		.label("Select")
+		.label_font_size(9)
+		.set(ids.settings_tool_select_toggle,ui)
+	{
+		editor.tool = Tool::Select;
+	}
+	for _ in widget::Toggle::new(editor.tool == Tool::Pencil)
+		.parent(ids.setting3_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_tool_select_toggle,8.0)
+		.wh([64.0 , 16.0])
+		.label("Pencil")
+		.label_font_size(9)
+		.set(ids.settings_tool_pencil_toggle,ui)
+	{
+		editor.tool = Tool::Pencil;
+	}
+
+	//Settings operation scope toggle
+	for whole_tracks in widget::Toggle::new(editor.whole_tracks)
+		.parent(ids.setting3_wrapper_canvas)
+		.place_on_kid_area(true)
+		.down_from(ids.settings_tool_select_toggle,8.0)
+		.wh([136.0 , 16.0])
+		.label("Whole tracks")
+		.label_font_size(9)
+		.set(ids.settings_whole_tracks_toggle,ui)
+	{
+		editor.whole_tracks = whole_tracks;
+	}
+
+	//Settings playback
+	for _ in widget::Button::new()
+		.parent(ids.setting3_wrapper_canvas)
+		.place_on_kid_area(true)
+		.down_from(ids.settings_whole_tracks_toggle,8.0)
+		.wh([136.0 , 16.0])
+		.label(if playback.is_playing(){"Pause"}else{"Play"})
+		.label_font_size(9)
+		.set(ids.settings_play_button,ui)
+	{
+		playback.toggle(song);
+	}
+
+	//Settings quantization
+	let quantize_before = editor.quantize;
+	let grid_names: Vec<String> = quantize::Grid::DENOMINATORS.iter().map(|denominator| format!("1/{}",denominator)).collect();
+	if let Some(i) = widget::DropDownList::new(&grid_names[..],quantize::Grid::DENOMINATORS.iter().position(|&denominator| denominator == editor.quantize.grid.denominator))
+		.parent(ids.setting4_wrapper_canvas)
+		.place_on_kid_area(true)
+		.top_left_of(ids.setting4_wrapper_canvas)
+		.wh([56.0 , 16.0])
+		.label_font_size(9)
+		.set(ids.settings_quantize_grid_list,ui)
+	{
+		editor.quantize.grid.denominator = quantize::Grid::DENOMINATORS[i];
+	}
+	let kind_names: Vec<&str> = quantize::GridKind::ALL.iter().map(|kind| kind.name()).collect();
+	if let Some(i) = widget::DropDownList::new(&kind_names[..],quantize::GridKind::ALL.iter().position(|&kind| kind == editor.quantize.grid.kind))
+		.parent(ids.setting4_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_quantize_grid_list,4.0)
+		.wh([72.0 , 16.0])
+		.label_font_size(9)
+		.set(ids.settings_quantize_kind_list,ui)
+	{
+		editor.quantize.grid.kind = quantize::GridKind::ALL[i];
+	}
+	let target_names: Vec<&str> = quantize::Target::ALL.iter().map(|target| target.name()).collect();
+	if let Some(i) = widget::DropDownList::new(&target_names[..],quantize::Target::ALL.iter().position(|&target| target == editor.quantize.target))
+		.parent(ids.setting4_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_quantize_kind_list,4.0)
+		.wh([64.0 , 16.0])
+		.label_font_size(9)
+		.set(ids.settings_quantize_target_list,ui)
+	{
+		editor.quantize.target = quantize::Target::ALL[i];
+	}
+	if let Some(value) = widget::Slider::new(editor.quantize.strength , 0.0 , 1.0)
+		.parent(ids.setting4_wrapper_canvas)
+		.place_on_kid_area(true)
+		.down_from(ids.settings_quantize_grid_list,6.0)
+		.wh([96.0 , 16.0])
+		.label("Strength")
+		.label_font_size(9)
+		.set(ids.settings_quantize_strength_slider,ui)
+	{
+		editor.quantize.strength = value;
+	}
+	if let Some(value) = widget::Slider::new(editor.quantize.swing , 0.0 , 0.5)
+		.parent(ids.setting4_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_quantize_strength_slider,4.0)
+		.wh([96.0 , 16.0])
+		.label("Swing")
+		.label_font_size(9)
+		.set(ids.settings_quantize_swing_slider,ui)
+	{
+		editor.quantize.swing = value;
+	}
+	for _ in widget::Button::new()
+		.parent(ids.setting4_wrapper_canvas)
+		.place_on_kid_area(true)
+		.down_from(ids.settings_quantize_strength_slider,6.0)
+		.wh([64.0 , 16.0])
+		.label("Quantize")
+		.label_font_size(9)
+		.set(ids.settings_quantize_button,ui)
+	{
+		editor.preview_quantize(song);
+	}
+	//Adjusting the quantization during its preview updates the preview
+	if editor.previewing(Operation::Quantize) && editor.quantize != quantize_before{
+		editor.preview_quantize(song);
+	}
+	for _ in widget::Button::new()
+		.parent(ids.setting4_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_quantize_button,4.0)
+		.wh([48.0 , 16.0])
+		.label("Apply")
+		.label_font_size(9)
+		.enabled(editor.preview.is_some())
+		.set(ids.settings_preview_apply_button,ui)
+	{
+		editor.commit_preview(song);
+	}
+	for _ in widget::Button::new()
+		.parent(ids.setting4_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_preview_apply_button,4.0)
+		.wh([48.0 , 16.0])
+		.label("Cancel")
+		.label_font_size(9)
+		.enabled(editor.preview.is_some())
+		.set(ids.settings_preview_cancel_button,ui)
+	{
+		editor.cancel_preview(song);
+		view.status = None;
+	}
+
+	//Settings transformations
+	let transform_before = editor.transform;
+	if let Some(value) = widget::NumberDialer::new(editor.transform.transpose as f64 , -48.0 , 48.0 , 0)
+		.parent(ids.setting5_wrapper_canvas)
+		.place_on_kid_area(true)
+		.top_left_of(ids.setting5_wrapper_canvas)
+		.wh([96.0 , 16.0])
+		.label("Transpose")
+		.label_font_size(9)
+		.set(ids.settings_transpose_dialer,ui)
+	{
+		editor.transform.transpose = value as i32;
+	}
+	if let Some(value) = widget::NumberDialer::new(editor.transform.stretch , 0.05 , 8.0 , 2)
+		.parent(ids.setting5_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_transpose_dialer,4.0)
+		.wh([96.0 , 16.0])
+		.label("Stretch")
+		.label_font_size(9)
+		.set(ids.settings_stretch_dialer,ui)
+	{
+		editor.transform.stretch = value;
+	}
+	if let Some(value) = widget::NumberDialer::new(editor.transform.velocity_scale , 0.0 , 4.0 , 2)
+		.parent(ids.setting5_wrapper_canvas)
+		.place_on_kid_area(true)
+		.down_from(ids.settings_transpose_dialer,6.0)
+		.wh([96.0 , 16.0])
+		.label("Velocity")
+		.label_font_size(9)
+		.set(ids.settings_velocity_scale_dialer,ui)
+	{
+		editor.transform.velocity_scale = value;
+	}
+	if let Some(value) = widget::NumberDialer::new(editor.transform.velocity_compress , 0.0 , 1.0 , 2)
+		.parent(ids.setting5_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_velocity_scale_dialer,4.0)
+		.wh([96.0 , 16.0])
+		.label("Compress")
+		.label_font_size(9)
+		.set(ids.settings_velocity_compress_dialer,ui)
+	{
+		editor.transform.velocity_compress = value;
+	}
+	if let Some(value) = widget::NumberDialer::new(editor.transform.velocity_random as f64 , 0.0 , 64.0 , 0)
+		.parent(ids.setting5_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_velocity_compress_dialer,4.0)
+		.wh([96.0 , 16.0])
+		.label("Randomize")
+		.label_font_size(9)
+		.set(ids.settings_velocity_random_dialer,ui)
+	{
+		editor.transform.velocity_random = value as u8;
+	}
+	for reverse in widget::Toggle::new(editor.transform.reverse)
+		.parent(ids.setting5_wrapper_canvas)
+		.place_on_kid_area(true)
+		.down_from(ids.settings_velocity_scale_dialer,6.0)
+		.wh([64.0 , 16.0])
+		.label("Reverse")
+		.label_font_size(9)
+		.set(ids.settings_reverse_toggle,ui)
+	{
+		editor.transform.reverse = reverse;
+	}
+	for invert in widget::Toggle::new(editor.transform.invert.is_some())
+		.parent(ids.setting5_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_reverse_toggle,4.0)
+		.wh([64.0 , 16.0])
+		.label("Invert")
+		.label_font_size(9)
+		.set(ids.settings_invert_toggle,ui)
+	{
+		editor.transform.invert = if invert{Some(60)}else{None}; //Inverts around C₄.
+	}
+	for _ in widget::Button::new()
+		.parent(ids.setting5_wrapper_canvas)
+		.place_on_kid_area(true)
+		.right_from(ids.settings_invert_toggle,4.0)
+		.wh([72.0 , 16.0])
+		.label("Transform")
+		.label_font_size(9)
+		.set(ids.settings_transform_button,ui)
+	{
+		view.status = clamped_status(editor.preview_transform(song));
+	}
+	//Adjusting the transformations during their preview updates the preview
+	if editor.previewing(Operation::Transform) && editor.transform != transform_before{
+		view.status = clamped_status(editor.preview_transform(song));
+	}
+
+	//Status text
+	if let Some(ref status) = view.status{
+		widget::Text::new(status)
+			.parent(ids.canvas)
+			.bottom_left_with_margins_on(ids.tones_wrapper_canvas,28.0,8.0)
+			.font_size(11)
+			.color(color::LIGHT_YELLOW)
+			.set(ids.status_text,ui);
+	}
+
+	//Message dialog
+	let mut dialog_closed = false;
+	if let Some(ref message) = *dialog{
+		widget::Canvas::new()
+			.floating(true)
+			.middle_of(ids.canvas)
+			.wh([400.0 , 160.0])
+			.color(color::CHARCOAL)
+			.border(2.0)
+			.border_color(color::LIGHT_RED)
+			.pad(12.0)
+			.set(ids.dialog_canvas,ui);
+
+		widget::Text::new(message)
+			.parent(ids.dialog_canvas)
+			.top_left_of(ids.dialog_canvas)
+			.padded_w_of(ids.dialog_canvas,12.0)
+			.font_size(12)
+			.color(color::WHITE)
+			.wrap_by_word()
+			.set(ids.dialog_text,ui);
+
+		for _ in widget::Button::new()
+			.parent(ids.dialog_canvas)
+			.bottom_right_of(ids.dialog_canvas)
+			.wh([64.0 , 20.0])
+			.label("OK")
+			.label_font_size(11)
+			.set(ids.dialog_button,ui)
+		{
+			dialog_closed = true;
+		}
+	}
+	if dialog_closed{
+		*dialog = None;
+	}
 }
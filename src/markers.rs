@@ -0,0 +1,128 @@
+//Named positions in a song: Marker and Cue Point meta events, and bookmarks added by the user.
+//Bookmarks are not written into the MIDI file but into a separate file next to it, so that the MIDI file is left as it is.
+
+use core::fmt;
+use std::{fs,io};
+use std::io::Write;
+
+use smf::{self,meta,Chunk,ChunkKind,RawEvent};
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum MarkerKind{
+	///A Marker meta event, usually naming a section such as a verse or a chorus.
+	Marker,
+	///A Cue Point meta event, usually describing something happening on stage.
+	CuePoint,
+	///Added by the user.
+	Bookmark,
+}
+
+impl MarkerKind{
+	pub const ALL: [MarkerKind; 3] = [MarkerKind::Marker,MarkerKind::CuePoint,MarkerKind::Bookmark];
+
+	pub fn name(self) -> &'static str{match self{
+		MarkerKind::Marker   => "Marker",
+		MarkerKind::CuePoint => "Cue point",
+		MarkerKind::Bookmark => "Bookmark",
+	}}
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Marker{
+	///Time in ticks.
+	pub time: u32,
+	pub kind: MarkerKind,
+	pub text: String,
+}
+
+impl fmt::Display for Marker{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{
+		if self.text.is_empty(){
+			write!(f,"{} at tick {}",self.kind.name(),self.time)
+		}else{
+			write!(f,"{} (tick {})",self.text,self.time)
+		}
+	}
+}
+
+///The markers and cue points in the tracks, ordered by time.
+pub fn meta_markers(data: &[u8],chunks: &[Chunk]) -> Vec<Marker>{
+	let mut markers = Vec::new();
+	for chunk in chunks{
+		if let ChunkKind::Track(_) = chunk.kind{
+			for (time,event) in smf::track_events(data,chunk){
+				if let RawEvent::Meta{kind,data} = event{
+					let kind = match kind{
+						meta::MARKER    => MarkerKind::Marker,
+						meta::CUE_POINT => MarkerKind::CuePoint,
+						_ => continue,
+					};
+					markers.push(Marker{time,kind,text: smf::decode_text(data).trim().to_string()});
+				}
+			}
+		}
+	}
+	sort(&mut markers);
+	markers
+}
+
+pub fn sort(markers: &mut [Marker]){
+	markers.sort_by_key(|marker| marker.time);
+}
+
+///The first marker after the time.
+pub fn next(markers: &[Marker],time: f64) -> Option<&Marker>{
+	markers.iter().find(|marker| (marker.time as f64) > time)
+}
+
+///The last marker before the time.
+pub fn previous(markers: &[Marker],time: f64) -> Option<&Marker>{
+	markers.iter().rev().find(|marker| (marker.time as f64) < time)
+}
+
+///The file containing the bookmarks of a MIDI file.
+pub fn bookmarks_path(path: &str) -> String{
+	format!("{}.bookmarks",path)
+}
+
+///Reads bookmarks from a file with a bookmark per line, consisting of the time in ticks and optionally a name separated by whitespace.
+///A missing file has no bookmarks. Returns the bookmarks and the numbers of the lines that could not be read, counted from 1.
+pub fn read_bookmarks(path: &str) -> io::Result<(Vec<Marker>,Vec<usize>)>{
+	let contents = match fs::read_to_string(path){
+		Ok(contents) => contents,
+		Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(),Vec::new())),
+		Err(error) => return Err(error),
+	};
+
+	let mut bookmarks = Vec::new();
+	let mut invalid_lines = Vec::new();
+	for (i,line) in contents.lines().enumerate(){
+		let line = line.trim();
+		if line.is_empty(){continue}
+		let mut parts = line.splitn(2,char::is_whitespace);
+		match parts.next().map(str::parse){
+			Some(Ok(time)) => bookmarks.push(Marker{time,kind: MarkerKind::Bookmark,text: parts.next().unwrap_or("").trim().to_string()}),
+			_ => invalid_lines.push(i+1),
+		}
+	}
+	sort(&mut bookmarks);
+	Ok((bookmarks,invalid_lines))
+}
+
+///Writes the bookmarks among the markers in the format read by `read_bookmarks`.
+///The file is removed when there are no bookmarks.
+pub fn write_bookmarks(path: &str,markers: &[Marker]) -> io::Result<()>{
+	let mut bookmarks = markers.iter().filter(|marker| marker.kind == MarkerKind::Bookmark).peekable();
+	if bookmarks.peek().is_none(){
+		return match fs::remove_file(path){
+			Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+			result => result,
+		};
+	}
+
+	let mut file = io::BufWriter::new(fs::File::create(path)?);
+	for bookmark in bookmarks{
+		writeln!(file,"{} {}",bookmark.time,bookmark.text)?;
+	}
+	file.flush()
+}
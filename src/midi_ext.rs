@@ -3,6 +3,9 @@ use midi::Note;
 
 use filtered_scan_iter::*;
 
+///Channel 10, which is used for percussion in General MIDI. It is the default of the drum channels, whose tones have no pitch.
+pub const PERCUSSION_CHANNEL: u8 = 9;
+
 #[derive(Copy,Clone,Debug,PartialEq)]
 pub struct Tone{
 	pub note              : Note,
@@ -14,21 +17,43 @@ pub struct Tone{
 	pub instrument_program: u8,
 	pub instrument_bank   : u16,
 }
+
+///The order of tones in a track: By start time, then by pitch.
+///The rendering relies on the tones of a track being in this order.
+pub fn tone_order(tone: &Tone) -> (u32,u8){
+	(tone.start_time , Into::<u8>::into(tone.note))
+}
+
+pub fn sort_tones(tones: &mut [Tone]){
+	tones.sort_by_key(tone_order);
+}
+
+///A note on with velocity 0 as the note off that it means. Many files end notes that way, with running status.
+///The release velocity is the default one, since the event has none.
+pub fn note_off_by_velocity(event: midi::MidiEventType) -> midi::MidiEventType{
+	match event{
+		midi::MidiEventType::NoteOn(note,0) => midi::MidiEventType::NoteOff(note,64),
+		event => event,
+	}
+}
+
+///The tones of a track, ordered by `tone_order`.
+///A note off ends the earliest note on of the same note on the same channel. Later note ons of a note that is already on are ignored.
 pub fn midi_track_to_tones(midi_track: &midi::Track) -> Vec<Tone>{
-	use core::u8;
 	use midi::{MidiEvent,MidiEventType};
 
-	midi_track.events
+	//The tones are emitted when they end, so they are sorted afterwards.
+	let mut tones: Vec<Tone> = midi_track.events
 		.iter()
 		.filtered_scan(
-			(0 , [None; (u8::MAX as usize)-(u8::MIN as usize)].to_vec() , 0,0), //TODO: Is this conversion inefficient? The allocation certainly should be.
+			(0 , vec![None; 16*NOTES] , 0,0),
 			|&mut (ref mut time,ref mut notes_on,ref mut program,ref mut bank) , &midi::Event{delta_time,ref event,..}|{
 				*time+= delta_time;
 
 				if let &midi::EventType::Midi(MidiEvent{event: midi_event_type,channel,..}) = event{
-					match midi_event_type{
+					match note_off_by_velocity(midi_event_type){
 						MidiEventType::NoteOn(note,atk_vel) => {
-							let note_on = &mut notes_on[Into::<u8>::into(note) as usize];
+							let note_on = &mut notes_on[note_index(channel,note)];
 
 							if note_on.is_none(){
 								*note_on = Some((*time,atk_vel));
@@ -37,7 +62,7 @@ pub fn midi_track_to_tones(midi_track: &midi::Track) -> Vec<Tone>{
 							None
 						},
 						MidiEventType::NoteOff(note,rel_vel) => {
-							let note_on = &mut notes_on[Into::<u8>::into(note) as usize];
+							let note_on = &mut notes_on[note_index(channel,note)];
 
 							if let &mut Some((start_time,atk_vel)) = note_on{
 								*note_on = None;
@@ -74,7 +99,14 @@ pub fn midi_track_to_tones(midi_track: &midi::Track) -> Vec<Tone>{
 				}
 			}// as fn(&mut (u32,Vec<Option<(u32,u8)>>),&midi::Event) -> Option<_>
 		)
-		.collect()
+		.collect();
+	sort_tones(&mut tones);
+	tones
+}
+
+///The index of a note on a channel in a table of all notes on all channels.
+pub fn note_index(channel: u8,note: Note) -> usize{
+	(channel & 0x0F) as usize * NOTES + (Into::<u8>::into(note) & 0x7F) as usize
 }
 
 pub fn midi_duration(midi_data: &midi::SimpleMidiFile) -> u32{
@@ -91,6 +123,11 @@ pub fn midi_duration(midi_data: &midi::SimpleMidiFile) -> u32{
 
 pub const NOTES: usize = 128;
 
+///Values above 127 are masked to the 7 bits used in MIDI.
+pub fn note_from_u8(note: u8) -> Note{
+	Note::from(note & 0x7F)
+}
+
 pub fn note_name(note: Note) -> &'static str{match note{
 	Note::C1n => "C₋₁",
 	Note::Cs1n=> "C♯₋₁",
@@ -224,6 +261,7 @@ pub fn note_name(note: Note) -> &'static str{match note{
 
 //TODO: https://immusic.co/gm_gm2_overview/
 
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
 #[repr(u8)]
 pub enum GeneralMidiLvl1PatchFamily{
 	Piano,
@@ -284,6 +322,7 @@ impl GeneralMidiLvl1PatchFamily{
 	}}
 }
 
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord)]
 #[repr(u8)]
 pub enum GeneralMidiLvl1Patch{
 	AcousticGrandPiano  = 1  ,
@@ -416,6 +455,12 @@ pub enum GeneralMidiLvl1Patch{
 	Gunshot             = 128,
 }
 impl GeneralMidiLvl1Patch{
+	///The patch of a program number from 0 to 127 (Program Change events count from 0 while the patches are numbered from 1).
+	pub fn from_program(program: u8) -> Self{
+		//Every value from 1 to 128 is a variant.
+		unsafe{::core::mem::transmute((program & 0x7F) + 1)}
+	}
+
 	pub fn name(self) -> &'static str{match self{
 		Self::AcousticGrandPiano  => "Acoustic Grand Piano",
 		Self::BrightAcousticPiano => "Bright Acoustic Piano",
@@ -548,7 +593,8 @@ impl GeneralMidiLvl1Patch{
 	}}
 }
 
-
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord)]
+#[repr(u8)]
 pub enum GeneralMidiLvl1PercussionNote{
 	AcousticBassDrum   = 35,
 	BassDrum1          = 36,
@@ -600,6 +646,17 @@ pub enum GeneralMidiLvl1PercussionNote{
 }
 
 impl GeneralMidiLvl1PercussionNote{
+	///The percussion sound of a note on the percussion channel, if it has one.
+	pub fn from_note(note: u8) -> Option<Self>{
+		//Every value from 35 to 81 is a variant.
+		if note >= 35 && note <= 81{Some(unsafe{::core::mem::transmute(note)})}else{None}
+	}
+
+	///The name of the percussion sound of a note, or a name with its number when General MIDI does not define one.
+	pub fn name_of(note: u8) -> String{
+		Self::from_note(note).map_or_else(|| format!("Percussion {}",note),|sound| sound.name().to_string())
+	}
+
 	pub fn name(self) -> &'static str{match self{
 	    Self::AcousticBassDrum   => "Acoustic Bass Drum",
 	    Self::BassDrum1          => "Bass Drum 1",
@@ -0,0 +1,152 @@
+use core::cmp;
+use std::io::{self,Write};
+
+use markers::MarkerKind;
+use midi_ext::*;
+use smf::meta;
+use song::Song;
+
+///Writes the song as a Standard MIDI File of format 1 (multiple simultaneous tracks).
+///Each track of the song becomes one track chunk. Program and bank changes are inserted before a tone whenever the instrument of its channel differs from the previous tone.
+///The track names are kept, and the tempo changes, time and key signatures, markers and cue points are written into the first track.
+///The other events of each track, e.g. controllers and lyrics, are written back as they were loaded.
+pub fn write_smf<W: Write>(song: &Song,out: &mut W) -> io::Result<()>{
+	let no_events = Vec::new();
+	let mut tracks: Vec<Vec<u8>> = song.tracks.iter().enumerate().map(|(track,tones)|{
+		let mut events = if track == 0{meta_events(song)}else{Vec::new()};
+		if let Some(name) = song.track_name(track){
+			events.insert(0,(0,meta_event(meta::TRACK_NAME,name.as_bytes())));
+		}
+		track_chunk_data(tones,events,song.other_events.get(track).unwrap_or(&no_events))
+	}).collect();
+	if tracks.is_empty(){
+		tracks.push(track_chunk_data(&[],meta_events(song),&no_events));
+	}
+
+	//Header chunk
+	out.write_all(b"MThd")?;
+	write_u32(out,6)?;
+	write_u16(out,1)?;
+	write_u16(out,cmp::min(tracks.len(),0xFFFF) as u16)?;
+	write_u16(out,song.division & 0x7FFF)?;
+
+	//Track chunks
+	for data in tracks.iter().take(0xFFFF){
+		out.write_all(b"MTrk")?;
+		write_u32(out,data.len() as u32)?;
+		out.write_all(data)?;
+	}
+
+	Ok(())
+}
+
+///Order of events occurring at the same time.
+///Tones ending at the same time as another begins are released first so that they do not cancel each other.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord)]
+enum EventOrder{
+	Meta,
+	NoteOff,
+	///Events kept from the loaded file.
+	Other,
+	Instrument,
+	NoteOn,
+	ZeroLengthNoteOff,
+}
+
+///The meta events of the song that are not part of a track, as (time,bytes).
+fn meta_events(song: &Song) -> Vec<(u32,Vec<u8>)>{
+	let mut events = Vec::new();
+	for change in &song.tempo.tempos{
+		events.push((change.time,meta_event(meta::SET_TEMPO,&[(change.tempo >> 16) as u8 , (change.tempo >> 8) as u8 , change.tempo as u8])));
+	}
+	for signature in &song.tempo.signatures{
+		let exponent = (signature.denominator.max(1) as u32).trailing_zeros() as u8;
+		events.push((signature.time,meta_event(meta::TIME_SIGNATURE,&[signature.numerator , exponent , 24 , 8]))); //A metronome click every quarter note, and 8 thirty-second notes per quarter note.
+	}
+	for signature in &song.key_signatures{
+		events.push((signature.time,meta_event(meta::KEY_SIGNATURE,&[signature.sharps as u8 , signature.minor as u8])));
+	}
+	for marker in &song.markers{
+		let kind = match marker.kind{
+			MarkerKind::Marker   => meta::MARKER,
+			MarkerKind::CuePoint => meta::CUE_POINT,
+			MarkerKind::Bookmark => continue, //Saved separately
+		};
+		events.push((marker.time,meta_event(kind,marker.text.as_bytes())));
+	}
+	events
+}
+
+fn meta_event(kind: u8,data: &[u8]) -> Vec<u8>{
+	let mut bytes = vec![0xFF , kind];
+	write_vlq(&mut bytes,data.len() as u32);
+	bytes.extend_from_slice(data);
+	bytes
+}
+
+fn track_chunk_data(tones: &[Tone],meta_events: Vec<(u32,Vec<u8>)>,other_events: &[(u32,Vec<u8>)]) -> Vec<u8>{
+	let mut order: Vec<&Tone> = tones.iter().collect();
+	order.sort_by_key(|tone| tone.start_time);
+
+	//Collect all events as (time,order,bytes).
+	let mut events: Vec<(u32,EventOrder,Vec<u8>)> = Vec::with_capacity(tones.len()*2 + meta_events.len() + other_events.len());
+	events.extend(meta_events.into_iter().map(|(time,bytes)| (time,EventOrder::Meta,bytes)));
+	events.extend(other_events.iter().map(|&(time,ref bytes)| (time,EventOrder::Other,bytes.clone())));
+	let mut instruments: [Option<(u8,u16)>; 16] = [None; 16];
+	for tone in order{
+		let channel = tone.channel & 0x0F;
+		let note = Into::<u8>::into(tone.note) & 0x7F;
+
+		if instruments[channel as usize] != Some((tone.instrument_program,tone.instrument_bank)){
+			instruments[channel as usize] = Some((tone.instrument_program,tone.instrument_bank));
+			events.push((tone.start_time,EventOrder::Instrument,vec![0xB0 | channel , 0  , ((tone.instrument_bank >> 8) as u8) & 0x7F])); //Bank Select (Most significant byte)
+			events.push((tone.start_time,EventOrder::Instrument,vec![0xB0 | channel , 32 , (tone.instrument_bank as u8) & 0x7F]));        //Bank Select (Least significant byte)
+			events.push((tone.start_time,EventOrder::Instrument,vec![0xC0 | channel , tone.instrument_program & 0x7F]));
+		}
+
+		events.push((tone.start_time,EventOrder::NoteOn,vec![0x90 | channel , note , tone.atk_vel & 0x7F]));
+		events.push((
+			cmp::max(tone.start_time,tone.end_time),
+			if tone.end_time <= tone.start_time{EventOrder::ZeroLengthNoteOff}else{EventOrder::NoteOff},
+			vec![0x80 | channel , note , tone.rel_vel & 0x7F]
+		));
+	}
+	events.sort_by_key(|&(time,order,_)| (time,order)); //Stable, so the bank selects are kept before the program change.
+
+	//Serialize
+	let mut data = Vec::with_capacity(events.len()*4 + 4);
+	let mut time = 0;
+	for (event_time,_,bytes) in events{
+		write_vlq(&mut data,event_time - time);
+		time = event_time;
+		data.extend_from_slice(&bytes);
+	}
+
+	//End of track
+	data.extend_from_slice(&[0x00 , 0xFF , 0x2F , 0x00]);
+	data
+}
+
+///Writes a variable-length quantity as used for delta times in MIDI files.
+pub fn write_vlq(out: &mut Vec<u8>,value: u32){
+	let mut bytes = [0u8; 5];
+	let mut len = 0;
+	let mut value = value;
+	loop{
+		bytes[len] = (value & 0x7F) as u8;
+		len+= 1;
+		value>>= 7;
+		if value == 0{break}
+	}
+	for i in (0..len).rev(){
+		out.push(bytes[i] | if i > 0{0x80}else{0x00});
+	}
+}
+
+fn write_u16<W: Write>(out: &mut W,value: u16) -> io::Result<()>{
+	out.write_all(&[(value >> 8) as u8 , value as u8])
+}
+
+fn write_u32<W: Write>(out: &mut W,value: u32) -> io::Result<()>{
+	out.write_all(&[(value >> 24) as u8 , (value >> 16) as u8 , (value >> 8) as u8 , value as u8])
+}
@@ -0,0 +1,188 @@
+//Writing of scores as MusicXML, for opening the notes in notation programs.
+
+use std::collections::BTreeSet;
+use std::io::{self,Write};
+
+use midi_ext::*;
+use notation::{Clef,Event,Measure,Part,Score,STEP_NAMES};
+use song::Song;
+use svg::escape;
+use tempo;
+
+///Writes the score as a partwise MusicXML 3.1 document. The tempo changes of the song are written into the first part.
+pub fn write_musicxml<W: Write>(song: &Song,score: &Score,out: &mut W) -> io::Result<()>{
+	writeln!(out,"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>")?;
+	writeln!(out,"<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 3.1 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">")?;
+	writeln!(out,"<score-partwise version=\"3.1\">")?;
+	writeln!(out,"\t<identification><encoding><software>midi-notes-display</software></encoding></identification>")?;
+
+	writeln!(out,"\t<part-list>")?;
+	for (i,part) in score.parts.iter().enumerate(){
+		writeln!(out,"\t\t<score-part id=\"P{}\">",i+1)?;
+		writeln!(out,"\t\t\t<part-name>{}</part-name>",escape(&part.name))?;
+		if part.percussion{
+			let notes = percussion_notes(part);
+			for &note in &notes{
+				writeln!(out,"\t\t\t<score-instrument id=\"P{}-I{}\"><instrument-name>{}</instrument-name></score-instrument>",i+1,note,escape(&GeneralMidiLvl1PercussionNote::name_of(note)))?;
+			}
+			for &note in &notes{
+				//MIDI numbers in MusicXML count from 1
+				writeln!(out,"\t\t\t<midi-instrument id=\"P{}-I{}\"><midi-channel>{}</midi-channel><midi-unpitched>{}</midi-unpitched></midi-instrument>",i+1,note,part.channel+1,note as u32 + 1)?;
+			}
+		}else{
+			writeln!(out,"\t\t\t<score-instrument id=\"P{}-I1\"><instrument-name>{}</instrument-name></score-instrument>",i+1,GeneralMidiLvl1Patch::from_program(part.program).name())?;
+			writeln!(out,"\t\t\t<midi-instrument id=\"P{}-I1\"><midi-channel>{}</midi-channel><midi-program>{}</midi-program></midi-instrument>",i+1,part.channel+1,part.program as u32 + 1)?;
+		}
+		writeln!(out,"\t\t</score-part>")?;
+	}
+	writeln!(out,"\t</part-list>")?;
+
+	for (i,part) in score.parts.iter().enumerate(){
+		writeln!(out,"\t<part id=\"P{}\">",i+1)?;
+		let mut previous: Option<&Measure> = None;
+		for (m,(measure,voices)) in score.measures.iter().zip(&part.voices).enumerate(){
+			writeln!(out,"\t\t<measure number=\"{}\">",m+1)?;
+			write_attributes(out,score,part,measure,previous)?;
+			if i == 0{
+				for change in score.tempos(song,m){
+					let bpm = tempo::bpm(change.tempo);
+					let offset = score.units(change.time).saturating_sub(measure.start);
+					writeln!(out,"\t\t\t<direction placement=\"above\">")?;
+					writeln!(out,"\t\t\t\t<direction-type><metronome><beat-unit>quarter</beat-unit><per-minute>{}</per-minute></metronome></direction-type>",bpm.round())?;
+					if offset > 0{
+						writeln!(out,"\t\t\t\t<offset>{}</offset>",offset)?;
+					}
+					writeln!(out,"\t\t\t\t<sound tempo=\"{:.2}\"/>",bpm)?;
+					writeln!(out,"\t\t\t</direction>")?;
+				}
+			}
+
+			//The voices after the first go back to the start of the measure
+			for (v,events) in voices.iter().enumerate().filter(|&(_,events)| !events.is_empty()){
+				if v > 0{
+					writeln!(out,"\t\t\t<backup><duration>{}</duration></backup>",measure.length)?;
+				}
+				for event in events{
+					write_event(out,i+1,part,v+1,event)?;
+				}
+			}
+			writeln!(out,"\t\t</measure>")?;
+			previous = Some(measure);
+		}
+		writeln!(out,"\t</part>")?;
+	}
+	writeln!(out,"</score-partwise>")
+}
+
+///Writes the divisions and clef of the first measure, and the key and time signatures where they change.
+fn write_attributes<W: Write>(out: &mut W,score: &Score,part: &Part,measure: &Measure,previous: Option<&Measure>) -> io::Result<()>{
+	let mut attributes = Vec::new();
+	if previous.is_none(){
+		attributes.push(format!("<divisions>{}</divisions>",score.grid / 4));
+	}
+	if !part.percussion && previous.map_or(true,|previous| (previous.key.sharps,previous.key.minor) != (measure.key.sharps,measure.key.minor)){
+		attributes.push(format!("<key><fifths>{}</fifths><mode>{}</mode></key>",measure.key.sharps,if measure.key.minor{"minor"}else{"major"}));
+	}
+	if previous.map_or(true,|previous| (previous.signature.numerator,previous.signature.denominator) != (measure.signature.numerator,measure.signature.denominator)){
+		attributes.push(format!("<time><beats>{}</beats><beat-type>{}</beat-type></time>",measure.signature.numerator,measure.signature.denominator));
+	}
+	if previous.is_none(){
+		attributes.push(match part.clef{
+			Clef::Treble     => "<clef><sign>G</sign><line>2</line></clef>".to_string(),
+			Clef::Bass       => "<clef><sign>F</sign><line>4</line></clef>".to_string(),
+			Clef::Percussion => "<clef><sign>percussion</sign></clef>".to_string(),
+		});
+	}
+
+	if attributes.is_empty(){return Ok(())}
+	writeln!(out,"\t\t\t<attributes>{}</attributes>",attributes.concat())
+}
+
+///Writes a rest, or a note for every pitch of a chord.
+fn write_event<W: Write>(out: &mut W,id: usize,part: &Part,voice: usize,event: &Event) -> io::Result<()>{
+	let mut note_type = format!("<voice>{}</voice><type>{}</type>",voice,type_name(event.duration.value));
+	for _ in 0..event.duration.dots{
+		note_type.push_str("<dot/>");
+	}
+
+	if event.pitches.is_empty(){
+		return writeln!(out,"\t\t\t<note><rest/><duration>{}</duration>{}</note>",event.duration.units,note_type);
+	}
+
+	for (i,pitch) in event.pitches.iter().enumerate(){
+		let spelling = pitch.spelling;
+		let mut note = String::from("<note>");
+		if i > 0{
+			note.push_str("<chord/>");
+		}
+		if part.percussion{
+			note.push_str(&format!("<unpitched><display-step>{}</display-step><display-octave>{}</display-octave></unpitched>",STEP_NAMES[spelling.step as usize],spelling.octave));
+		}else if spelling.alter != 0{
+			note.push_str(&format!("<pitch><step>{}</step><alter>{}</alter><octave>{}</octave></pitch>",STEP_NAMES[spelling.step as usize],spelling.alter,spelling.octave));
+		}else{
+			note.push_str(&format!("<pitch><step>{}</step><octave>{}</octave></pitch>",STEP_NAMES[spelling.step as usize],spelling.octave));
+		}
+		note.push_str(&format!("<duration>{}</duration>",event.duration.units));
+		if event.tied_from{
+			note.push_str("<tie type=\"stop\"/>");
+		}
+		if event.tied_to{
+			note.push_str("<tie type=\"start\"/>");
+		}
+		if part.percussion{
+			note.push_str(&format!("<instrument id=\"P{}-I{}\"/>",id,pitch.note));
+		}
+		note.push_str(&note_type);
+		if pitch.accidental{
+			note.push_str(match spelling.alter{
+				-1 => "<accidental>flat</accidental>",
+				 1 => "<accidental>sharp</accidental>",
+				 _ => "<accidental>natural</accidental>",
+			});
+		}
+		if part.percussion && is_cymbal(pitch.note){
+			note.push_str("<notehead>x</notehead>");
+		}
+		if event.tied_from || event.tied_to{
+			note.push_str("<notations>");
+			if event.tied_from{
+				note.push_str("<tied type=\"stop\"/>");
+			}
+			if event.tied_to{
+				note.push_str("<tied type=\"start\"/>");
+			}
+			note.push_str("</notations>");
+		}
+		note.push_str("</note>");
+		writeln!(out,"\t\t\t{}",note)?;
+	}
+	Ok(())
+}
+
+///The percussion notes used in the part.
+fn percussion_notes(part: &Part) -> BTreeSet<u8>{
+	part.voices.iter()
+		.flat_map(|voices| voices.iter())
+		.flat_map(|events| events.iter())
+		.flat_map(|event| event.pitches.iter().map(|pitch| pitch.note))
+		.collect()
+}
+
+///Hi-hats and cymbals, which are written with crosses as note heads.
+fn is_cymbal(note: u8) -> bool{
+	match note{
+		42 | 44 | 46 | 49 | 51 | 52 | 53 | 55 | 57 | 59 => true,
+		_ => false,
+	}
+}
+
+fn type_name(value: u32) -> &'static str{match value{
+	1  => "whole",
+	2  => "half",
+	4  => "quarter",
+	8  => "eighth",
+	16 => "16th",
+	32 => "32nd",
+	64 => "64th",
+	_  => "128th",
+}}
@@ -0,0 +1,438 @@
+//The tones of a song as sheet music: quantized into measures by the time signatures, split into voices and spelled according to the key signatures.
+//Tuplets are not recognized, so every time is moved to the nearest line of a straight grid.
+
+use core::cmp::Reverse;
+use std::collections::HashMap;
+
+use key::KeySignature;
+use midi_ext::*;
+use song::Song;
+use tempo::{TempoChange,TimeSignature};
+
+///The grid that times are quantized to, as the denominator of a whole note.
+pub const DEFAULT_GRID: u32 = 16;
+
+///The letters of the steps, from C.
+pub const STEP_NAMES: [char; 7] = ['C','D','E','F','G','A','B'];
+///The pitch classes of the steps without alteration.
+const STEP_PITCHES: [u8; 7] = [0,2,4,5,7,9,11];
+///The steps that the sharps of a key signature raise, in order. The flats lower them in the reverse order.
+const SHARP_ORDER: [u8; 7] = [3,0,4,1,5,2,6];
+
+///The lowest note on the upper staff of a grand staff.
+pub const MIDDLE_C: u8 = 60;
+
+const C_MAJOR: KeySignature = KeySignature{time: 0,sharps: 0,minor: false};
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Spelling{
+	///The letter of the note from 0 for C to 6 for B.
+	pub step  : u8,
+	///Semitones that the letter is raised by, from -1 for a flat to 1 for a sharp.
+	pub alter : i8,
+	///The octave in scientific pitch notation, where middle C is C4.
+	pub octave: i8,
+}
+
+impl Spelling{
+	///Spells the note preferring the letters of the key signature, then natural notes, then sharps in sharp keys and flats in flat keys.
+	pub fn of(note: u8,key: KeySignature) -> Self{
+		let (step,alter) = (0..7u8)
+			.flat_map(|step| (-1..=1i8).map(move |alter| (step,alter)))
+			.filter(|&(step,alter)| (STEP_PITCHES[step as usize] as i8 + alter + 12) % 12 == (note % 12) as i8)
+			.min_by_key(|&(step,alter)| (alter != key_alter(key,step) , alter != 0 , if key.sharps < 0{alter > 0}else{alter < 0}))
+			.unwrap();
+		Spelling{step,alter,octave: ((note as i16 - alter as i16 - STEP_PITCHES[step as usize] as i16) / 12 - 1) as i8}
+	}
+
+	///The position on the staff in steps, counting letters from C₋₁.
+	pub fn position(&self) -> i32{
+		(self.octave as i32 + 1) * 7 + self.step as i32
+	}
+}
+
+///The alteration of the letter by the key signature.
+pub fn key_alter(key: KeySignature,step: u8) -> i8{
+	let index = SHARP_ORDER.iter().position(|&sharp| sharp == step).unwrap_or(0) as i8;
+	if index < key.sharps{1}else if 6 - index < -key.sharps{-1}else{0}
+}
+
+///The tonic of the key of the key signature, spelled in the key.
+pub fn tonic(key: KeySignature) -> Spelling{
+	Spelling::of(key.key().tonic + 60,key)
+}
+
+///Where the sounds on the percussion channel are written on a five line staff, following the common drum set notation.
+pub fn percussion_position(note: u8) -> Spelling{
+	let (step,octave) = match note{
+		35 | 36           => (3 , 4), //Bass drums on F4
+		37..=40           => (0 , 5), //Snares and claps on C5
+		41 | 43           => (5 , 4), //Floor toms on A4
+		45 | 47           => (1 , 5), //Low toms on D5
+		48 | 50           => (2 , 5), //High toms on E5
+		42 | 46           => (4 , 5), //Hi-hats on G5
+		44                => (1 , 4), //Pedal hi-hat on D4
+		49 | 52 | 55 | 57 => (5 , 5), //Crash cymbals on A5
+		51 | 53 | 59      => (3 , 5), //Ride cymbals on F5
+		_                 => (2 , 4), //Other percussion on E4
+	};
+	Spelling{step,alter: 0,octave}
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Clef{
+	Treble,
+	Bass,
+	Percussion,
+}
+
+impl Clef{
+	///The clef that has the most notes on the staff or at most three ledger lines from it.
+	///When both have as many, the bass clef is chosen if the median note is below middle C.
+	pub fn for_notes(notes: &[u8]) -> Self{
+		let mut notes = notes.to_vec();
+		notes.sort();
+		let within = |(lowest,highest): (u8,u8)| notes.iter().filter(|&&note| note >= lowest && note <= highest).count();
+		let (treble,bass) = (within(TREBLE_RANGE) , within(BASS_RANGE));
+		match notes.get(notes.len()/2){
+			_ if treble != bass => if treble > bass{Clef::Treble}else{Clef::Bass},
+			Some(&median) if median < 60 => Clef::Bass,
+			_ => Clef::Treble,
+		}
+	}
+}
+
+///The notes from three ledger lines below to three above the staff with a treble clef, from F3 to E6.
+const TREBLE_RANGE: (u8,u8) = (53 , 88);
+///The notes from three ledger lines below to three above the staff with a bass clef, from A1 to G4.
+const BASS_RANGE: (u8,u8) = (33 , 67);
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Duration{
+	///Length in units of the grid.
+	pub units: u32,
+	///The note value as the denominator of a whole note, e.g. 4 for a quarter note.
+	pub value: u32,
+	pub dots : u8,
+}
+
+///Splits a length in units of the grid into note values that can be tied together, the longest first.
+pub fn durations(length: u32,grid: u32) -> Vec<Duration>{
+	//Every power of two from a whole note down to the grid, also dotted
+	let mut values = Vec::new();
+	let mut base = grid;
+	while base >= 1{
+		if base >= 2{
+			values.push(Duration{units: base*3/2,value: grid/base,dots: 1});
+		}
+		values.push(Duration{units: base,value: grid/base,dots: 0});
+		base/= 2;
+	}
+
+	let mut remaining = length;
+	let mut durations = Vec::new();
+	while let Some(&duration) = values.iter().find(|duration| duration.units <= remaining){
+		durations.push(duration);
+		remaining-= duration.units;
+	}
+	durations
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Pitch{
+	pub note      : u8,
+	///The spelling of the note, or where it is written for percussion.
+	pub spelling  : Spelling,
+	///Whether an accidental is written, because the key signature or an earlier note in the measure alters the letter differently.
+	pub accidental: bool,
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Event{
+	///Start in units of the grid from the start of the measure.
+	pub offset   : u32,
+	pub duration : Duration,
+	///The notes of a chord from the lowest, or none for a rest.
+	pub pitches  : Vec<Pitch>,
+	///Whether the notes are tied to the ones of the previous event.
+	pub tied_from: bool,
+	///Whether the notes are tied to the ones of the next event.
+	pub tied_to  : bool,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Measure{
+	///Start in units of the grid.
+	pub start    : u32,
+	///Length in units of the grid. Shorter than a bar when a time signature change interrupts it.
+	pub length   : u32,
+	///Start in ticks.
+	pub time     : u32,
+	pub signature: TimeSignature,
+	pub key      : KeySignature,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Part{
+	pub track     : usize,
+	pub name      : String,
+	///Whether the part has the tones of the percussion channel, whose notes are drum sounds instead of pitches.
+	pub percussion: bool,
+	///The program of the first tone.
+	pub program   : u8,
+	///The channel of the first tone.
+	pub channel   : u8,
+	pub clef      : Clef,
+	///The events indexed by measure and voice, filling the measures. Voices other than the first have no events in measures where they are silent.
+	pub voices    : Vec<Vec<Vec<Event>>>,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Score{
+	///The grid as the denominator of a whole note.
+	pub grid    : u32,
+	///Ticks per unit of the grid.
+	pub unit    : f64,
+	pub measures: Vec<Measure>,
+	///A part for the pitched tones and one for the percussion of every track that has them.
+	pub parts   : Vec<Part>,
+}
+
+///Tones with the same start and end in a voice.
+struct Chord{
+	start: u32,
+	end  : u32,
+	notes: Vec<u8>,
+}
+
+impl Score{
+	///The score of the song quantized to the grid, which is a power of two from 4. The tones on the drum channels are in percussion parts.
+	pub fn of(song: &Song,drums: &[u8],grid: u32) -> Self{
+		Score::of_range(song,drums,grid,(0 , ::core::u32::MAX))
+	}
+
+	///The score of the measures that overlap the range of ticks.
+	fn of_range(song: &Song,drums: &[u8],grid: u32,range: (u32,u32)) -> Self{
+		let grid = grid.max(4).next_power_of_two();
+		let unit = song.division as f64 * 4.0 / grid as f64;
+		let units = |time: u32| (time as f64 / unit).round() as u32;
+		let measures = measures(song,range,&units);
+
+		let mut parts = Vec::new();
+		for (track,tones) in song.tracks.iter().enumerate(){
+			for &percussion in &[false,true]{
+				let tones: Vec<&Tone> = tones.iter().filter(|tone| drums.contains(&tone.channel) == percussion).collect();
+				let first = match tones.first(){
+					Some(&first) => *first,
+					None => continue,
+				};
+				let chords = if percussion{hits(&tones,&units,grid/4,&measures)}else{chords(&tones,&units)};
+				let voices = voices(chords);
+				let notes: Vec<u8> = tones.iter().map(|tone| tone.note.into()).collect();
+
+				parts.push(Part{
+					track,
+					name      : match song.track_name(track){
+						Some(name) if !name.is_empty() => name.to_string(),
+						_ if percussion => "Drums".to_string(),
+						_ => GeneralMidiLvl1Patch::from_program(first.instrument_program).name().to_string(),
+					},
+					percussion,
+					program   : first.instrument_program,
+					channel   : first.channel,
+					clef      : if percussion{Clef::Percussion}else{Clef::for_notes(&notes)},
+					voices    : measures.iter().map(|measure|{
+						let mut events: Vec<Vec<Event>> = voices.iter().enumerate().map(|(i,voice)| measure_events(voice,measure,grid,percussion,i == 0)).collect();
+						if !percussion{
+							mark_accidentals(&mut events,measure.key);
+						}
+						events
+					}).collect(),
+				});
+			}
+		}
+
+		Score{grid,unit,measures,parts}
+	}
+
+	///The pitched tones that overlap the range of ticks on a grand staff, using the tones for which `accept`, given the track index and the tone, returns true.
+	///The notes from middle C are in a part with the treble clef for the upper staff, and the lower notes are in a part with the bass clef for the lower staff.
+	///The parts have the tracks 0 and 1, and are left out when they have no notes. Only the measures that overlap the range are in the score.
+	pub fn grand_staff<F: Fn(usize,&Tone) -> bool>(song: &Song,accept: F,drums: &[u8],range: (u32,u32),grid: u32) -> Self{
+		let mut staves = Song::empty();
+		staves.division       = song.division;
+		staves.duration       = song.duration;
+		staves.tempo          = song.tempo.clone();
+		staves.key_signatures = song.key_signatures.clone();
+		staves.tracks         = vec![Vec::new() , Vec::new()];
+		for (track,tones) in song.tracks.iter().enumerate(){
+			for tone in tones.iter().filter(|tone| !drums.contains(&tone.channel) && tone.end_time >= range.0 && tone.start_time <= range.1 && accept(track,tone)){
+				staves.tracks[if Into::<u8>::into(tone.note) >= MIDDLE_C{0}else{1}].push(*tone);
+			}
+		}
+		for tones in &mut staves.tracks{
+			sort_tones(tones);
+		}
+
+		let mut score = Score::of_range(&staves,drums,grid,range);
+		for part in &mut score.parts{
+			part.clef = if part.track == 0{Clef::Treble}else{Clef::Bass};
+		}
+		score
+	}
+
+	///A time in ticks in units of the grid.
+	pub fn units(&self,time: u32) -> u32{
+		(time as f64 / self.unit).round() as u32
+	}
+
+	///The tempo changes of the song in the measure.
+	pub fn tempos<'s>(&self,song: &'s Song,measure: usize) -> impl Iterator<Item = &'s TempoChange> + 's{
+		let start = self.measures.get(measure).map_or(::core::u32::MAX,|measure| measure.time);
+		let end = self.measures.get(measure+1).map_or(::core::u32::MAX,|next| next.time);
+		song.tempo.tempos.iter().filter(move |change| change.time >= start && change.time < end)
+	}
+}
+
+///The measures of the song that overlap the range of ticks. A bar starts at every time signature change, and the last time signature lasts until the end of the song.
+fn measures<F: Fn(u32) -> u32>(song: &Song,range: (u32,u32),units: F) -> Vec<Measure>{
+	let initial = if song.tempo.signatures.first().map_or(false,|signature| signature.time == 0){None}else{Some(TimeSignature::DEFAULT)};
+	let signatures: Vec<TimeSignature> = initial.into_iter().chain(song.tempo.signatures.iter().cloned()).collect();
+
+	let mut measures = Vec::new();
+	for (i,signature) in signatures.iter().enumerate(){
+		let next = signatures.get(i+1).map(|next| next.time);
+		let bar = signature.bar_length(song.division).max(1);
+		let mut time = signature.time;
+		if next.map_or(false,|next| next <= range.0){continue}
+		//Skips the whole bars before the range
+		if range.0 > time{
+			time+= (range.0 - time) / bar * bar;
+		}
+		while next.map_or(time < song.duration || measures.is_empty(),|next| time < next) && (time <= range.1 || measures.is_empty()){
+			let end = next.map_or(time + bar,|next| next.min(time + bar));
+			let (start,stop) = (units(time) , units(end));
+			if stop > start{
+				let key = song.key_signatures.iter().rev().find(|key| key.time <= time).cloned().unwrap_or(C_MAJOR);
+				measures.push(Measure{start,length: stop - start,time,signature: *signature,key});
+			}
+			time = end;
+		}
+	}
+	measures
+}
+
+///The pitched tones grouped into chords by their quantized times. Every chord lasts at least one unit.
+fn chords<F: Fn(u32) -> u32>(tones: &[&Tone],units: F) -> Vec<Chord>{
+	let mut chords: Vec<Chord> = Vec::new();
+	for tone in tones{
+		let start = units(tone.start_time);
+		let end = units(tone.end_time).max(start + 1);
+		//The tones are ordered by start time
+		match chords.iter_mut().rev().take_while(|chord| chord.start == start).find(|chord| chord.end == end){
+			Some(chord) => chord.notes.push(tone.note.into()),
+			None => chords.push(Chord{start,end,notes: vec![tone.note.into()]}),
+		}
+	}
+	chords
+}
+
+///The percussion tones grouped into chords by their quantized starts. The length of a percussion tone means little, so every hit lasts until the next one, at most the length given in units and never across a barline.
+fn hits<F: Fn(u32) -> u32>(tones: &[&Tone],units: F,length: u32,measures: &[Measure]) -> Vec<Chord>{
+	let mut chords: Vec<Chord> = Vec::new();
+	for tone in tones{
+		let start = units(tone.start_time);
+		if let Some(chord) = chords.last_mut(){
+			if chord.start == start{
+				chord.notes.push(tone.note.into());
+				continue;
+			}
+		}
+		let measure = match measures.binary_search_by_key(&start,|measure| measure.start){
+			Ok(i)  => measures.get(i),
+			Err(i) => measures.get(i.saturating_sub(1)),
+		};
+		let end = measure.map_or(start + length,|measure| (start + length).min(measure.start + measure.length)).max(start + 1);
+		chords.push(Chord{start,end,notes: vec![tone.note.into()]});
+	}
+	for i in 1..chords.len(){
+		chords[i-1].end = chords[i-1].end.min(chords[i].start);
+	}
+	chords
+}
+
+///Splits the chords into voices without overlapping chords. The higher chords come first when chords start together, so the first voice tends to be the highest.
+fn voices(mut chords: Vec<Chord>) -> Vec<Vec<Chord>>{
+	for chord in &mut chords{
+		chord.notes.sort();
+		chord.notes.dedup();
+	}
+	chords.sort_by_key(|chord| (chord.start , Reverse(chord.notes.last().cloned())));
+
+	let mut voices: Vec<Vec<Chord>> = Vec::new();
+	for chord in chords{
+		match voices.iter_mut().find(|voice| voice.last().map_or(true,|last| last.end <= chord.start)){
+			Some(voice) => voice.push(chord),
+			None => voices.push(vec![chord]),
+		}
+	}
+	voices
+}
+
+///The events of a voice in the measure with rests between the chords. Chords crossing the barlines are tied.
+///Only the first voice is filled with rests when it has no chords in the measure.
+fn measure_events(voice: &[Chord],measure: &Measure,grid: u32,percussion: bool,first: bool) -> Vec<Event>{
+	let (start,end) = (measure.start , measure.start + measure.length);
+	let mut events = Vec::new();
+	let push = |events: &mut Vec<Event>,from: u32,to: u32,pitches: &[Pitch],tied_from: bool,tied_to: bool|{
+		let durations = durations(to - from,grid);
+		let mut offset = from - start;
+		for (i,&duration) in durations.iter().enumerate(){
+			let rest = pitches.is_empty();
+			events.push(Event{
+				offset,
+				duration,
+				pitches  : pitches.to_vec(),
+				tied_from: !rest && (tied_from || i > 0),
+				tied_to  : !rest && (tied_to || i+1 < durations.len()),
+			});
+			offset+= duration.units;
+		}
+	};
+
+	let mut position = start;
+	for chord in voice.iter().skip_while(|chord| chord.end <= start).take_while(|chord| chord.start < end){
+		if chord.start > position{
+			push(&mut events,position,chord.start,&[],false,false);
+		}
+		let pitches: Vec<Pitch> = chord.notes.iter().map(|&note| Pitch{
+			note,
+			spelling  : if percussion{percussion_position(note)}else{Spelling::of(note,measure.key)},
+			accidental: false,
+		}).collect();
+		let (from,to) = (chord.start.max(start) , chord.end.min(end));
+		push(&mut events,from,to,&pitches,chord.start < start,chord.end > end);
+		position = to;
+	}
+	if position < end && (first || !events.is_empty()){
+		push(&mut events,position,end,&[],false,false);
+	}
+	events
+}
+
+///Decides which notes of the measure need accidentals. An accidental lasts until the end of the measure for the same letter in the same octave, in every voice.
+fn mark_accidentals(voices: &mut [Vec<Event>],key: KeySignature){
+	let mut events: Vec<&mut Event> = voices.iter_mut().flat_map(|events| events.iter_mut()).collect();
+	events.sort_by_key(|event| event.offset);
+
+	let mut alterations: HashMap<(u8,i8),i8> = HashMap::new();
+	for event in events{
+		let tied_from = event.tied_from;
+		for pitch in &mut event.pitches{
+			let spelling = pitch.spelling;
+			let current = alterations.get(&(spelling.step,spelling.octave)).cloned().unwrap_or_else(|| key_alter(key,spelling.step));
+			pitch.accidental = spelling.alter != current && !tied_from;
+			alterations.insert((spelling.step,spelling.octave),spelling.alter);
+		}
+	}
+}
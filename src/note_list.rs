@@ -0,0 +1,677 @@
+//The tones of a song as a list of notes in CSV or JSON, for processing them with other programs and reading them back.
+//Channels are numbered from 1 like in the command line options, and times are in ticks with the times in seconds alongside.
+
+use core::fmt;
+use std::collections::BTreeMap;
+use std::io::{self,Write};
+
+use json::{self,Value};
+use key::KeySignature;
+use markers::{self,Marker,MarkerKind};
+use midi_ext::*;
+use song::Song;
+use tempo;
+
+///The columns of the CSV format and the members of a note in the JSON format.
+pub const COLUMNS: [&str; 12] = [
+	"track",
+	"channel",
+	"note",
+	"name",
+	"start",
+	"end",
+	"start_seconds",
+	"end_seconds",
+	"velocity",
+	"release_velocity",
+	"program",
+	"bank",
+];
+
+///The tones of every track, ordered by start time.
+fn notes(song: &Song) -> Vec<(usize,&Tone)>{
+	let mut notes: Vec<(usize,&Tone)> = song.tracks.iter().enumerate().flat_map(|(track,tones)| tones.iter().map(move |tone| (track,tone))).collect();
+	notes.sort_by_key(|&(track,tone)| (tone.start_time , track , Into::<u8>::into(tone.note)));
+	notes
+}
+
+///The values of the columns for a note.
+fn row(song: &Song,track: usize,tone: &Tone) -> [Value; 12]{
+	let seconds = |time: u32| song.tempo.seconds(time as f64,song.division);
+	[
+		track.into(),
+		(tone.channel + 1).into(),
+		Into::<u8>::into(tone.note).into(),
+		note_name(tone.note).into(),
+		tone.start_time.into(),
+		tone.end_time.into(),
+		seconds(tone.start_time).into(),
+		seconds(tone.end_time).into(),
+		tone.atk_vel.into(),
+		tone.rel_vel.into(),
+		tone.instrument_program.into(),
+		tone.instrument_bank.into(),
+	]
+}
+
+///Writes a header line with the column names, followed by a line for every note.
+pub fn write_csv<W: Write>(song: &Song,out: &mut W) -> io::Result<()>{
+	writeln!(out,"{}",COLUMNS.join(","))?;
+	for (track,tone) in notes(song){
+		let fields: Vec<String> = row(song,track,tone).iter().map(|value| match *value{
+			Value::String(ref text) => csv_field(text),
+			ref value => value.to_string(),
+		}).collect();
+		writeln!(out,"{}",fields.join(","))?;
+	}
+	Ok(())
+}
+
+///Quotes a field when it contains characters that separate fields or lines.
+pub fn csv_field(text: &str) -> String{
+	if text.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r'){
+		format!("\"{}\"",text.replace('"',"\"\""))
+	}else{
+		text.to_string()
+	}
+}
+
+///The notes together with the meta events and the tempo map.
+pub fn json(song: &Song) -> Value{
+	Value::object(vec![
+		("format"          , song.format.into()),
+		("division"        , song.division.into()),
+		("duration"        , song.duration.into()),
+		("tracks"          , Value::Array((0..song.tracks.len()).map(|track| Value::object(vec![
+			("index" , track.into()),
+			("name"  , song.track_name(track).into()),
+		])).collect())),
+		("tempos"          , Value::Array(song.tempo.tempos.iter().map(|change| Value::object(vec![
+			("time"   , change.time.into()),
+			("seconds", song.tempo.seconds(change.time as f64,song.division).into()),
+			("tempo"  , change.tempo.into()),
+			("bpm"    , tempo::bpm(change.tempo).into()),
+		])).collect())),
+		("time_signatures" , Value::Array(song.tempo.signatures.iter().map(|signature| Value::object(vec![
+			("time"       , signature.time.into()),
+			("numerator"  , signature.numerator.into()),
+			("denominator", signature.denominator.into()),
+		])).collect())),
+		("key_signatures"  , Value::Array(song.key_signatures.iter().map(|signature| Value::object(vec![
+			("time"  , signature.time.into()),
+			("sharps", (signature.sharps as f64).into()),
+			("minor" , signature.minor.into()),
+			("key"   , signature.key().to_string().into()),
+		])).collect())),
+		("markers"         , Value::Array(song.markers.iter().map(|marker| Value::object(vec![
+			("time", marker.time.into()),
+			("kind", marker.kind.name().into()),
+			("text", marker.text.as_str().into()),
+		])).collect())),
+		("lyrics"          , Value::Array(song.lyrics.iter().map(|lyric| Value::object(vec![
+			("time" , lyric.time.into()),
+			("track", lyric.track.into()),
+			("text" , lyric.text.as_str().into()),
+		])).collect())),
+		("notes"           , Value::Array(notes(song).into_iter().map(|(track,tone)| Value::Object(
+			COLUMNS.iter().map(|column| column.to_string()).zip(row(song,track,tone).to_vec()).collect()
+		)).collect())),
+	])
+}
+
+///The largest time in ticks that can be imported.
+const MAX_TICKS: f64 = 0xFFFF_FFFFu32 as f64;
+
+///The problems that prevented a note list from being read, each with its location.
+#[derive(Clone,Debug,PartialEq)]
+pub struct ImportError(pub Vec<String>);
+
+impl fmt::Display for ImportError{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{
+		write!(f,"Invalid note list:")?;
+		for error in &self.0{
+			write!(f,"\n  {}",error)?;
+		}
+		Ok(())
+	}
+}
+
+///Reads a note list in the CSV format, which must have a header line naming its columns.
+///The columns `note`, `start` and `end` are required. The names and times in seconds are ignored because they follow from the other columns.
+///Returns the song together with warnings about what was changed to be able to write it as MIDI.
+pub fn read_csv(text: &str) -> Result<(Song,Vec<String>),ImportError>{
+	let mut records = csv_records(text).into_iter();
+	let header = match records.next(){
+		Some((_,header)) => header,
+		None => return Err(ImportError(vec!["The header line is missing".to_string()])),
+	};
+
+	let mut errors = Vec::new();
+	for column in header.iter().filter(|column| !COLUMNS.contains(&column.as_str())){
+		errors.push(format!("Line 1: Unknown column \"{}\"",column));
+	}
+	for &column in ["note","start","end"].iter().filter(|&&column| !header.iter().any(|name| name == column)){
+		errors.push(format!("Line 1: The column \"{}\" is missing",column));
+	}
+	if !errors.is_empty(){
+		return Err(ImportError(errors));
+	}
+	let mut notes = Vec::new();
+	for (line,fields) in records{
+		if fields.len() == 1 && fields[0].trim().is_empty(){continue}
+		if fields.len() != header.len(){
+			errors.push(format!("Line {}: Expected {} fields but found {}",line,header.len(),fields.len()));
+			continue;
+		}
+		let members: Vec<(String,Value)> = header.iter().cloned().zip(fields.into_iter().map(Value::String)).collect();
+		match note(&members){
+			Ok(note) => notes.push(note),
+			Err(message) => errors.push(format!("Line {}: {}",line,message)),
+		}
+	}
+	if !errors.is_empty(){
+		return Err(ImportError(errors));
+	}
+
+	let mut song = Song::empty();
+	let warnings = insert_notes(&mut song,notes);
+	Ok((song,warnings))
+}
+
+///Reads a note list in the JSON format. Only `notes` is required.
+///Returns the song together with warnings about what was changed or left out to be able to write it as MIDI.
+pub fn read_json(text: &str) -> Result<(Song,Vec<String>),ImportError>{
+	const MEMBERS: [&str; 10] = ["format","division","duration","tracks","tempos","time_signatures","key_signatures","markers","lyrics","notes"];
+
+	let document = json::parse(text).map_err(|message| ImportError(vec![message]))?;
+	let members = match document{
+		Value::Object(ref members) => members,
+		_ => return Err(ImportError(vec!["The document is not an object".to_string()])),
+	};
+
+	let mut errors = Vec::new();
+	let mut warnings = Vec::new();
+	let mut song = Song::empty();
+	for &(ref name,_) in members.iter().filter(|&&(ref name,_)| !MEMBERS.contains(&name.as_str())){
+		errors.push(format!("Unknown member \"{}\"",name));
+	}
+
+	//Lists of objects
+	let list = |name: &str,errors: &mut Vec<String>| -> Vec<Vec<(String,Value)>>{
+		match document.get(name){
+			None | Some(&Value::Null) => Vec::new(),
+			Some(&Value::Array(ref values)) => values.iter().enumerate().filter_map(|(i,value)| match *value{
+				Value::Object(ref members) => Some(members.clone()),
+				_ => {errors.push(format!("{}[{}]: Expected an object",name,i)); None},
+			}).collect(),
+			Some(_) => {errors.push(format!("{}: Expected an array",name)); Vec::new()},
+		}
+	};
+
+	match number(document.get("division"),1.0,0x7FFF as f64){
+		Ok(Some(division)) => song.division = division as u16,
+		Ok(None) => (),
+		Err(message) => errors.push(format!("division: {}",message)),
+	}
+	match number(document.get("duration"),0.0,MAX_TICKS){
+		Ok(Some(duration)) => song.duration = duration as u32,
+		Ok(None) => (),
+		Err(message) => errors.push(format!("duration: {}",message)),
+	}
+
+	for (i,members) in list("tracks",&mut errors).into_iter().enumerate(){
+		let result = check_members(&members,&["index","name"]).and_then(|()| Ok((
+			required(number(field(&members,"index"),0.0,0xFFFE as f64),"index")? as usize,
+			match field(&members,"name"){
+				None | Some(&Value::Null) => None,
+				Some(&Value::String(ref name)) => Some(name.clone()),
+				Some(_) => return Err("name: Expected a string".to_string()),
+			},
+		)));
+		match result{
+			Ok((index,name)) => {
+				if song.track_names.len() <= index{
+					song.track_names.resize(index+1,None);
+				}
+				song.track_names[index] = name;
+			},
+			Err(message) => errors.push(format!("tracks[{}]: {}",i,message)),
+		}
+	}
+
+	for (i,members) in list("tempos",&mut errors).into_iter().enumerate(){
+		let result = check_members(&members,&["time","seconds","tempo","bpm"]).and_then(|()|{
+			let time = required(number(field(&members,"time"),0.0,MAX_TICKS),"time")? as u32;
+			let tempo = match (number(field(&members,"tempo"),1.0,0xFF_FFFF as f64)?,number(field(&members,"bpm"),4.0,60_000_000.0)?){
+				(Some(tempo),_)  => tempo as u32,
+				(None,Some(bpm)) => tempo::tempo_from_bpm(bpm),
+				(None,None)      => return Err("Expected tempo or bpm".to_string()),
+			};
+			Ok((time,tempo))
+		});
+		match result{
+			Ok((time,tempo)) => song.tempo.set_tempo(time,tempo),
+			Err(message) => errors.push(format!("tempos[{}]: {}",i,message)),
+		}
+	}
+
+	for (i,members) in list("time_signatures",&mut errors).into_iter().enumerate(){
+		let result = check_members(&members,&["time","numerator","denominator"]).and_then(|()|{
+			let time = required(number(field(&members,"time"),0.0,MAX_TICKS),"time")? as u32;
+			let numerator = required(number(field(&members,"numerator"),1.0,255.0),"numerator")? as u8;
+			let denominator = required(number(field(&members,"denominator"),1.0,128.0),"denominator")? as u8;
+			if !denominator.is_power_of_two(){
+				return Err("denominator: Expected a power of two".to_string());
+			}
+			Ok((time,numerator,denominator))
+		});
+		match result{
+			Ok((time,numerator,denominator)) => song.tempo.set_signature(time,numerator,denominator),
+			Err(message) => errors.push(format!("time_signatures[{}]: {}",i,message)),
+		}
+	}
+
+	for (i,members) in list("key_signatures",&mut errors).into_iter().enumerate(){
+		let result = check_members(&members,&["time","sharps","minor","key"]).and_then(|()| Ok(KeySignature{
+			time  : required(number(field(&members,"time"),0.0,MAX_TICKS),"time")? as u32,
+			sharps: required(number(field(&members,"sharps"),-7.0,7.0),"sharps")? as i8,
+			minor : match field(&members,"minor"){
+				None => false,
+				Some(&Value::Bool(minor)) => minor,
+				Some(_) => return Err("minor: Expected true or false".to_string()),
+			},
+		}));
+		match result{
+			Ok(signature) => song.key_signatures.push(signature),
+			Err(message) => errors.push(format!("key_signatures[{}]: {}",i,message)),
+		}
+	}
+	song.key_signatures.sort_by_key(|signature| signature.time);
+
+	for (i,members) in list("markers",&mut errors).into_iter().enumerate(){
+		let result = check_members(&members,&["time","kind","text"]).and_then(|()| Ok(Marker{
+			time: required(number(field(&members,"time"),0.0,MAX_TICKS),"time")? as u32,
+			kind: match field(&members,"kind"){
+				None => MarkerKind::Marker,
+				Some(&Value::String(ref name)) => match MarkerKind::ALL.iter().find(|kind| kind.name() == name){
+					Some(&kind) => kind,
+					None => return Err(format!("kind: Unknown kind \"{}\"",name)),
+				},
+				Some(_) => return Err("kind: Expected a string".to_string()),
+			},
+			text: match field(&members,"text"){
+				None => String::new(),
+				Some(&Value::String(ref text)) => text.clone(),
+				Some(_) => return Err("text: Expected a string".to_string()),
+			},
+		}));
+		match result{
+			Ok(marker) => song.markers.push(marker),
+			Err(message) => errors.push(format!("markers[{}]: {}",i,message)),
+		}
+	}
+	markers::sort(&mut song.markers);
+
+	if !list("lyrics",&mut errors).is_empty(){
+		warnings.push("The lyrics were left out because they are not written to MIDI files".to_string());
+	}
+
+	if document.get("notes").is_none(){
+		errors.push("The member \"notes\" is missing".to_string());
+	}
+	let mut notes = Vec::new();
+	for (i,members) in list("notes",&mut errors).into_iter().enumerate(){
+		match note(&members){
+			Ok(note) => notes.push(note),
+			Err(message) => errors.push(format!("notes[{}]: {}",i,message)),
+		}
+	}
+
+	if !errors.is_empty(){
+		return Err(ImportError(errors));
+	}
+	warnings.extend(insert_notes(&mut song,notes));
+	Ok((song,warnings))
+}
+
+///The note described by the members of an object or the fields of a CSV line, as its track and tone.
+fn note(members: &[(String,Value)]) -> Result<(usize,Tone),String>{
+	check_members(members,&COLUMNS)?;
+	match field(members,"name"){
+		None | Some(&Value::String(_)) => (),
+		Some(_) => return Err("name: Expected a string".to_string()),
+	}
+
+	let integer = |name: &str,min: f64,max: f64,default: Option<f64>| -> Result<f64,String>{
+		match number(field(members,name),min,max).map_err(|message| format!("{}: {}",name,message))?{
+			Some(value) => Ok(value),
+			None => default.ok_or_else(|| format!("The value of {} is missing",name)),
+		}
+	};
+	let start_time = integer("start",0.0,MAX_TICKS,None)? as u32;
+	let end_time = integer("end",0.0,MAX_TICKS,None)? as u32;
+	if end_time < start_time{
+		return Err(format!("The end {} is before the start {}",end_time,start_time));
+	}
+	Ok((integer("track",0.0,0xFFFE as f64,Some(0.0))? as usize , Tone{
+		note              : note_from_u8(integer("note",0.0,127.0,None)? as u8),
+		start_time,
+		end_time,
+		atk_vel           : integer("velocity",0.0,127.0,Some(100.0))? as u8,
+		rel_vel           : integer("release_velocity",0.0,127.0,Some(64.0))? as u8,
+		channel           : integer("channel",1.0,16.0,Some(1.0))? as u8 - 1,
+		instrument_program: integer("program",0.0,127.0,Some(0.0))? as u8,
+		instrument_bank   : integer("bank",0.0,16383.0,Some(0.0))? as u16,
+	}))
+}
+
+fn field<'m>(members: &'m [(String,Value)],name: &str) -> Option<&'m Value>{
+	members.iter().find(|&&(ref member,_)| member == name).map(|&(_,ref value)| value)
+}
+
+fn check_members(members: &[(String,Value)],known: &[&str]) -> Result<(),String>{
+	match members.iter().find(|&&(ref name,_)| !known.contains(&name.as_str())){
+		Some(&(ref name,_)) => Err(format!("Unknown member \"{}\"",name)),
+		None => Ok(()),
+	}
+}
+
+fn required(value: Result<Option<f64>,String>,name: &str) -> Result<f64,String>{
+	value.map_err(|message| format!("{}: {}",name,message))?.ok_or_else(|| format!("The value of {} is missing",name))
+}
+
+///An integer in the range, given as a number or as a string of a number like in CSV. None when missing, null or empty.
+fn number(value: Option<&Value>,min: f64,max: f64) -> Result<Option<f64>,String>{
+	let number = match value{
+		None | Some(&Value::Null) => return Ok(None),
+		Some(&Value::Number(number)) => number,
+		Some(&Value::String(ref text)) if text.trim().is_empty() => return Ok(None),
+		Some(&Value::String(ref text)) => text.trim().parse().map_err(|_| format!("Expected a number but found \"{}\"",text))?,
+		Some(_) => return Err("Expected a number".to_string()),
+	};
+	if number.fract() != 0.0{
+		Err(format!("Expected an integer but found {}",number))
+	}else if number < min || number > max{
+		Err(format!("{} is outside the range from {} to {}",number,min,max))
+	}else{
+		Ok(Some(number))
+	}
+}
+
+///Adds the notes to their tracks and sorts them.
+///Notes of the same pitch on the same channel of a track cannot overlap in MIDI, so the earlier of two overlapping notes is shortened and a warning is returned.
+///A note starting at the same tick as the earlier one would be shortened to nothing, so it is dropped instead.
+fn insert_notes(song: &mut Song,notes: Vec<(usize,Tone)>) -> Vec<String>{
+	let mut warnings = Vec::new();
+	for (track,tone) in notes{
+		if song.tracks.len() <= track{
+			song.tracks.resize(track+1,Vec::new());
+		}
+		song.tracks[track].push(tone);
+	}
+	for (track,tones) in song.tracks.iter_mut().enumerate(){
+		sort_tones(tones);
+		let mut kept: Vec<Tone> = Vec::with_capacity(tones.len());
+		//The previous kept note of every channel and pitch
+		let mut previous: BTreeMap<(u8,u8),usize> = BTreeMap::new();
+		for tone in tones.drain(..){
+			let key = (tone.channel , Into::<u8>::into(tone.note));
+			if let Some(&before) = previous.get(&key){
+				if kept[before].start_time == tone.start_time{
+					warnings.push(format!(
+						"Track {}: The note {} on channel {} at tick {} starts together with another one and was dropped",
+						track,note_name(tone.note),key.0+1,tone.start_time
+					));
+					continue;
+				}
+				if kept[before].end_time > tone.start_time{
+					warnings.push(format!(
+						"Track {}: The note {} on channel {} from tick {} overlaps the one starting at tick {} and was shortened",
+						track,note_name(tone.note),key.0+1,kept[before].start_time,tone.start_time
+					));
+					kept[before].end_time = tone.start_time;
+				}
+			}
+			previous.insert(key,kept.len());
+			kept.push(tone);
+		}
+		*tones = kept;
+	}
+	song.update_duration();
+	warnings
+}
+
+///Splits CSV text into records of fields together with the line numbers where they begin.
+///Fields can be quoted to contain separators, line breaks and doubled quotes.
+fn csv_records(text: &str) -> Vec<(usize,Vec<String>)>{
+	let mut records = Vec::new();
+	let mut fields = Vec::new();
+	let mut field = String::new();
+	let (mut line,mut record_line) = (1,1);
+	let mut quoted = false;
+	let mut chars = text.chars().peekable();
+	while let Some(c) = chars.next(){
+		match c{
+			'"' if quoted => if chars.peek() == Some(&'"'){
+				chars.next();
+				field.push('"');
+			}else{
+				quoted = false;
+			},
+			'"' if field.is_empty() => quoted = true,
+			',' if !quoted => fields.push(::core::mem::replace(&mut field,String::new())),
+			'\r' if !quoted && chars.peek() == Some(&'\n') => (),
+			'\n' if !quoted => {
+				fields.push(::core::mem::replace(&mut field,String::new()));
+				records.push((record_line , ::core::mem::replace(&mut fields,Vec::new())));
+				line+= 1;
+				record_line = line;
+			},
+			c => {
+				if c == '\n'{line+= 1;}
+				field.push(c);
+			},
+		}
+	}
+	if !field.is_empty() || !fields.is_empty(){
+		fields.push(field);
+		records.push((record_line,fields));
+	}
+	records
+}
+
+#[cfg(test)]
+mod tests{
+	use json::Value;
+	use key::KeySignature;
+	use markers::{Marker,MarkerKind};
+	use midi_ext::*;
+	use song::Song;
+	use super::{csv_records,insert_notes,json,number,read_csv,read_json,write_csv,ImportError};
+
+	fn tone(note: u8,start_time: u32,end_time: u32,channel: u8) -> Tone{
+		Tone{
+			note              : note_from_u8(note),
+			start_time,
+			end_time,
+			atk_vel           : 90,
+			rel_vel           : 40,
+			channel,
+			instrument_program: 24,
+			instrument_bank   : 130,
+		}
+	}
+
+	fn song() -> Song{
+		let mut song = Song::empty();
+		song.division = 480;
+		song.tracks = vec![
+			vec![tone(60,0,480,0) , tone(64,0,240,0) , tone(60,480,960,1) , tone(127,960,961,15)],
+			Vec::new(),
+			vec![tone(0,100,4000,9)],
+		];
+		for tones in &mut song.tracks{
+			sort_tones(tones);
+		}
+		song.track_names = vec![Some("Piano, \"left\"".to_string()) , None , Some("Drums".to_string())];
+		song.tempo.set_tempo(0,500_000);
+		song.tempo.set_tempo(960,400_000);
+		song.tempo.set_signature(0,3,4);
+		song.key_signatures.push(KeySignature{time: 0,sharps: -3,minor: true});
+		song.markers.push(Marker{time: 480,kind: MarkerKind::CuePoint,text: "Ü \"cue\"\n".to_string()});
+		song.update_duration();
+		song
+	}
+
+	fn errors<T>(result: Result<T,ImportError>) -> Vec<String>{
+		match result{
+			Ok(_) => Vec::new(),
+			Err(ImportError(errors)) => errors,
+		}
+	}
+
+	#[test]
+	fn csv_round_trip(){
+		let song = song();
+		let mut csv = Vec::new();
+		write_csv(&song,&mut csv).unwrap();
+		let (imported,warnings) = read_csv(&String::from_utf8(csv).unwrap()).unwrap();
+		assert_eq!(warnings,Vec::<String>::new());
+		assert_eq!((imported.tracks,imported.duration),(song.tracks , song.duration));
+	}
+
+	#[test]
+	fn json_round_trip(){
+		let song = song();
+		for text in &[json(&song).to_string() , format!("{:#}",json(&song))]{
+			let (imported,warnings) = read_json(text).unwrap();
+			assert_eq!(warnings,Vec::<String>::new());
+			assert_eq!(imported.tracks,song.tracks);
+			assert_eq!(imported.track_names,song.track_names);
+			assert_eq!((imported.division,imported.duration),(song.division , song.duration));
+			assert_eq!(imported.tempo,song.tempo);
+			assert_eq!(imported.key_signatures,song.key_signatures);
+			assert_eq!(imported.markers,song.markers);
+		}
+	}
+
+	#[test]
+	fn csv_records_are_split(){
+		assert_eq!(csv_records("a,b\r\n\"c,d\",\"e\"\"f\"\n\"g\nh\",\n,i"),vec![
+			(1 , vec!["a".to_string() , "b".to_string()]),
+			(2 , vec!["c,d".to_string() , "e\"f".to_string()]),
+			(3 , vec!["g\nh".to_string() , String::new()]),
+			(5 , vec![String::new() , "i".to_string()]),
+		]);
+		assert_eq!(csv_records(""),Vec::new());
+	}
+
+	#[test]
+	fn csv_errors_are_located(){
+		assert_eq!(errors(read_csv("")),vec!["The header line is missing"]);
+		assert_eq!(errors(read_csv("note,start,length,channel\n")),vec!["Line 1: Unknown column \"length\"" , "Line 1: The column \"end\" is missing"]);
+		assert_eq!(errors(read_csv("note,name,start,end\n60,\"C\n4\",0,10\n60,C4,0\n\n61,C#4,x,10\n62,D4,10,5\n128,,0,1\n60,,1.5,2\n")),vec![
+			"Line 4: Expected 4 fields but found 3",
+			"Line 6: start: Expected a number but found \"x\"",
+			"Line 7: The end 5 is before the start 10",
+			"Line 8: note: 128 is outside the range from 0 to 127",
+			"Line 9: start: Expected an integer but found 1.5",
+		]);
+		let (song,_) = read_csv("start,end,note,channel\n0,10,60,\n").unwrap();
+		assert_eq!(song.tracks,vec![vec![Tone{atk_vel: 100,rel_vel: 64,instrument_program: 0,instrument_bank: 0,..tone(60,0,10,0)}]]);
+		assert_eq!(errors(read_csv("start,end\n0,10\n")),vec!["Line 1: The column \"note\" is missing"]);
+		assert_eq!(errors(read_csv("note,start,end\n60,0,\n")),vec!["Line 2: The value of end is missing"]);
+		assert_eq!(errors(read_csv("note,start,end,channel\n60,0,1,0\n")),vec!["Line 2: channel: 0 is outside the range from 1 to 16"]);
+	}
+
+	#[test]
+	fn json_errors_are_located(){
+		assert_eq!(errors(read_json("{\"notes\": [\n{\"note\": 60, \"start\": 0, \"end\": 1},\n]}")),vec!["Unexpected character at line 3, column 1"]);
+		assert_eq!(errors(read_json("[]")),vec!["The document is not an object"]);
+		assert_eq!(errors(read_json("{}")),vec!["The member \"notes\" is missing"]);
+		assert_eq!(errors(read_json(r#"{
+			"division": 0,
+			"tempos": [{"time": 0}, {"time": 10, "bpm": 120}],
+			"time_signatures": [{"time": 0, "numerator": 3, "denominator": 3}],
+			"key_signatures": [{"time": 0, "sharps": 8}, {"time": 0, "sharps": 1, "minor": 1}],
+			"markers": [{"time": 0, "kind": "Chapter"}, 1],
+			"tracks": {},
+			"extra": null,
+			"notes": [
+				{"note": 60, "start": 0, "end": 1},
+				{"note": 60, "start": 0},
+				{"note": "C4", "start": 0, "end": 1},
+				{"note": 60, "start": 0, "end": 1, "velocity": 128},
+				{"note": 60, "start": 0, "end": 1, "color": "red"},
+				true
+			]
+		}"#)),vec![
+			"Unknown member \"extra\"",
+			"division: 0 is outside the range from 1 to 32767",
+			"tracks: Expected an array",
+			"tempos[0]: Expected tempo or bpm",
+			"time_signatures[0]: denominator: Expected a power of two",
+			"key_signatures[0]: sharps: 8 is outside the range from -7 to 7",
+			"key_signatures[1]: minor: Expected true or false",
+			"markers[1]: Expected an object",
+			"markers[0]: kind: Unknown kind \"Chapter\"",
+			"notes[5]: Expected an object",
+			"notes[1]: The value of end is missing",
+			"notes[2]: note: Expected a number but found \"C4\"",
+			"notes[3]: velocity: 128 is outside the range from 0 to 127",
+			"notes[4]: Unknown member \"color\"",
+		]);
+		assert_eq!(
+			read_json("{\"lyrics\": [{\"time\": 0, \"text\": \"la\"}], \"notes\": []}").unwrap().1,
+			vec!["The lyrics were left out because they are not written to MIDI files"]
+		);
+	}
+
+	#[test]
+	fn names_must_be_strings(){
+		assert!(read_json("{\"notes\": [{\"note\": 60, \"name\": \"anything\", \"start\": 0, \"end\": 1}]}").is_ok());
+		for name in &["60" , "true" , "null" , "[]" , "{}"]{
+			assert_eq!(
+				errors(read_json(&format!("{{\"notes\": [{{\"note\": 60, \"name\": {}, \"start\": 0, \"end\": 1}}]}}",name))),
+				vec!["notes[0]: name: Expected a string"],
+				"{}",name
+			);
+		}
+	}
+
+	#[test]
+	fn numbers(){
+		assert_eq!(number(None,0.0,10.0),Ok(None));
+		assert_eq!(number(Some(&Value::Null),0.0,10.0),Ok(None));
+		assert_eq!(number(Some(&" ".into()),0.0,10.0),Ok(None));
+		assert_eq!(number(Some(&" 7 ".into()),0.0,10.0),Ok(Some(7.0)));
+		assert_eq!(number(Some(&10.0.into()),0.0,10.0),Ok(Some(10.0)));
+		assert_eq!(number(Some(&(-0.0).into()),0.0,10.0),Ok(Some(0.0)));
+		assert_eq!(number(Some(&11.0.into()),0.0,10.0),Err("11 is outside the range from 0 to 10".to_string()));
+		assert_eq!(number(Some(&"2.5".into()),0.0,10.0),Err("Expected an integer but found 2.5".to_string()));
+		assert_eq!(number(Some(&"seven".into()),0.0,10.0),Err("Expected a number but found \"seven\"".to_string()));
+		assert_eq!(number(Some(&true.into()),0.0,10.0),Err("Expected a number".to_string()));
+		assert_eq!(number(Some(&Value::Array(Vec::new())),0.0,10.0),Err("Expected a number".to_string()));
+	}
+
+	#[test]
+	fn overlapping_notes_are_shortened_or_dropped(){
+		let mut song = Song::empty();
+		let warnings = insert_notes(&mut song,vec![
+			(0 , tone(60,0,100,0)),
+			(0 , Tone{atk_vel: 50,..tone(60,0,200,0)}),
+			(0 , tone(60,0,100,1)),
+			(1 , tone(60,0,100,0)),
+			(0 , tone(61,0,100,0)),
+		]);
+		assert_eq!(warnings,vec!["Track 0: The note C₄ on channel 1 at tick 0 starts together with another one and was dropped"]);
+		assert_eq!(song.tracks.iter().map(Vec::len).collect::<Vec<_>>(),vec![3 , 1]);
+		assert_eq!(song.tracks[0].iter().filter(|tone| tone.channel == 0 && Into::<u8>::into(tone.note) == 60).count(),1);
+
+		let mut song = Song::empty();
+		let warnings = insert_notes(&mut song,vec![(0 , tone(60,50,200,0)) , (0 , tone(60,0,100,0))]);
+		assert_eq!(warnings,vec!["Track 0: The note C₄ on channel 1 from tick 0 overlaps the one starting at tick 50 and was shortened"]);
+		assert_eq!(song.tracks,vec![vec![tone(60,0,50,0) , tone(60,50,200,0)]]);
+		assert_eq!(song.duration,200);
+	}
+}
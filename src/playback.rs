@@ -0,0 +1,86 @@
+//Moving position in the song for following it while it plays.
+//TODO: There is no sound yet. Only the position is advanced.
+
+use std::time::Instant;
+
+use song::Song;
+
+pub struct Playback{
+	///The time in ticks when paused, and the time where playing started otherwise.
+	position: f64,
+	///When playing started.
+	started: Option<Instant>,
+	///Whether the position was moved since the last `take_seeked`.
+	seeked: bool,
+}
+
+impl Playback{
+	pub fn new(position: f64) -> Self{
+		Playback{position,started: None,seeked: false}
+	}
+
+	pub fn is_playing(&self) -> bool{
+		self.started.is_some()
+	}
+
+	///The current time in ticks.
+	///Follows the tempo map of the song, so changes to it take effect immediately.
+	pub fn position(&self,song: &Song) -> f64{
+		match self.started{
+			Some(started) => {
+				let elapsed = started.elapsed();
+				let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+				let start = song.tempo.seconds(self.position,song.division);
+				song.tempo.ticks(start + seconds,song.division).min(song.duration as f64)
+			},
+			None => self.position,
+		}
+	}
+
+	pub fn play(&mut self){
+		if self.started.is_none(){
+			self.started = Some(Instant::now());
+		}
+	}
+
+	pub fn pause(&mut self,song: &Song){
+		self.position = self.position(song);
+		self.started = None;
+	}
+
+	pub fn toggle(&mut self,song: &Song){
+		if self.is_playing(){
+			self.pause(song);
+		}else{
+			//Start from the beginning when at the end
+			if self.position >= song.duration as f64{
+				self.position = 0.0;
+			}
+			self.play();
+		}
+	}
+
+	///Moves to the time in ticks, continuing to play from there when playing.
+	pub fn seek(&mut self,position: f64){
+		self.position = position.max(0.0);
+		self.seeked = true;
+		if self.started.is_some(){
+			self.started = Some(Instant::now());
+		}
+	}
+
+	///Whether the position was moved by seeking since the last call.
+	pub fn take_seeked(&mut self) -> bool{
+		let seeked = self.seeked;
+		self.seeked = false;
+		seeked
+	}
+
+	///Stops at the end of the song. Returns whether it is still playing.
+	pub fn update(&mut self,song: &Song) -> bool{
+		if self.is_playing() && self.position(song) >= song.duration as f64{
+			self.pause(song);
+		}
+		self.is_playing()
+	}
+}
@@ -0,0 +1,61 @@
+//Writing of images as Portable Network Graphics.
+
+use std::io::{self,Write};
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use raster::Image;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1A\n";
+
+///Writes the image as a PNG of 8 bit RGBA pixels.
+pub fn write_png<W: Write>(image: &Image,out: &mut W) -> io::Result<()>{
+	out.write_all(SIGNATURE)?;
+
+	let mut header = Vec::with_capacity(13);
+	header.extend_from_slice(&u32_bytes(image.width));
+	header.extend_from_slice(&u32_bytes(image.height));
+	header.extend_from_slice(&[
+		8, //Bit depth
+		6, //Color type: RGBA
+		0, //Compression method: Deflate
+		0, //Filter method: Adaptive
+		0, //Interlace method: None
+	]);
+	write_chunk(out,b"IHDR",&header)?;
+
+	//Every row begins with its filter type, which is always none.
+	let pixels = image.rgba8();
+	let mut encoder = ZlibEncoder::new(Vec::new(),Compression::default());
+	for row in pixels.chunks((image.width as usize * 4).max(1)){
+		encoder.write_all(&[0])?;
+		encoder.write_all(row)?;
+	}
+	write_chunk(out,b"IDAT",&encoder.finish()?)?;
+
+	write_chunk(out,b"IEND",&[])
+}
+
+fn write_chunk<W: Write>(out: &mut W,kind: &[u8; 4],data: &[u8]) -> io::Result<()>{
+	out.write_all(&u32_bytes(data.len() as u32))?;
+	out.write_all(kind)?;
+	out.write_all(data)?;
+	out.write_all(&u32_bytes(crc32(kind.iter().chain(data))))
+}
+
+fn u32_bytes(value: u32) -> [u8; 4]{
+	[(value >> 24) as u8 , (value >> 16) as u8 , (value >> 8) as u8 , value as u8]
+}
+
+///The CRC-32 checksum used by PNG chunks, computed bit by bit.
+fn crc32<'d,I: IntoIterator<Item = &'d u8>>(data: I) -> u32{
+	let mut crc = 0xFFFF_FFFFu32;
+	for &byte in data{
+		crc^= byte as u32;
+		for _ in 0..8{
+			crc = if crc & 1 != 0{(crc >> 1) ^ 0xEDB8_8320}else{crc >> 1};
+		}
+	}
+	!crc
+}
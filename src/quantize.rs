@@ -0,0 +1,203 @@
+use core::cmp;
+
+use edit::MIN_TONE_LENGTH;
+use midi_ext::*;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum GridKind{
+	Straight,
+	///Three notes in the time of two.
+	Triplet,
+	///One and a half times the length.
+	Dotted,
+}
+
+impl GridKind{
+	pub const ALL: [GridKind; 3] = [GridKind::Straight,GridKind::Triplet,GridKind::Dotted];
+
+	pub fn name(self) -> &'static str{match self{
+		Self::Straight => "Straight",
+		Self::Triplet  => "Triplet",
+		Self::Dotted   => "Dotted",
+	}}
+}
+
+///A grid of note lengths, e.g. 1/16 triplets.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Grid{
+	///The note value as the denominator of a whole note. 4 is quarter notes, 16 is sixteenth notes.
+	pub denominator: u32,
+	pub kind: GridKind,
+}
+
+impl Grid{
+	pub const DENOMINATORS: [u32; 5] = [4,8,16,32,64];
+
+	///The distance between grid lines in ticks for the given ticks per quarter note.
+	pub fn step(self,division: u16) -> f64{
+		let straight = (division as f64) * 4.0 / (self.denominator as f64);
+		match self.kind{
+			GridKind::Straight => straight,
+			GridKind::Triplet  => straight * 2.0 / 3.0,
+			GridKind::Dotted   => straight * 3.0 / 2.0,
+		}
+	}
+}
+
+///Which times of a tone to quantize.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Target{
+	Start,
+	End,
+	Both,
+}
+
+impl Target{
+	pub const ALL: [Target; 3] = [Target::Start,Target::End,Target::Both];
+
+	pub fn name(self) -> &'static str{match self{
+		Self::Start => "Starts",
+		Self::End   => "Ends",
+		Self::Both  => "Both",
+	}}
+}
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Quantize{
+	pub grid: Grid,
+	///How far towards the grid line a time is moved. 0.0 leaves the time as it is, 1.0 moves it all the way.
+	pub strength: f64,
+	///The fraction of a grid step that every second grid line is delayed by. 0.0 is straight and 1/3 gives a triplet feel.
+	pub swing: f64,
+	pub target: Target,
+}
+
+impl Default for Quantize{
+	fn default() -> Self{
+		Quantize{
+			grid    : Grid{denominator: 16,kind: GridKind::Straight},
+			strength: 1.0,
+			swing   : 0.0,
+			target  : Target::Both,
+		}
+	}
+}
+
+impl Quantize{
+	///The grid line nearest to the given time.
+	pub fn nearest(&self,time: u32,division: u16) -> f64{
+		let step = self.grid.step(division);
+		if step <= 0.0{return time as f64}
+		let swing = self.swing.max(0.0).min(0.99);
+
+		//Grid lines come in pairs where the second line is delayed by the swing.
+		let time = time as f64;
+		let pair_start = (time / (step*2.0)).floor() * step*2.0;
+		[pair_start , pair_start + step*(1.0+swing) , pair_start + step*2.0]
+			.iter()
+			.cloned()
+			.min_by(|a,b| (a-time).abs().partial_cmp(&(b-time).abs()).unwrap())
+			.unwrap()
+	}
+
+	///The time moved towards the nearest grid line according to the strength.
+	pub fn time(&self,time: u32,division: u16) -> u32{
+		let strength = self.strength.max(0.0).min(1.0);
+		let target = self.nearest(time,division);
+		((time as f64) + (target - (time as f64))*strength).round().max(0.0) as u32
+	}
+
+	///The tone with its start and/or end quantized.
+	///The length of the tone is kept when only the start is quantized.
+	pub fn tone(&self,tone: Tone,division: u16) -> Tone{
+		let mut tone = tone;
+		match self.target{
+			Target::Start => {
+				let length = tone.end_time.saturating_sub(tone.start_time);
+				tone.start_time = self.time(tone.start_time,division);
+				tone.end_time   = tone.start_time.saturating_add(length);
+			},
+			Target::End => {
+				tone.end_time = self.time(tone.end_time,division);
+			},
+			Target::Both => {
+				tone.start_time = self.time(tone.start_time,division);
+				tone.end_time   = self.time(tone.end_time,division);
+			},
+		}
+		tone.end_time = cmp::max(tone.end_time,tone.start_time.saturating_add(MIN_TONE_LENGTH));
+		tone
+	}
+}
+
+#[cfg(test)]
+mod tests{
+	use edit::MIN_TONE_LENGTH;
+	use midi_ext::*;
+	use super::{Grid,GridKind,Quantize,Target};
+
+	const DIVISION: u16 = 96;
+
+	fn quantize(denominator: u32,kind: GridKind,strength: f64,swing: f64,target: Target) -> Quantize{
+		Quantize{grid: Grid{denominator,kind},strength,swing,target}
+	}
+
+	fn tone(start_time: u32,end_time: u32) -> Tone{
+		Tone{note: note_from_u8(60),start_time,end_time,atk_vel: 100,rel_vel: 64,channel: 0,instrument_program: 0,instrument_bank: 0}
+	}
+
+	#[test]
+	fn grid_steps(){
+		assert_eq!(Grid{denominator: 4,kind: GridKind::Straight}.step(DIVISION),96.0);
+		assert_eq!(Grid{denominator: 16,kind: GridKind::Straight}.step(DIVISION),24.0);
+		assert_eq!(Grid{denominator: 8,kind: GridKind::Triplet}.step(DIVISION),32.0);
+		assert_eq!(Grid{denominator: 8,kind: GridKind::Dotted}.step(DIVISION),72.0);
+	}
+
+	#[test]
+	fn times_move_to_the_nearest_line_by_the_strength(){
+		let full = quantize(16,GridKind::Straight,1.0,0.0,Target::Both);
+		assert_eq!([full.time(0,DIVISION) , full.time(11,DIVISION) , full.time(13,DIVISION) , full.time(40,DIVISION)],[0 , 0 , 24 , 48]);
+
+		let half = quantize(16,GridKind::Straight,0.5,0.0,Target::Both);
+		assert_eq!([half.time(10,DIVISION) , half.time(40,DIVISION)],[5 , 44]);
+
+		let none = quantize(16,GridKind::Straight,0.0,0.0,Target::Both);
+		assert_eq!(none.time(13,DIVISION),13);
+
+		let triplets = quantize(8,GridKind::Triplet,1.0,0.0,Target::Both);
+		assert_eq!([triplets.time(20,DIVISION) , triplets.time(50,DIVISION) , triplets.time(90,DIVISION)],[32 , 64 , 96]);
+	}
+
+	#[test]
+	fn swing_delays_every_second_line(){
+		let swing = quantize(8,GridKind::Straight,1.0,1.0/3.0,Target::Both);
+		//The lines are at 0, 64, 96, 160, ...
+		assert_eq!(swing.nearest(50,DIVISION),64.0);
+		assert_eq!(swing.nearest(70,DIVISION),64.0);
+		assert_eq!(swing.nearest(85,DIVISION),96.0);
+		assert_eq!(swing.nearest(150,DIVISION),160.0);
+	}
+
+	#[test]
+	fn targets(){
+		let tone = tone(10,61);
+		let start = quantize(16,GridKind::Straight,1.0,0.0,Target::Start).tone(tone,DIVISION);
+		assert_eq!((start.start_time,start.end_time),(0 , 51));
+		let end = quantize(16,GridKind::Straight,1.0,0.0,Target::End).tone(tone,DIVISION);
+		assert_eq!((end.start_time,end.end_time),(10 , 72));
+		let both = quantize(16,GridKind::Straight,1.0,0.0,Target::Both).tone(tone,DIVISION);
+		assert_eq!((both.start_time,both.end_time),(0 , 72));
+	}
+
+	#[test]
+	fn tones_keep_a_length(){
+		//Both times move to the same line
+		let both = quantize(4,GridKind::Straight,1.0,0.0,Target::Both).tone(tone(90,100),DIVISION);
+		assert_eq!((both.start_time,both.end_time),(96 , 96 + MIN_TONE_LENGTH));
+
+		//A tone that ends before it starts does not overflow
+		let start = quantize(4,GridKind::Straight,1.0,0.0,Target::Start).tone(tone(100,90),DIVISION);
+		assert_eq!((start.start_time,start.end_time),(96 , 96 + MIN_TONE_LENGTH));
+	}
+}
@@ -0,0 +1,211 @@
+//Rasterization of pictures in software, for rendering images without a graphics card.
+
+use rusttype::{point,Font,Scale};
+
+use render::{Align,Picture,Rgba,Shape};
+
+pub struct Image{
+	pub width : u32,
+	pub height: u32,
+	///Colors row by row from the top, not premultiplied by alpha.
+	pub pixels: Vec<Rgba>,
+}
+
+impl Image{
+	pub fn new(width: u32,height: u32,color: Rgba) -> Self{
+		Image{width,height,pixels: vec![color; width as usize * height as usize]}
+	}
+
+	///Blends the color over the pixel by the part of the pixel that it covers, from 0 to 1.
+	fn blend(&mut self,x: i64,y: i64,color: Rgba,coverage: f32){
+		if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64{return}
+		let alpha = color[3] * coverage.max(0.0).min(1.0);
+		if alpha <= 0.0{return}
+
+		let pixel = &mut self.pixels[y as usize * self.width as usize + x as usize];
+		let out_alpha = alpha + pixel[3] * (1.0 - alpha);
+		for i in 0..3{
+			pixel[i] = if out_alpha > 0.0{(color[i] * alpha + pixel[i] * pixel[3] * (1.0 - alpha)) / out_alpha}else{0.0};
+		}
+		pixel[3] = out_alpha;
+	}
+
+	///Fills a rectangle given as its left, top, width and height. Pixels partly inside are blended by how much is covered.
+	pub fn fill_rect(&mut self,[x,y,w,h]: [f64; 4],color: Rgba){
+		let (left,top) = (x.max(0.0) , y.max(0.0));
+		let (right,bottom) = ((x+w).min(self.width as f64) , (y+h).min(self.height as f64));
+		if right <= left || bottom <= top{return}
+
+		for py in (top.floor() as i64)..(bottom.ceil() as i64){
+			let coverage_y = bottom.min(py as f64 + 1.0) - top.max(py as f64);
+			for px in (left.floor() as i64)..(right.ceil() as i64){
+				let coverage_x = right.min(px as f64 + 1.0) - left.max(px as f64);
+				self.blend(px,py,color,(coverage_x * coverage_y) as f32);
+			}
+		}
+	}
+
+	///Draws a line of text vertically centered on the position.
+	pub fn text(&mut self,font: &Font,[x,y]: [f64; 2],size: f64,align: Align,text: &str,color: Rgba){
+		if size < 1.0{return}
+		let scale = Scale::uniform(size as f32);
+		let metrics = font.v_metrics(scale);
+		let width = font.layout(text,scale,point(0.0,0.0))
+			.last()
+			.map_or(0.0,|glyph| glyph.position().x + glyph.unpositioned().h_metrics().advance_width);
+		let left = match align{
+			Align::Left   => x as f32,
+			Align::Center => x as f32 - width/2.0,
+		};
+		let baseline = y as f32 + (metrics.ascent + metrics.descent)/2.0;
+
+		for glyph in font.layout(text,scale,point(left,baseline)){
+			if let Some(bounds) = glyph.pixel_bounding_box(){
+				glyph.draw(|gx,gy,coverage| self.blend(bounds.min.x as i64 + gx as i64 , bounds.min.y as i64 + gy as i64,color,coverage));
+			}
+		}
+	}
+
+	///The colors as 8 bit red, green, blue and alpha values.
+	pub fn rgba8(&self) -> Vec<u8>{
+		self.pixels.iter().flat_map(|pixel| pixel.iter().map(|&value| (value.max(0.0).min(1.0) * 255.0).round() as u8)).collect()
+	}
+}
+
+///Draws the picture into an image of exactly the given width and height, clipping what is outside.
+///The size of the picture is not used, since rounding errors in it could make frames of a video differ in size.
+pub fn rasterize(picture: &Picture,font: &Font,[width,height]: [u32; 2]) -> Image{
+	let mut image = Image::new(width,height,[0.0 , 0.0 , 0.0 , 0.0]);
+	for shape in &picture.shapes{
+		match *shape{
+			Shape::Rect{rect,color} => image.fill_rect(rect,color),
+			Shape::Text{position,size,align,ref text,color} => image.text(font,position,size,align,text,color),
+		}
+	}
+	image
+}
+
+#[cfg(test)]
+mod tests{
+	use std::env;
+	use std::fs;
+	use std::io::Read;
+
+	use flate2::read::ZlibDecoder;
+	use rusttype::{Font,FontCollection};
+
+	use midi_ext::*;
+	use png;
+	use render::{self,View};
+	use song::{Filter,Song};
+	use video::VideoSettings;
+	use super::{rasterize,Image};
+
+	const FONT: &[u8] = include_bytes!("../tests/fonts/DejaVuSansMono.ttf");
+	const SIZE: [u32; 2] = [640 , 480];
+
+	fn font() -> Font<'static>{
+		FontCollection::from_bytes(FONT).and_then(|fonts| fonts.font_at(0)).unwrap()
+	}
+
+	fn tone(note: u8,start_time: u32,end_time: u32,atk_vel: u8,channel: u8) -> Tone{
+		Tone{note: note_from_u8(note),start_time,end_time,atk_vel,rel_vel: 64,channel,instrument_program: 0,instrument_bank: 0}
+	}
+
+	///A short song of two tracks, 149 ticks long, where the width per tick does not divide the size evenly.
+	fn song() -> Song{
+		let mut song = Song::empty();
+		song.tracks = vec![
+			vec![tone(60,0,48,100,0) , tone(64,24,72,80,0) , tone(67,48,96,60,0) , tone(72,96,149,127,0)],
+			vec![tone(36,0,96,90,1) , tone(43,96,149,40,1)],
+		];
+		song.duration = 149;
+		song
+	}
+
+	fn png_data(image: &Image) -> Vec<u8>{
+		let mut data = Vec::new();
+		png::write_png(image,&mut data).unwrap();
+		data
+	}
+
+	///Reads the size and the 8 bit RGBA pixels of a PNG written by `write_png`, which only uses a single IDAT chunk and no filters.
+	fn decode_png(data: &[u8]) -> ([u32; 2],Vec<u8>){
+		let u32_at = |i: usize| (data[i] as u32) << 24 | (data[i+1] as u32) << 16 | (data[i+2] as u32) << 8 | data[i+3] as u32;
+		let (mut size,mut pixels) = ([0 , 0],Vec::new());
+		let mut i = 8;
+		while i < data.len(){
+			let length = u32_at(i) as usize;
+			let chunk = &data[i+8 .. i+8+length];
+			match &data[i+4 .. i+8]{
+				b"IHDR" => size = [u32_at(i+8) , u32_at(i+12)],
+				b"IDAT" => {
+					let mut rows = Vec::new();
+					ZlibDecoder::new(chunk).read_to_end(&mut rows).unwrap();
+					for row in rows.chunks(size[0] as usize * 4 + 1){
+						assert_eq!(row[0],0,"Only unfiltered rows are expected");
+						pixels.extend_from_slice(&row[1..]);
+					}
+				},
+				_ => {},
+			}
+			i+= 12 + length;
+		}
+		(size,pixels)
+	}
+
+	///Compares the image with the golden PNG in `tests/golden`, allowing small differences from rounding.
+	///When the environment variable `UPDATE_GOLDEN` is set, the golden PNG is written instead.
+	fn check_golden(name: &str,image: &Image){
+		let path = format!("{}/tests/golden/{}.png",env!("CARGO_MANIFEST_DIR"),name);
+		let data = png_data(image);
+		if env::var_os("UPDATE_GOLDEN").is_some(){
+			fs::write(&path,&data).unwrap();
+			return;
+		}
+
+		let (golden_size,golden_pixels) = decode_png(&fs::read(&path).unwrap_or_else(|e| panic!("Unable to read {}: {}",path,e)));
+		let (size,pixels) = decode_png(&data);
+		assert_eq!(size,golden_size,"Size differs from {}",path);
+		let differing = pixels.iter().zip(&golden_pixels).filter(|&(&a,&b)| (a as i32 - b as i32).abs() > 2).count();
+		assert!(differing == 0,"{} color values differ from {}",differing,path);
+	}
+
+	#[test]
+	fn fitted_image_has_requested_size(){
+		let (song,font) = (song(),font());
+		for &size in &[SIZE , [641 , 479] , [100 , 37] , [1 , 1]]{
+			let view = View::fit(&song,(0.0 , song.duration as f64),size);
+			let image = rasterize(&render::draw(&song,&Filter::default(),&view,None),&font,size);
+			assert_eq!([image.width , image.height],size);
+			assert_eq!(image.pixels.len(),size[0] as usize * size[1] as usize);
+			assert_eq!(decode_png(&png_data(&image)).0,size);
+		}
+	}
+
+	#[test]
+	fn video_frames_have_requested_size(){
+		let (song,font) = (song(),font());
+		for &zoom in &[1.0 , 3.3 , 7.77]{
+			let settings = VideoSettings{size: SIZE,fps: 30,zoom,range: (0.0 , song.duration as f64)};
+			for &time in settings.frame_times(&song).iter(){
+				let image = settings.frame(&song,&font,time);
+				assert_eq!([image.width , image.height],SIZE,"Frame at {} with zoom {}",time,zoom);
+			}
+		}
+	}
+
+	#[test]
+	fn roll_matches_golden(){
+		let song = song();
+		let view = View::fit(&song,(0.0 , song.duration as f64),SIZE);
+		check_golden("roll",&rasterize(&render::draw(&song,&Filter::default(),&view,None),&font(),SIZE));
+	}
+
+	#[test]
+	fn video_frame_matches_golden(){
+		let song = song();
+		let settings = VideoSettings{size: SIZE,fps: 30,zoom: 3.3,range: (0.0 , song.duration as f64)};
+		check_golden("frame",&settings.frame(&song,&font(),60.0));
+	}
+}
@@ -0,0 +1,177 @@
+//Drawing of the piano roll as shapes, for exporting images without the GUI.
+//The shapes are laid out like in the GUI: The ruler is at the top, the keyboard at the left and the lowest notes at the top of the roll.
+
+use midi_ext::*;
+use song::{visible_tones,Filter,Song};
+
+pub type Rgba = [f32; 4];
+
+///The colors of the GUI.
+pub mod colors{
+	use super::Rgba;
+
+	pub const BACKGROUND: Rgba = [0.180 , 0.204 , 0.212 , 1.0];
+	pub const RULER     : Rgba = [0.333 , 0.341 , 0.325 , 1.0];
+	pub const GRID      : Rgba = [0.5 , 0.5 , 0.5 , 0.1];
+	pub const BAR_LINE  : Rgba = [0.6 , 0.6 , 0.6 , 0.35];
+	pub const BEAT_LINE : Rgba = [0.5 , 0.5 , 0.5 , 0.12];
+	pub const TONE      : Rgba = [1.0 , 1.0 , 1.0 , 0.5];
+	pub const ACTIVE    : Rgba = [0.6 , 1.0 , 0.7 , 0.9];
+	pub const PLAYHEAD  : Rgba = [0.3 , 1.0 , 0.4 , 0.8];
+	pub const TONE_TEXT : Rgba = [0.0 , 0.0 , 0.0 , 1.0];
+	pub const WHITE_KEY : Rgba = [0.9 , 0.9 , 0.9 , 1.0];
+	pub const BLACK_KEY : Rgba = [0.15 , 0.15 , 0.15 , 1.0];
+	pub const KEY_TEXT  : Rgba = [0.3 , 0.3 , 0.3 , 1.0];
+	pub const RULER_TEXT: Rgba = [0.9 , 0.9 , 0.9 , 1.0];
+}
+
+///Width of the keyboard at the left in pixels.
+pub const KEYBOARD_WIDTH: f64 = 40.0;
+///Height of the ruler at the top in pixels.
+pub const RULER_HEIGHT: f64 = 20.0;
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Align{
+	Left,
+	Center,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Shape{
+	///A filled rectangle as its left, top, width and height.
+	Rect{rect: [f64; 4],color: Rgba},
+	///A line of text, vertically centered on the position.
+	Text{position: [f64; 2],size: f64,align: Align,text: String,color: Rgba},
+}
+
+///Shapes to be drawn in order, with the origin at the top left and y pointing down.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Picture{
+	pub width : f64,
+	pub height: f64,
+	pub shapes: Vec<Shape>,
+}
+
+///The part of the song that is drawn.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct View{
+	///Range of times in ticks.
+	pub times    : (f64,f64),
+	///Range of notes, where a note occupies the range from its number to its number plus one.
+	pub notes    : (f64,f64),
+	///Width of a tick and height of a note in pixels.
+	pub tone_size: [f64; 2],
+}
+
+impl View{
+	///The range of times with the notes of the song, leaving some space around them.
+	pub fn of_song(song: &Song,times: (f64,f64),tone_size: [f64; 2]) -> Self{
+		let notes = song.tracks.iter().flat_map(|tones| tones.iter()).map(|tone| Into::<u8>::into(tone.note) as f64);
+		let (lowest,highest) = notes.fold((NOTES as f64 , 0.0),|(lowest,highest),note| (f64::min(lowest,note) , f64::max(highest,note)));
+		View{
+			times,
+			notes: if lowest > highest{(48.0 , 84.0)}else{((lowest - 2.0).max(0.0) , (highest + 3.0).min(NOTES as f64))},
+			tone_size,
+		}
+	}
+
+	///Like `of_song`, but with the size of a tone chosen so that the picture has the size in pixels.
+	pub fn fit(song: &Song,times: (f64,f64),size: [u32; 2]) -> Self{
+		let View{notes,..} = View::of_song(song,times,[1.0 , 1.0]);
+		View{
+			times,
+			notes,
+			tone_size: [
+				((size[0] as f64 - KEYBOARD_WIDTH) / (times.1 - times.0).max(1.0)).max(0.0),
+				((size[1] as f64 - RULER_HEIGHT) / (notes.1 - notes.0).max(1.0)).max(0.0),
+			],
+		}
+	}
+
+	pub fn x_of(&self,time: f64) -> f64{
+		KEYBOARD_WIDTH + (time - self.times.0) * self.tone_size[0]
+	}
+
+	pub fn y_of(&self,note: f64) -> f64{
+		RULER_HEIGHT + (note - self.notes.0) * self.tone_size[1]
+	}
+}
+
+///Draws the tones accepted by the filter in the view.
+///When there is a playhead, given in ticks, it is drawn and the tones sounding there are highlighted.
+pub fn draw(song: &Song,filter: &Filter,view: &View,playhead: Option<f64>) -> Picture{
+	let [zoom,note_height] = view.tone_size;
+	let width  = view.x_of(view.times.1);
+	let height = view.y_of(view.notes.1);
+	let mut shapes = vec![Shape::Rect{rect: [0.0 , 0.0 , width , height],color: colors::BACKGROUND}];
+
+	//Keeps the rectangles inside the roll
+	let rect = |shapes: &mut Vec<Shape>,[x,y,w,h]: [f64; 4],color: Rgba|{
+		let (left,top) = (x.max(KEYBOARD_WIDTH) , y.max(RULER_HEIGHT));
+		let (right,bottom) = ((x+w).min(width) , (y+h).min(height));
+		if right > left && bottom > top{
+			shapes.push(Shape::Rect{rect: [left , top , right-left , bottom-top],color});
+		}
+	};
+	let rows = (view.notes.0.max(0.0) as u8)..(view.notes.1.ceil().min(NOTES as f64) as u8);
+
+	//Notes grid
+	for note in rows.clone(){
+		rect(&mut shapes,[KEYBOARD_WIDTH , view.y_of(note as f64) , width , 1.0],colors::GRID);
+	}
+
+	//Beat and bar lines, leaving out those too close to each other to be distinguished like in the GUI
+	for (time,bar) in song.tempo.beats(song.division,view.times){
+		let signature = song.tempo.signature_at(time as f64);
+		let spacing = (if bar{signature.bar_length(song.division)}else{signature.beat_length(song.division)}) as f64 * zoom;
+		if spacing < if bar{4.0}else{8.0}{continue}
+		rect(&mut shapes,[view.x_of(time as f64) , RULER_HEIGHT , 1.0 , height],if bar{colors::BAR_LINE}else{colors::BEAT_LINE});
+	}
+
+	//Tones
+	for (track,tones) in song.tracks.iter().enumerate(){
+		for (_,tone) in visible_tones(tones,view.times,view.notes).filter(|&(_,tone)| filter.accepts(track,tone)){
+			let x = view.x_of(tone.start_time as f64);
+			let y = view.y_of(Into::<u8>::into(tone.note) as f64);
+			let w = (tone.end_time as f64 - tone.start_time as f64) * zoom;
+			let active = playhead.map_or(false,|time| (tone.start_time as f64) <= time && time < tone.end_time as f64);
+			rect(&mut shapes,[x , y , w , note_height],if active{colors::ACTIVE}else{colors::TONE});
+			if w >= 20.0 && note_height >= 10.0 && x >= KEYBOARD_WIDTH && x+w <= width{
+				shapes.push(Shape::Text{position: [x + w/2.0 , y + note_height/2.0],size: note_height*0.75,align: Align::Center,text: note_name(tone.note).to_string(),color: colors::TONE_TEXT});
+			}
+		}
+	}
+
+	if let Some(time) = playhead{
+		rect(&mut shapes,[view.x_of(time) , RULER_HEIGHT , 2.0 , height],colors::PLAYHEAD);
+	}
+
+	//Keyboard, with the names of the C keys
+	for note in rows{
+		let y = view.y_of(note as f64);
+		let (top,bottom) = (y.max(RULER_HEIGHT) , (y + note_height).min(height));
+		if bottom <= top{continue}
+		let black = [1,3,6,8,10].contains(&(note % 12));
+		shapes.push(Shape::Rect{rect: [0.0 , top , if black{KEYBOARD_WIDTH*0.6}else{KEYBOARD_WIDTH} , bottom-top],color: if black{colors::BLACK_KEY}else{colors::WHITE_KEY}});
+		if note % 12 == 0 && note_height >= 8.0{
+			shapes.push(Shape::Text{position: [2.0 , y + note_height/2.0],size: note_height*0.75,align: Align::Left,text: note_name(note_from_u8(note)).to_string(),color: colors::KEY_TEXT});
+		}
+	}
+
+	//Ruler with the bar numbers, counted from 1 at the start of the song
+	shapes.push(Shape::Rect{rect: [0.0 , 0.0 , width , RULER_HEIGHT],color: colors::RULER});
+	let mut number = 0;
+	let mut last_x = ::core::f64::NEG_INFINITY;
+	for (time,bar) in song.tempo.beats(song.division,(0.0 , view.times.1)){
+		if !bar{continue}
+		number+= 1;
+		let x = view.x_of(time as f64);
+		if (time as f64) < view.times.0 || x - last_x < 24.0{continue}
+		last_x = x;
+		shapes.push(Shape::Rect{rect: [x , RULER_HEIGHT/2.0 , 1.0 , RULER_HEIGHT/2.0],color: colors::RULER_TEXT});
+		shapes.push(Shape::Text{position: [x + 3.0 , RULER_HEIGHT/2.0],size: 10.0,align: Align::Left,text: number.to_string(),color: colors::RULER_TEXT});
+	}
+	shapes.push(Shape::Rect{rect: [0.0 , 0.0 , KEYBOARD_WIDTH , RULER_HEIGHT],color: colors::BACKGROUND});
+
+	Picture{width,height,shapes}
+}
@@ -0,0 +1,293 @@
+//Recovery of damaged Standard MIDI Files.
+//The damaged file is rewritten into a valid one that the parser accepts, keeping as much as possible.
+
+use core::{cmp,fmt};
+
+use midi_write::write_vlq;
+use smf::{self,RawEvent,HEADER_ID,TRACK_ID};
+
+///Something that had to be fixed in a damaged file.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Repair{
+	///Bytes before the header chunk were skipped.
+	SkippedLeadingBytes(usize),
+	///The header chunk length was not 6.
+	HeaderLength(usize),
+	///A track chunk length exceeded the end of the file.
+	ChunkLengthExceedsFile{track: usize,length: usize},
+	///A track chunk length went past the End of Track event into the following track chunks, so it was shortened.
+	ChunkLength{track: usize,length: usize,fixed: usize},
+	///A track had invalid events. The track was cut off before the first of them.
+	TruncatedTrack{track: usize,offset: usize,reason: String},
+	///A track did not end with an End of Track meta event, so one was added.
+	AddedEndOfTrack{track: usize},
+	///A track used running status, which the parser does not support, so every status byte was written.
+	RunningStatus{track: usize},
+	///Bytes between chunks that did not belong to any chunk were skipped until the next track chunk.
+	Resynchronized{offset: usize,skipped: usize},
+	///Bytes at the end of the file that did not belong to any chunk were ignored.
+	TrailingGarbage{offset: usize,bytes: usize},
+	///The number of tracks in the header did not match the number of track chunks.
+	TrackCount{header: u16,found: usize},
+	///A format 0 file had multiple tracks, so it was changed to format 1.
+	Format{header: u16,fixed: u16},
+}
+
+impl fmt::Display for Repair{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{match *self{
+		Repair::SkippedLeadingBytes(bytes)            => write!(f,"Skipped {} bytes before the header chunk",bytes),
+		Repair::HeaderLength(length)                  => write!(f,"Fixed the header chunk length from {} to 6",length),
+		Repair::ChunkLengthExceedsFile{track,length}  => write!(f,"Track {}: Chunk length {} exceeds the end of the file",track,length),
+		Repair::ChunkLength{track,length,fixed}       => write!(f,"Track {}: Shortened the chunk length from {} to {}, which overlapped the following track chunks",track,length,fixed),
+		Repair::TruncatedTrack{track,offset,ref reason} => write!(f,"Track {}: Cut off at byte offset {}: {}",track,offset,reason),
+		Repair::AddedEndOfTrack{track}                => write!(f,"Track {}: Added a missing End of Track event",track),
+		Repair::RunningStatus{track}                  => write!(f,"Track {}: Wrote out the status bytes of running status",track),
+		Repair::Resynchronized{offset,skipped}        => write!(f,"Skipped {} bytes of damaged data at byte offset {} until the next track chunk",skipped,offset),
+		Repair::TrailingGarbage{offset,bytes}         => write!(f,"Ignored {} bytes of trailing data at byte offset {}",bytes,offset),
+		Repair::TrackCount{header,found}              => write!(f,"Fixed the number of tracks in the header from {} to {}",header,found),
+		Repair::Format{header,fixed}                  => write!(f,"Changed the format from {} to {}",header,fixed),
+	}}
+}
+
+fn find(data: &[u8],from: usize,pattern: &[u8]) -> Option<usize>{
+	if from >= data.len(){return None}
+	data[from..].windows(pattern.len()).position(|window| window == pattern).map(|i| from + i)
+}
+
+///Writes the valid events in the byte range again with all their status bytes, so that the parser accepts them.
+///Returns the events and whether any of them used running status.
+fn rewrite_events(data: &[u8],start: usize,end: usize) -> (Vec<u8>,bool){
+	let mut events = Vec::with_capacity(end - start);
+	let mut running_status = false;
+	let mut previous_time = 0;
+	smf::scan_events_with(data,start,end,|time,event|{
+		//The byte before the data of a channel message is its status byte, unless it is the last byte of the delta time or of the previous message
+		if let RawEvent::Channel{data: message,..} = event{
+			let offset = message.as_ptr() as usize - data.as_ptr() as usize;
+			running_status|= data[offset-1] < 0x80;
+		}
+		write_vlq(&mut events,time - previous_time);
+		previous_time = time;
+		events.extend_from_slice(&event.bytes());
+	});
+	(events,running_status)
+}
+
+///Rewrites a possibly damaged file into a valid Standard MIDI File, reporting everything that had to be fixed.
+///Returns None when no header chunk could be found. Containers like RIFF RMID are expected to be unwrapped already, by `load::unwrap_containers`.
+pub fn repair(data: &[u8]) -> Option<(Vec<u8>,Vec<Repair>)>{
+	let mut repairs = Vec::new();
+
+	//Header chunk
+	let header_offset = find(data,0,HEADER_ID)?;
+	if header_offset > 0{
+		repairs.push(Repair::SkippedLeadingBytes(header_offset));
+	}
+	let header_length = smf::read_u32(data,header_offset+4)? as usize;
+	let (format,track_count,division) = (
+		smf::read_u16(data,header_offset+8)?,
+		smf::read_u16(data,header_offset+10)?,
+		smf::read_u16(data,header_offset+12)?,
+	);
+	let mut offset = header_offset + 8 + if header_length < 6 || header_length > 64{
+		repairs.push(Repair::HeaderLength(header_length));
+		6
+	}else{
+		header_length
+	};
+
+	//Track chunks
+	let mut tracks: Vec<Vec<u8>> = Vec::new();
+	while offset < data.len(){
+		if data.get(offset..offset+4) != Some(&TRACK_ID[..]){
+			//Skip chunks of unknown types when they fit in the file, as specified. Otherwise look for the next track chunk.
+			let length = smf::read_u32(data,offset+4).map(|length| length as usize);
+			let plausible = data[offset..].iter().take(4).all(|byte| byte.is_ascii_alphanumeric()) && length.map_or(false,|length| offset + 8 + length <= data.len());
+			if plausible{
+				offset+= 8 + length.unwrap();
+				continue;
+			}
+			match find(data,offset,TRACK_ID){
+				Some(next) => {
+					repairs.push(Repair::Resynchronized{offset,skipped: next - offset});
+					offset = next;
+				},
+				None => {
+					repairs.push(Repair::TrailingGarbage{offset,bytes: data.len() - offset});
+					break;
+				},
+			}
+			continue;
+		}
+
+		let track = tracks.len();
+		let declared_length = smf::read_u32(data,offset+4).map_or(0,|length| length as usize);
+		let start = cmp::min(offset + 8,data.len());
+		let mut end = start + declared_length;
+		if end > data.len(){
+			repairs.push(Repair::ChunkLengthExceedsFile{track,length: declared_length});
+			end = data.len();
+		}
+
+		let mut scan = smf::scan_events(data,start,end);
+		let mut next_track = None;
+		if scan.end_of_track.is_none(){
+			//A too long chunk length can swallow the following chunks, so the track is scanned again up to the next track chunk header.
+			next_track = find(&data[..end],start,TRACK_ID);
+			if let Some(next) = next_track{
+				scan = smf::scan_events(data,start,next);
+			}
+		}
+		if let Some((error_offset,ref reason)) = scan.error{
+			repairs.push(Repair::TruncatedTrack{track,offset: error_offset,reason: reason.clone()});
+		}
+
+		let (mut events,running_status) = rewrite_events(data,start,scan.valid_end);
+		if running_status{
+			repairs.push(Repair::RunningStatus{track});
+		}
+		if scan.end_of_track.is_none(){
+			repairs.push(Repair::AddedEndOfTrack{track});
+			events.extend_from_slice(&[0x00 , 0xFF , 0x2F , 0x00]);
+		}
+		tracks.push(events);
+
+		//A too long chunk length can also swallow the following chunks after the End of Track event, which are looked for from there
+		if let Some(end_of_track) = scan.end_of_track{
+			next_track = find(&data[..end],end_of_track,TRACK_ID);
+			if next_track.is_some(){
+				repairs.push(Repair::ChunkLength{track,length: declared_length,fixed: end_of_track - start});
+			}
+		}
+
+		//Continue at the next track chunk header when the chunk length was wrong, and otherwise after the chunk.
+		offset = match next_track{
+			Some(next) => {
+				if next > scan.valid_end && scan.error.is_some(){
+					repairs.push(Repair::Resynchronized{offset: scan.valid_end,skipped: next - scan.valid_end});
+				}
+				next
+			},
+			None => end,
+		};
+	}
+
+	//Rewrite the file
+	let fixed_format = if format > 2 || (format == 0 && tracks.len() > 1){1}else{format};
+	if fixed_format != format{
+		repairs.push(Repair::Format{header: format,fixed: fixed_format});
+	}
+	if track_count as usize != tracks.len(){
+		repairs.push(Repair::TrackCount{header: track_count,found: tracks.len()});
+	}
+
+	let mut output = Vec::with_capacity(data.len() + 8*tracks.len());
+	output.extend_from_slice(HEADER_ID);
+	output.extend_from_slice(&[0,0,0,6]);
+	output.extend_from_slice(&[(fixed_format >> 8) as u8 , fixed_format as u8]);
+	output.extend_from_slice(&[(tracks.len() >> 8) as u8 , tracks.len() as u8]);
+	output.extend_from_slice(&[(division >> 8) as u8 , division as u8]);
+	for events in tracks{
+		output.extend_from_slice(TRACK_ID);
+		let length = events.len() as u32;
+		output.extend_from_slice(&[(length >> 24) as u8 , (length >> 16) as u8 , (length >> 8) as u8 , length as u8]);
+		output.extend_from_slice(&events);
+	}
+
+	Some((output,repairs))
+}
+
+#[cfg(test)]
+mod tests{
+	use midi;
+	use load::{load_song_from_bytes,Container,LoadOptions,LoadWarning};
+	use super::{repair,Repair};
+
+	const END_OF_TRACK: &[u8] = &[0x00 , 0xFF , 0x2F , 0x00];
+	const NOTE: &[u8] = &[0x00 , 0x90 , 0x3C , 0x64 , 0x60 , 0x80 , 0x3C , 0x40];
+
+	///A file of the format with the number of tracks in its header, and the track chunks as their declared length and their data.
+	fn file(format: u8,track_count: u8,tracks: &[(usize,&[u8])]) -> Vec<u8>{
+		let mut data = b"MThd\0\0\0\x06\0".to_vec();
+		data.extend_from_slice(&[format , 0 , track_count , 0 , 96]);
+		for &(length,events) in tracks{
+			data.extend_from_slice(b"MTrk");
+			data.extend_from_slice(&[0 , 0 , (length >> 8) as u8 , length as u8]);
+			data.extend_from_slice(events);
+		}
+		data
+	}
+
+	///Repairs the file, checking that the parser accepts the result, and returns the number of tones in every track and the repairs.
+	fn check(data: &[u8]) -> (Vec<usize>,Vec<Repair>){
+		let (repaired,repairs) = repair(data).unwrap();
+		let midi_data = midi::parser::parse_smf(&repaired).unwrap().1;
+		(midi_data.tracks.iter().map(|track| ::midi_ext::midi_track_to_tones(track).len()).collect() , repairs)
+	}
+
+	#[test]
+	fn wrong_track_count_is_fixed(){
+		let events = [NOTE , END_OF_TRACK].concat();
+		let data = file(1,3,&[(events.len() , &events) , (events.len() , &events)]);
+		assert_eq!(check(&data),(vec![1 , 1] , vec![Repair::TrackCount{header: 3,found: 2}]));
+	}
+
+	#[test]
+	fn missing_end_of_track_is_added(){
+		let events = [NOTE , NOTE].concat();
+		let data = file(1,2,&[(events.len() , &events) , (END_OF_TRACK.len() , END_OF_TRACK)]);
+		assert_eq!(check(&data),(vec![2 , 0] , vec![Repair::AddedEndOfTrack{track: 0}]));
+	}
+
+	#[test]
+	fn chunk_length_past_the_end_of_the_file_is_fixed(){
+		let events = [NOTE , END_OF_TRACK].concat();
+		let data = file(0,1,&[(events.len() + 100 , &events)]);
+		assert_eq!(check(&data),(vec![1] , vec![Repair::ChunkLengthExceedsFile{track: 0,length: events.len() + 100}]));
+
+		//Without an End of Track event, the track is cut off at the next track chunk
+		let data = file(1,2,&[(NOTE.len() + 100 , NOTE) , (END_OF_TRACK.len() , END_OF_TRACK)]);
+		assert_eq!(check(&data),(vec![1 , 0] , vec![Repair::ChunkLengthExceedsFile{track: 0,length: NOTE.len() + 100} , Repair::AddedEndOfTrack{track: 0}]));
+	}
+
+	#[test]
+	fn trailing_garbage_is_ignored(){
+		let events = [NOTE , END_OF_TRACK].concat();
+		let mut data = file(0,1,&[(events.len() , &events)]);
+		let offset = data.len();
+		data.extend_from_slice(&[0xDE , 0xAD , 0xBE , 0xEF , 0x00]);
+		assert_eq!(check(&data),(vec![1] , vec![Repair::TrailingGarbage{offset,bytes: 5}]));
+	}
+
+	#[test]
+	fn damaged_file_in_rmid_is_repaired(){
+		let events = [NOTE , END_OF_TRACK].concat();
+		let midi_data = file(0,2,&[(events.len() , &events)]);
+		let mut data = b"RIFF".to_vec();
+		data.extend_from_slice(&[(midi_data.len() + 12) as u8 , 0 , 0 , 0]);
+		data.extend_from_slice(b"RMIDdata");
+		data.extend_from_slice(&[midi_data.len() as u8 , 0 , 0 , 0]);
+		data.extend_from_slice(&midi_data);
+
+		let song = load_song_from_bytes(&data,LoadOptions{lenient: true}).unwrap();
+		assert_eq!(song.containers,vec![Container::Rmid]);
+		assert_eq!(song.tracks.iter().map(Vec::len).collect::<Vec<_>>(),vec![1]);
+		assert_eq!(song.warnings,vec![LoadWarning::Repaired(Repair::TrackCount{header: 2,found: 1})]);
+	}
+
+	#[test]
+	fn running_status_is_written_out(){
+		let events = [&[0x00 , 0x90 , 0x3C , 0x64 , 0x60 , 0x3C , 0x00 , 0x00 , 0x40 , 0x64 , 0x30 , 0x40 , 0x00][..] , END_OF_TRACK].concat();
+		let data = file(0,1,&[(events.len() , &events)]);
+		assert!(midi::parser::parse_smf(&data).is_err());
+		assert_eq!(check(&data),(vec![2] , vec![Repair::RunningStatus{track: 0}]));
+	}
+
+	#[test]
+	fn too_long_chunk_does_not_swallow_the_next_tracks(){
+		let first = [NOTE , END_OF_TRACK].concat();
+		let second = [NOTE , NOTE , END_OF_TRACK].concat();
+		let data = file(1,2,&[(first.len() + 10 , &first) , (second.len() , &second)]);
+		assert_eq!(check(&data),(vec![1 , 2] , vec![Repair::ChunkLength{track: 0,length: first.len() + 10,fixed: first.len()}]));
+	}
+}
@@ -0,0 +1,274 @@
+//Scanning of the chunk and event structure of Standard MIDI Files.
+//Used for locating errors before parsing, because the parser only tells whether the whole file could be parsed.
+
+use core::fmt;
+
+use midi_write::write_vlq;
+
+///Identifies a chunk in a file.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum ChunkKind{
+	Header,
+	///A track chunk by its index among the track chunks.
+	Track(usize),
+	///A chunk of an unknown type by its index among all chunks.
+	Unknown(usize),
+}
+
+impl fmt::Display for ChunkKind{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{match *self{
+		ChunkKind::Header       => write!(f,"header chunk"),
+		ChunkKind::Track(i)     => write!(f,"track chunk {}",i),
+		ChunkKind::Unknown(i)   => write!(f,"unknown chunk {}",i),
+	}}
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Chunk{
+	pub kind: ChunkKind,
+	///Byte offset of the chunk type.
+	pub offset: usize,
+	///Byte range of the chunk data.
+	pub data: (usize,usize),
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Header{
+	pub format: u16,
+	pub tracks: u16,
+	pub division: u16,
+}
+
+///A syntax error in the file.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct SyntaxError{
+	///Byte offset from the beginning of the file.
+	pub offset: usize,
+	pub chunk: ChunkKind,
+	pub message: String,
+}
+
+pub const HEADER_ID: &[u8; 4] = b"MThd";
+pub const TRACK_ID : &[u8; 4] = b"MTrk";
+
+pub fn read_u16(data: &[u8],offset: usize) -> Option<u16>{
+	data.get(offset..offset+2).map(|bytes| ((bytes[0] as u16) << 8) | (bytes[1] as u16))
+}
+
+pub fn read_u32(data: &[u8],offset: usize) -> Option<u32>{
+	data.get(offset..offset+4).map(|bytes| ((bytes[0] as u32) << 24) | ((bytes[1] as u32) << 16) | ((bytes[2] as u32) << 8) | (bytes[3] as u32))
+}
+
+///Reads a variable-length quantity, returning it and the offset after it.
+pub fn read_vlq(data: &[u8],offset: usize,end: usize) -> Option<(u32,usize)>{
+	let mut value: u32 = 0;
+	for i in 0..4{
+		let byte = *data.get(offset+i).filter(|_| offset+i < end)?;
+		value = (value << 7) | ((byte & 0x7F) as u32);
+		if byte & 0x80 == 0{
+			return Some((value,offset+i+1));
+		}
+	}
+	None
+}
+
+///Reads the header chunk and the positions of all following chunks.
+pub fn chunks(data: &[u8]) -> Result<(Header,Vec<Chunk>),SyntaxError>{
+	let error = |offset,chunk,message: &str| SyntaxError{offset,chunk,message: message.to_string()};
+
+	if data.get(0..4) != Some(&HEADER_ID[..]){
+		return Err(error(0,ChunkKind::Header,"Missing \"MThd\" header chunk. This is not a Standard MIDI File"));
+	}
+	let header_length = read_u32(data,4).ok_or_else(|| error(4,ChunkKind::Header,"Unexpected end of file in header chunk"))? as usize;
+	if header_length < 6{
+		return Err(error(4,ChunkKind::Header,"Header chunk is too short"));
+	}
+	let header = match (read_u16(data,8),read_u16(data,10),read_u16(data,12)){
+		(Some(format),Some(tracks),Some(division)) => Header{format,tracks,division},
+		_ => return Err(error(8,ChunkKind::Header,"Unexpected end of file in header chunk")),
+	};
+
+	let mut chunks = vec![Chunk{kind: ChunkKind::Header,offset: 0,data: (8,8+header_length)}];
+	let mut offset = 8 + header_length;
+	let mut tracks = 0;
+	while offset < data.len(){
+		let kind = if data.get(offset..offset+4) == Some(&TRACK_ID[..]){
+			tracks+= 1;
+			ChunkKind::Track(tracks-1)
+		}else{
+			ChunkKind::Unknown(chunks.len())
+		};
+		let length = read_u32(data,offset+4).ok_or_else(|| error(offset,kind,"Unexpected end of file in chunk header"))? as usize;
+		if offset + 8 + length > data.len(){
+			return Err(error(offset+4,kind,&format!("Chunk length {} exceeds the end of the file by {} bytes",length,offset + 8 + length - data.len())));
+		}
+		chunks.push(Chunk{kind,offset,data: (offset+8 , offset+8+length)});
+		offset+= 8 + length;
+	}
+
+	Ok((header,chunks))
+}
+
+///Summary of a scanned track chunk.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct TrackScan{
+	///Offset after the End of Track meta event, if there is one.
+	pub end_of_track: Option<usize>,
+}
+
+///Checks the syntax of the events in a track chunk.
+pub fn scan_track(data: &[u8],chunk: &Chunk) -> Result<TrackScan,SyntaxError>{
+	let scan = scan_events(data,chunk.data.0,chunk.data.1);
+	match scan.error{
+		Some((offset,message)) => Err(SyntaxError{offset,chunk: chunk.kind,message}),
+		None => Ok(TrackScan{end_of_track: scan.end_of_track}),
+	}
+}
+
+///The result of scanning events up to an End of Track event, an error or the end of the range.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct EventScan{
+	///Offset after the last complete event.
+	pub valid_end: usize,
+	///Offset after the End of Track meta event, if there is one.
+	pub end_of_track: Option<usize>,
+	///The offset and description of the first invalid event.
+	pub error: Option<(usize,String)>,
+}
+
+///An event in a track chunk.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum RawEvent<'d>{
+	///A channel message with its status byte, possibly from running status, and its data bytes.
+	Channel{status: u8,data: &'d [u8]},
+	///A system exclusive message or escape sequence by its status byte, and its data after the length.
+	SystemExclusive{status: u8,data: &'d [u8]},
+	Meta{kind: u8,data: &'d [u8]},
+}
+
+impl<'d> RawEvent<'d>{
+	///The bytes of the event as they are written in a track chunk, without running status.
+	pub fn bytes(&self) -> Vec<u8>{
+		let (mut bytes,data) = match *self{
+			RawEvent::Channel{status,data} => return [&[status],data].concat(),
+			RawEvent::SystemExclusive{status,data} => (vec![status],data),
+			RawEvent::Meta{kind,data} => (vec![0xFF , kind],data),
+		};
+		write_vlq(&mut bytes,data.len() as u32);
+		bytes.extend_from_slice(data);
+		bytes
+	}
+}
+
+pub mod meta{
+	pub const TEXT          : u8 = 0x01;
+	pub const TRACK_NAME    : u8 = 0x03;
+	pub const LYRIC         : u8 = 0x05;
+	pub const MARKER        : u8 = 0x06;
+	pub const CUE_POINT     : u8 = 0x07;
+	pub const END_OF_TRACK  : u8 = 0x2F;
+	pub const SET_TEMPO     : u8 = 0x51;
+	pub const TIME_SIGNATURE: u8 = 0x58;
+	pub const KEY_SIGNATURE : u8 = 0x59;
+}
+
+///Scans the events in the byte range.
+pub fn scan_events(data: &[u8],start: usize,end: usize) -> EventScan{
+	scan_events_with(data,start,end,|_,_| ())
+}
+
+///The events of a track chunk with their absolute times in ticks, up to the first invalid event.
+pub fn track_events<'d>(data: &'d [u8],chunk: &Chunk) -> Vec<(u32,RawEvent<'d>)>{
+	let mut events = Vec::new();
+	scan_events_with(data,chunk.data.0,chunk.data.1,|time,event| events.push((time,event)));
+	events
+}
+
+///Decodes text from a meta event. Text is usually ASCII, but the encoding is unspecified, so UTF-8 is tried before falling back to Latin-1.
+pub fn decode_text(data: &[u8]) -> String{
+	match ::core::str::from_utf8(data){
+		Ok(text) => text.to_string(),
+		Err(_)   => data.iter().map(|&byte| byte as char).collect(),
+	}
+}
+
+///Scans the events in the byte range, calling the function with the absolute time in ticks and each complete event.
+pub fn scan_events_with<'d,F: FnMut(u32,RawEvent<'d>)>(data: &'d [u8],start: usize,end: usize,mut f: F) -> EventScan{
+	let mut scan = EventScan{valid_end: start,end_of_track: None,error: None};
+	let mut offset = start;
+	let mut running_status = None;
+	let mut time: u32 = 0;
+
+	macro_rules! fail{($offset: expr , $($message: tt)*) => {{
+		scan.error = Some(($offset , format!($($message)*)));
+		return scan;
+	}}}
+	let byte_at = |offset: usize| if offset < end{data.get(offset).cloned()}else{None};
+
+	while offset < end{
+		offset = match read_vlq(data,offset,end){
+			Some((delta_time,next)) => {time = time.saturating_add(delta_time); next},
+			None => fail!(offset,"Invalid delta time"),
+		};
+
+		let status = match byte_at(offset){
+			Some(byte) if byte >= 0x80 => {offset+= 1; byte},
+			Some(_) => match running_status{
+				Some(status) => status,
+				None => fail!(offset,"Data byte without a preceding status byte"),
+			},
+			None => fail!(offset,"Unexpected end of track chunk"),
+		};
+
+		match status{
+			//Channel messages
+			0x80..=0xEF => {
+				running_status = Some(status);
+				let length = if status & 0xF0 == 0xC0 || status & 0xF0 == 0xD0{1}else{2};
+				for i in 0..length{
+					match byte_at(offset+i){
+						Some(byte) if byte < 0x80 => (),
+						Some(byte) => fail!(offset+i,"Unexpected status byte 0x{:02X} in the data of a channel message",byte),
+						None => fail!(offset+i,"Unexpected end of track chunk"),
+					}
+				}
+				f(time,RawEvent::Channel{status,data: &data[offset..offset+length]});
+				offset+= length;
+			},
+			//System exclusive messages
+			0xF0 | 0xF7 => {
+				running_status = None;
+				let (length,next) = match read_vlq(data,offset,end){
+					Some((length,next)) if next + (length as usize) <= end => (length as usize,next),
+					_ => fail!(offset,"Unexpected end of track chunk in a system exclusive message"),
+				};
+				f(time,RawEvent::SystemExclusive{status,data: &data[next..next+length]});
+				offset = next + length;
+			},
+			//Meta events
+			0xFF => {
+				running_status = None;
+				let kind = match byte_at(offset){
+					Some(kind) => kind,
+					None => fail!(offset,"Unexpected end of track chunk"),
+				};
+				let (length,next) = match read_vlq(data,offset+1,end){
+					Some((length,next)) if next + (length as usize) <= end => (length as usize,next),
+					_ => fail!(offset,"Unexpected end of track chunk in a meta event"),
+				};
+				f(time,RawEvent::Meta{kind,data: &data[next..next+length]});
+				offset = next + length;
+				if kind == meta::END_OF_TRACK{
+					scan.valid_end = offset;
+					scan.end_of_track = Some(offset);
+					return scan;
+				}
+			},
+			_ => fail!(offset-1,"Invalid status byte 0x{:02X}",status),
+		}
+
+		scan.valid_end = offset;
+	}
+
+	scan
+}
@@ -0,0 +1,240 @@
+use midi;
+
+use key::KeySignature;
+use load::{Container,LoadWarning};
+use lyrics::Lyric;
+use markers::Marker;
+use midi_ext::*;
+use tempo::TempoMap;
+
+///Used when the file does not specify the time division in ticks per quarter note (e.g. SMPTE based time division).
+pub const DEFAULT_DIVISION: u16 = 480;
+
+pub struct Song{
+	///The format of the Standard MIDI File: 0 for a single track, 1 for simultaneous tracks and 2 for independent tracks.
+	pub format        : u16,
+	pub tracks        : Vec<Vec<Tone>>,
+	///The names of the tracks from their Sequence/Track Name meta events, by track index.
+	pub track_names   : Vec<Option<String>>,
+	pub duration      : u32,
+	///Ticks per quarter note.
+	pub division      : u16,
+	pub tempo         : TempoMap,
+	///Key signatures ordered by time.
+	pub key_signatures: Vec<KeySignature>,
+	///Lyrics ordered by time.
+	pub lyrics        : Vec<Lyric>,
+	///Markers, cue points and bookmarks ordered by time.
+	pub markers       : Vec<Marker>,
+	///The events of every track that the other fields do not cover, e.g. controllers, pitch bends, system exclusive messages and text, as (time,bytes) by track index.
+	///They are kept as they are so that saving the song does not lose them.
+	pub other_events  : Vec<Vec<(u32,Vec<u8>)>>,
+	///The containers that the file was wrapped in, from the outermost.
+	pub containers    : Vec<Container>,
+	///Problems found when loading the song.
+	pub warnings      : Vec<LoadWarning>,
+}
+
+impl Song{
+	pub fn from_midi(midi_data: &midi::SimpleMidiFile) -> Self{
+		Song{
+			format        : 1,
+			tracks        : midi_data.tracks.iter().map(midi_track_to_tones).collect(),
+			track_names   : Vec::new(),
+			duration      : midi_duration(midi_data),
+			division      : match midi_data.header.division{
+				midi::Division::Metrical(ticks) => ticks,
+				_ => DEFAULT_DIVISION, //TODO: SMPTE time division.
+			},
+			tempo         : TempoMap::default(),
+			key_signatures: Vec::new(),
+			lyrics        : Vec::new(),
+			markers       : Vec::new(),
+			other_events  : Vec::new(),
+			containers    : Vec::new(),
+			warnings      : Vec::new(),
+		}
+	}
+
+	pub fn empty() -> Self{
+		Song{
+			format        : 1,
+			tracks        : Vec::new(),
+			track_names   : Vec::new(),
+			duration      : 0,
+			division      : DEFAULT_DIVISION,
+			tempo         : TempoMap::default(),
+			key_signatures: Vec::new(),
+			lyrics        : Vec::new(),
+			markers       : Vec::new(),
+			other_events  : Vec::new(),
+			containers    : Vec::new(),
+			warnings      : Vec::new(),
+		}
+	}
+
+	pub fn track_name(&self,track: usize) -> Option<&str>{
+		self.track_names.get(track).and_then(|name| name.as_ref()).map(String::as_str)
+	}
+
+	pub fn tone(&self,id: ToneId) -> Option<&Tone>{
+		self.tracks.get(id.track).and_then(|tones| tones.get(id.index))
+	}
+
+	pub fn tone_mut(&mut self,id: ToneId) -> Option<&mut Tone>{
+		self.tracks.get_mut(id.track).and_then(|tones| tones.get_mut(id.index))
+	}
+
+	///Keeps only the tones for which the predicate, given the track index and the tone, returns true.
+	pub fn retain_tones<F: FnMut(usize,&Tone) -> bool>(&mut self,mut f: F){
+		for (track,tones) in self.tracks.iter_mut().enumerate(){
+			tones.retain(|tone| f(track,tone));
+		}
+	}
+
+	///Extends the duration so that it covers every tone.
+	///The duration is never shrunk because the end of the song may also be determined by other events than the tones.
+	pub fn update_duration(&mut self){
+		let end = self.tracks
+			.iter()
+			.flat_map(|tones| tones.iter())
+			.map(|tone| tone.end_time)
+			.max()
+			.unwrap_or(0);
+		if end > self.duration{
+			self.duration = end;
+		}
+	}
+}
+
+///Which tracks and channels to include.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Filter{
+	pub tracks  : Option<Vec<usize>>,
+	///Channels numbered from 0.
+	pub channels: Option<Vec<u8>>,
+}
+
+impl Filter{
+	pub fn accepts(&self,track: usize,tone: &Tone) -> bool{
+		self.accepts_event(track,Some(tone.channel))
+	}
+
+	///Whether an event in the track is included. Events without a channel only depend on the track.
+	pub fn accepts_event(&self,track: usize,channel: Option<u8>) -> bool{
+		self.tracks.as_ref().map_or(true,|tracks| tracks.contains(&track)) &&
+		channel.map_or(true,|channel| self.channels.as_ref().map_or(true,|channels| channels.contains(&channel)))
+	}
+
+	pub fn is_empty(&self) -> bool{
+		self.tracks.is_none() && self.channels.is_none()
+	}
+}
+
+///The tones of a track that intersect the view, together with their indices.
+///The view is given as a range of times in ticks and a range of notes where a note occupies the range from its number to its number plus one.
+///Expects the tones to be sorted by `tone_order`, which makes it possible to stop at the first tone starting after the view.
+pub fn visible_tones<'t>(tones: &'t [Tone],times: (f64,f64),notes: (f64,f64)) -> impl Iterator<Item = (usize,&'t Tone)> + 't{
+	tones
+		.iter()
+		.enumerate()
+		.take_while(move |&(_,tone)| (tone.start_time as f64) <= times.1)
+		.filter(move |&(_,tone)|{
+			let note = Into::<u8>::into(tone.note) as f64;
+			(tone.end_time as f64) >= times.0 && note+1.0 >= notes.0 && note <= notes.1
+		})
+}
+
+///Identifies a tone in a song by its position.
+///Only valid until the track it refers to is modified.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub struct ToneId{
+	pub track: usize,
+	pub index: usize,
+}
+
+#[cfg(test)]
+mod tests{
+	use midi;
+	use midi_ext::*;
+	use midi_write;
+	use super::{visible_tones,Song};
+
+	///A xorshift generator, so that the same cases are tested every time.
+	struct Random(u32);
+
+	impl Random{
+		fn next(&mut self,bound: u32) -> u32{
+			self.0^= self.0 << 13;
+			self.0^= self.0 >> 17;
+			self.0^= self.0 << 5;
+			self.0 % bound
+		}
+
+		///A number in the range with a fractional part.
+		fn between(&mut self,min: f64,max: f64) -> f64{
+			min + (self.next(1 << 16) as f64) / ((1 << 16) as f64) * (max - min)
+		}
+	}
+
+	///Tones in no particular order. Some of them overlap.
+	fn random_tones(random: &mut Random,count: usize) -> Vec<Tone>{
+		(0..count).map(|_|{
+			let start_time = random.next(2000);
+			Tone{
+				note              : note_from_u8(random.next(NOTES as u32) as u8),
+				start_time        : start_time,
+				end_time          : start_time + 1 + random.next(300),
+				atk_vel           : 1 + random.next(127) as u8,
+				rel_vel           : 64,
+				channel           : random.next(16) as u8,
+				instrument_program: 0,
+				instrument_bank   : 0,
+			}
+		}).collect()
+	}
+
+	///Checks that `visible_tones` finds the same tones as checking every tone, for many views.
+	fn check_culling(random: &mut Random,tones: &[Tone]){
+		for _ in 0..500{
+			let time = random.between(-100.0,2400.0);
+			let times = (time , time + random.between(0.0,800.0));
+			let note = random.between(-4.0,NOTES as f64 + 4.0);
+			let notes = (note , note + random.between(0.0,64.0));
+
+			let culled: Vec<usize> = visible_tones(tones,times,notes).map(|(index,_)| index).collect();
+			let expected: Vec<usize> = tones.iter().enumerate().filter(|&(_,tone)|{
+				let note = Into::<u8>::into(tone.note) as f64;
+				(tone.end_time as f64) >= times.0 && (tone.start_time as f64) <= times.1 && note+1.0 >= notes.0 && note <= notes.1
+			}).map(|(index,_)| index).collect();
+			assert_eq!(culled,expected,"Tones in times {:?} and notes {:?}",times,notes);
+		}
+	}
+
+	#[test]
+	fn culling_keeps_visible_sorted_tones(){
+		let mut random = Random(0x2545_F491);
+		for &count in &[0,1,2,10,100,1000]{
+			let mut tones = random_tones(&mut random,count);
+			sort_tones(&mut tones);
+			check_culling(&mut random,&tones);
+		}
+	}
+
+	#[test]
+	fn culling_keeps_visible_loaded_tones(){
+		let mut random = Random(0x9E37_79B9);
+		for &count in &[0,1,2,10,100,1000]{
+			let mut song = Song::empty();
+			song.tracks = vec![random_tones(&mut random,count) , random_tones(&mut random,count / 2)];
+			let mut data = Vec::new();
+			midi_write::write_smf(&song,&mut data).unwrap();
+
+			let midi_data = midi::parser::parse_smf(&data).unwrap().1;
+			assert_eq!(midi_data.tracks.len(),2);
+			for midi_track in &midi_data.tracks{
+				check_culling(&mut random,&midi_track_to_tones(midi_track));
+			}
+		}
+	}
+}
@@ -0,0 +1,306 @@
+//Statistics about the contents of a song, as reported by the `info` command and shown in the overview strip.
+
+use core::fmt;
+use std::collections::BTreeMap;
+
+use json::Value;
+use key::{self,Key};
+use midi_ext::*;
+use song::{visible_tones,Song};
+use tempo::{self,DEFAULT_TEMPO};
+
+pub struct TrackStatistics{
+	pub name    : Option<String>,
+	pub notes   : usize,
+	///The number of notes on every channel that has notes, as (channel,notes).
+	pub channels: Vec<(u8,usize)>,
+}
+
+///An instrument that notes are played with.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord)]
+pub struct InstrumentUse{
+	///Whether it is played on the percussion channel, where the program selects a drum kit instead of a patch.
+	pub percussion: bool,
+	pub program   : u8,
+	pub bank      : u16,
+}
+
+impl InstrumentUse{
+	pub fn name(&self) -> String{
+		if self.percussion{
+			format!("Drum kit {}",self.program+1)
+		}else{
+			GeneralMidiLvl1Patch::from_program(self.program).name().to_string()
+		}
+	}
+
+	pub fn family(&self) -> &'static str{
+		if self.percussion{
+			"Percussion"
+		}else{
+			GeneralMidiLvl1PatchFamily::from_instrument(GeneralMidiLvl1Patch::from_program(self.program)).name()
+		}
+	}
+}
+
+pub struct Statistics{
+	pub format     : u16,
+	pub division   : u16,
+	pub containers : Vec<String>,
+	pub tracks     : Vec<TrackStatistics>,
+	pub notes      : usize,
+	///The lowest and highest note, leaving out the percussion channel.
+	pub pitch_range: Option<(u8,u8)>,
+	///The lowest, mean and highest attack velocity.
+	pub velocity   : Option<(u8,f64,u8)>,
+	///The instruments and how many notes they play.
+	pub instruments: Vec<(InstrumentUse,usize)>,
+	///The highest number of simultaneously sounding notes and the first time in ticks it occurs.
+	pub polyphony  : (usize,u32),
+	///Duration in ticks.
+	pub duration   : u32,
+	///Duration in seconds.
+	pub seconds    : f64,
+	///The lowest and highest tempo in beats per minute.
+	pub tempo_range: (f64,f64),
+	pub key        : Option<Key>,
+	pub lyrics     : usize,
+	pub warnings   : Vec<String>,
+}
+
+impl Statistics{
+	///The statistics of the song, where the tones on the drum channels count as percussion without pitch.
+	pub fn of(song: &Song,drums: &[u8]) -> Self{
+		let tones = || song.tracks.iter().flat_map(|tones| tones.iter());
+		let pitched = || tones().filter(|tone| !drums.contains(&tone.channel));
+
+		let mut instruments: BTreeMap<InstrumentUse,usize> = BTreeMap::new();
+		for tone in tones(){
+			*instruments.entry(InstrumentUse{percussion: drums.contains(&tone.channel),program: tone.instrument_program,bank: tone.instrument_bank}).or_insert(0)+= 1;
+		}
+
+		let notes = tones().count();
+		let velocity = match (tones().map(|tone| tone.atk_vel).min() , tones().map(|tone| tone.atk_vel).max()){
+			(Some(min),Some(max)) => Some((min , tones().map(|tone| tone.atk_vel as f64).sum::<f64>() / notes as f64 , max)),
+			_ => None,
+		};
+		let pitch_range = match (pitched().map(|tone| Into::<u8>::into(tone.note)).min() , pitched().map(|tone| Into::<u8>::into(tone.note)).max()){
+			(Some(min),Some(max)) => Some((min,max)),
+			_ => None,
+		};
+
+		//The tempo before the first change is the default one.
+		let mut tempos: Vec<u32> = song.tempo.tempos.iter().map(|change| change.tempo).collect();
+		if song.tempo.tempos.first().map_or(true,|change| change.time > 0){
+			tempos.push(DEFAULT_TEMPO);
+		}
+		let tempo_range = (
+			tempo::bpm(tempos.iter().cloned().max().unwrap_or(DEFAULT_TEMPO)),
+			tempo::bpm(tempos.iter().cloned().min().unwrap_or(DEFAULT_TEMPO)),
+		);
+
+		Statistics{
+			format     : song.format,
+			division   : song.division,
+			containers : song.containers.iter().map(ToString::to_string).collect(),
+			tracks     : song.tracks.iter().enumerate().map(|(track,tones)|{
+				let mut channels = [0; 16];
+				for tone in tones{
+					channels[tone.channel as usize & 0x0F]+= 1;
+				}
+				TrackStatistics{
+					name    : song.track_name(track).map(str::to_string),
+					notes   : tones.len(),
+					channels: channels.iter().enumerate().filter(|&(_,&count)| count > 0).map(|(channel,&count)| (channel as u8,count)).collect(),
+				}
+			}).collect(),
+			notes,
+			pitch_range,
+			velocity,
+			instruments: instruments.into_iter().collect(),
+			polyphony  : polyphony(song,|_,_| true,(0 , song.duration)).into_iter().fold((0,0),|max,(time,count)| if count > max.0{(count,time)}else{max}),
+			duration   : song.duration,
+			seconds    : song.tempo.seconds(song.duration as f64,song.division),
+			tempo_range,
+			key        : key::song_key(song,drums),
+			lyrics     : song.lyrics.len(),
+			warnings   : song.warnings.iter().map(ToString::to_string).collect(),
+		}
+	}
+
+	pub fn json(&self) -> Value{
+		Value::object(vec![
+			("containers" , self.containers.clone().into()),
+			("format"     , self.format.into()),
+			("division"   , self.division.into()),
+			("duration"   , Value::object(vec![("ticks" , self.duration.into()) , ("seconds" , self.seconds.into())])),
+			("tempo"      , Value::object(vec![("min_bpm" , self.tempo_range.0.into()) , ("max_bpm" , self.tempo_range.1.into())])),
+			("key"        , self.key.map(|key| key.to_string()).into()),
+			("tracks"     , Value::Array(self.tracks.iter().enumerate().map(|(track,statistics)| Value::object(vec![
+				("index"   , track.into()),
+				("name"    , statistics.name.clone().into()),
+				("notes"   , statistics.notes.into()),
+				("channels", Value::Array(statistics.channels.iter().map(|&(channel,notes)| Value::object(vec![("channel" , (channel+1).into()) , ("notes" , notes.into())])).collect())),
+			])).collect())),
+			("notes"      , self.notes.into()),
+			("pitch_range", self.pitch_range.map_or(Value::Null,|(lowest,highest)| Value::object(vec![
+				("lowest"      , lowest.into()),
+				("lowest_name" , note_name(note_from_u8(lowest)).into()),
+				("highest"     , highest.into()),
+				("highest_name", note_name(note_from_u8(highest)).into()),
+			]))),
+			("velocity"   , self.velocity.map_or(Value::Null,|(min,mean,max)| Value::object(vec![("min" , min.into()) , ("mean" , mean.into()) , ("max" , max.into())]))),
+			("polyphony"  , Value::object(vec![("max" , self.polyphony.0.into()) , ("time" , self.polyphony.1.into())])),
+			("instruments", Value::Array(self.instruments.iter().map(|&(instrument,notes)| Value::object(vec![
+				("name"      , instrument.name().into()),
+				("family"    , instrument.family().into()),
+				("percussion", instrument.percussion.into()),
+				("program"   , instrument.program.into()),
+				("bank"      , instrument.bank.into()),
+				("notes"     , notes.into()),
+			])).collect())),
+			("lyrics"     , self.lyrics.into()),
+			("warnings"   , self.warnings.clone().into()),
+		])
+	}
+}
+
+///A human readable report.
+impl fmt::Display for Statistics{
+	fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result{
+		for container in &self.containers{
+			writeln!(f,"Container: {}",container)?;
+		}
+		writeln!(f,"Format: {}",self.format)?;
+		writeln!(f,"Division: {} ticks per quarter note",self.division)?;
+		writeln!(f,"Duration: {} ticks ({:.1} s)",self.duration,self.seconds)?;
+		if self.tempo_range.0 == self.tempo_range.1{
+			writeln!(f,"Tempo: {:.1} BPM",self.tempo_range.0)?;
+		}else{
+			writeln!(f,"Tempo: {:.1} to {:.1} BPM",self.tempo_range.0,self.tempo_range.1)?;
+		}
+		if let Some(key) = self.key{
+			writeln!(f,"Key: {}",key)?;
+		}
+
+		writeln!(f,"Tracks: {}",self.tracks.len())?;
+		for (track,statistics) in self.tracks.iter().enumerate(){
+			write!(f,"  Track {}",track)?;
+			if let Some(ref name) = statistics.name{
+				write!(f," \"{}\"",name)?;
+			}
+			write!(f,": {} notes",statistics.notes)?;
+			if !statistics.channels.is_empty(){
+				let channels: Vec<String> = statistics.channels.iter().map(|&(channel,notes)| format!("channel {}: {}",channel+1,notes)).collect();
+				write!(f," ({})",channels.join(", "))?;
+			}
+			writeln!(f)?;
+		}
+
+		writeln!(f,"Notes: {}",self.notes)?;
+		if let Some((lowest,highest)) = self.pitch_range{
+			writeln!(f,"Pitch range: {} to {}",note_name(note_from_u8(lowest)),note_name(note_from_u8(highest)))?;
+		}
+		if let Some((min,mean,max)) = self.velocity{
+			writeln!(f,"Velocity: {} min, {:.1} mean, {} max",min,mean,max)?;
+		}
+		writeln!(f,"Maximum polyphony: {} notes at tick {}",self.polyphony.0,self.polyphony.1)?;
+
+		writeln!(f,"Instruments: {}",self.instruments.len())?;
+		for &(instrument,notes) in &self.instruments{
+			writeln!(f,"  {} ({}, program {}, bank {}): {} notes",instrument.name(),instrument.family(),instrument.program,instrument.bank,notes)?;
+		}
+
+		if self.lyrics > 0{
+			writeln!(f,"Lyrics: {} syllables",self.lyrics)?;
+		}
+		if !self.warnings.is_empty(){
+			writeln!(f,"Warnings: {}",self.warnings.len())?;
+			for warning in &self.warnings{
+				writeln!(f,"  {}",warning)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+///The number of sounding notes over time, as (time in ticks,count) for every time that it changes, using the tones for which `accept`, given the track index and the tone, returns true.
+///Only the tones intersecting the range of ticks are counted, so the counts are only complete inside it. Tones without length are left out.
+pub fn polyphony<F: Fn(usize,&Tone) -> bool>(song: &Song,accept: F,range: (u32,u32)) -> Vec<(u32,usize)>{
+	//Ends are ordered before starts at the same time.
+	let mut changes: Vec<(u32,i32)> = Vec::new();
+	for (track,tones) in song.tracks.iter().enumerate(){
+		for (_,tone) in visible_tones(tones,(range.0 as f64 , range.1 as f64),(0.0 , NOTES as f64)).filter(|&(_,tone)| tone.end_time > tone.start_time && accept(track,tone)){
+			changes.push((tone.start_time , 1));
+			changes.push((tone.end_time , -1));
+		}
+	}
+	changes.sort();
+
+	let mut curve: Vec<(u32,usize)> = Vec::new();
+	let mut count = 0i32;
+	for (time,change) in changes{
+		count+= change;
+		match curve.last_mut(){
+			Some(last) if last.0 == time => last.1 = count as usize,
+			_ => curve.push((time , count as usize)),
+		}
+	}
+	curve
+}
+
+///How densely the notes fill a grid of cells dividing the range of ticks into `columns` and the notes into `bands`, using the tones for which `accept` returns true.
+///A cell is indexed by `[band][column]` with band 0 at note 0, and holds the total time that notes sound in it divided by the time of the cell per note, so 1.0 means that every note of the band sounds throughout.
+pub fn density<F: Fn(usize,&Tone) -> bool>(song: &Song,accept: F,range: (u32,u32),columns: usize,bands: usize) -> Vec<Vec<f64>>{
+	let mut cells = vec![vec![0.0; columns]; bands];
+	if columns == 0 || bands == 0 || range.1 <= range.0{return cells}
+
+	let column_length = (range.1 - range.0) as f64 / columns as f64;
+	let band_notes = NOTES as f64 / bands as f64;
+	for (track,tones) in song.tracks.iter().enumerate(){
+		for (_,tone) in visible_tones(tones,(range.0 as f64 , range.1 as f64),(0.0 , NOTES as f64)).filter(|&(_,tone)| accept(track,tone)){
+			let band = ((Into::<u8>::into(tone.note) as f64 / band_notes) as usize).min(bands-1);
+			let start = (tone.start_time.max(range.0) - range.0) as f64;
+			let end = (tone.end_time.min(range.1).max(range.0) - range.0) as f64;
+			let mut column = (start / column_length) as usize;
+			while column < columns && (column as f64) * column_length < end{
+				let overlap = end.min((column+1) as f64 * column_length) - start.max(column as f64 * column_length);
+				cells[band][column]+= overlap.max(0.0) / (column_length * band_notes);
+				column+= 1;
+			}
+		}
+	}
+	cells
+}
+
+///The notes sounding in a slice of time.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Slice{
+	pub lowest : u8,
+	pub highest: u8,
+	///The number of tones sounding in the slice.
+	pub tones  : usize,
+}
+
+///Divides the whole song into `count` slices of equal length and finds the notes sounding in each, using the tones for which `accept` returns true.
+///Slices where nothing sounds are None.
+pub fn slices<F: Fn(usize,&Tone) -> bool>(song: &Song,accept: F,count: usize) -> Vec<Option<Slice>>{
+	let mut slices: Vec<Option<Slice>> = vec![None; count];
+	if count == 0 || song.duration == 0{return slices}
+
+	let slice_length = song.duration as f64 / count as f64;
+	for (track,tones) in song.tracks.iter().enumerate(){
+		for tone in tones.iter().filter(|tone| accept(track,tone)){
+			let note = Into::<u8>::into(tone.note);
+			let first = ((tone.start_time as f64 / slice_length) as usize).min(count-1);
+			let last = (((tone.end_time as f64 / slice_length).ceil() as usize).max(first+1) - 1).min(count-1);
+			for slice in &mut slices[first..=last]{
+				*slice = Some(match *slice{
+					Some(Slice{lowest,highest,tones}) => Slice{lowest: lowest.min(note),highest: highest.max(note),tones: tones+1},
+					None => Slice{lowest: note,highest: note,tones: 1},
+				});
+			}
+		}
+	}
+	slices
+}
@@ -0,0 +1,45 @@
+//Writing of pictures as Scalable Vector Graphics.
+
+use std::io::{self,Write};
+
+use render::{Align,Picture,Rgba,Shape};
+
+pub fn write_svg<W: Write>(picture: &Picture,out: &mut W) -> io::Result<()>{
+	writeln!(out,r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+	writeln!(out,r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,w = picture.width,h = picture.height)?;
+	for shape in &picture.shapes{
+		match *shape{
+			Shape::Rect{rect: [x,y,w,h],color} => writeln!(out,
+				r#"<rect x="{}" y="{}" width="{}" height="{}" {}/>"#,
+				x,y,w,h,fill(color)
+			)?,
+			Shape::Text{position: [x,y],size,align,ref text,color} => writeln!(out,
+				r#"<text x="{}" y="{}" font-size="{}" font-family="sans-serif" text-anchor="{}" dominant-baseline="central" {}>{}</text>"#,
+				x,y,size,match align{Align::Left => "start",Align::Center => "middle"},fill(color),escape(text)
+			)?,
+		}
+	}
+	writeln!(out,"</svg>")
+}
+
+fn fill(color: Rgba) -> String{
+	let channel = |value: f32| (value.max(0.0).min(1.0) * 255.0).round() as u8;
+	let rgb = format!(r#"fill="rgb({},{},{})""#,channel(color[0]),channel(color[1]),channel(color[2]));
+	if color[3] < 1.0{format!(r#"{} fill-opacity="{}""#,rgb,color[3])}else{rgb}
+}
+
+///Escapes the characters that have a special meaning in XML text and attribute values.
+pub fn escape(text: &str) -> String{
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars(){
+		match c{
+			'<'  => escaped.push_str("&lt;"),
+			'>'  => escaped.push_str("&gt;"),
+			'&'  => escaped.push_str("&amp;"),
+			'"'  => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&apos;"),
+			c    => escaped.push(c),
+		}
+	}
+	escaped
+}
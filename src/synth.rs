@@ -0,0 +1,83 @@
+//Offline synthesis of the tones as simple sounds, for giving videos a sound track.
+//Pitched tones are sine waves with a few overtones, and percussion tones are bursts of noise. The instruments are not distinguished.
+
+use core::f64::consts::PI;
+use std::io::{self,Write};
+
+use song::Song;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+///Seconds for a tone to reach its full volume.
+const ATTACK: f64 = 0.005;
+///Seconds for a tone to fade out after it ends.
+const RELEASE: f64 = 0.08;
+///Seconds that a percussion tone sounds, regardless of its length.
+const PERCUSSION_LENGTH: f64 = 0.12;
+///Volume of a tone at full velocity.
+const VOLUME: f64 = 0.15;
+
+///The sound of the tones in the range of ticks as mono samples from -1 to 1. The tones on the drum channels are percussion.
+pub fn synthesize(song: &Song,drums: &[u8],range: (f64,f64)) -> Vec<f32>{
+	let seconds = |ticks: f64| song.tempo.seconds(ticks,song.division);
+	let start = seconds(range.0);
+	let mut samples = vec![0.0f64; ((seconds(range.1) - start) * SAMPLE_RATE as f64).ceil() as usize];
+	let mut noise = 0x2545_F491u32;
+
+	for tone in song.tracks.iter().flat_map(|tones| tones.iter()){
+		if (tone.end_time as f64) < range.0 || (tone.start_time as f64) > range.1{continue}
+		let tone_start = seconds(tone.start_time as f64) - start;
+		let percussion = drums.contains(&tone.channel);
+		let length = if percussion{PERCUSSION_LENGTH}else{seconds(tone.end_time as f64) - start - tone_start};
+		let volume = VOLUME * tone.atk_vel as f64 / 127.0;
+		let frequency = 440.0 * 2f64.powf((Into::<u8>::into(tone.note) as f64 - 69.0) / 12.0);
+
+		let first = (tone_start.max(0.0) * SAMPLE_RATE as f64) as usize;
+		let last = (((tone_start + length + RELEASE) * SAMPLE_RATE as f64).ceil() as usize).min(samples.len());
+		for i in first..last{
+			let t = i as f64 / SAMPLE_RATE as f64 - tone_start;
+			let envelope = (t / ATTACK).min(1.0) * if t > length{1.0 - (t - length) / RELEASE}else{1.0};
+			let wave = if percussion{
+				//Xorshift noise, decaying over the length
+				noise^= noise << 13;
+				noise^= noise >> 17;
+				noise^= noise << 5;
+				(noise as f64 / ::core::u32::MAX as f64 * 2.0 - 1.0) * (1.0 - t / (length + RELEASE))
+			}else{
+				let phase = 2.0 * PI * frequency * t;
+				phase.sin() + 0.3 * (2.0 * phase).sin() + 0.1 * (3.0 * phase).sin()
+			};
+			samples[i]+= volume * envelope.max(0.0) * wave;
+		}
+	}
+
+	//Scale down when the tones add up to more than the full range
+	let peak = samples.iter().fold(0.0f64,|peak,sample| peak.max(sample.abs()));
+	let gain = if peak > 1.0{1.0 / peak}else{1.0};
+	samples.into_iter().map(|sample| (sample * gain) as f32).collect()
+}
+
+///Writes mono samples as a WAVE file of 16 bit PCM.
+pub fn write_wav<W: Write>(samples: &[f32],out: &mut W) -> io::Result<()>{
+	let data_length = samples.len() as u32 * 2;
+	out.write_all(b"RIFF")?;
+	out.write_all(&u32_le(36 + data_length))?;
+	out.write_all(b"WAVEfmt ")?;
+	out.write_all(&u32_le(16))?;
+	out.write_all(&[1 , 0 , 1 , 0])?; //PCM, mono
+	out.write_all(&u32_le(SAMPLE_RATE))?;
+	out.write_all(&u32_le(SAMPLE_RATE * 2))?; //Bytes per second
+	out.write_all(&[2 , 0 , 16 , 0])?; //Bytes per sample, bits per sample
+	out.write_all(b"data")?;
+	out.write_all(&u32_le(data_length))?;
+
+	let data: Vec<u8> = samples.iter().flat_map(|&sample|{
+		let value = (sample.max(-1.0).min(1.0) * 32767.0).round() as i16;
+		vec![value as u8 , (value >> 8) as u8]
+	}).collect();
+	out.write_all(&data)
+}
+
+fn u32_le(value: u32) -> [u8; 4]{
+	[value as u8 , (value >> 8) as u8 , (value >> 16) as u8 , (value >> 24) as u8]
+}
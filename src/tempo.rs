@@ -0,0 +1,170 @@
+//Tempo and time signature changes, and the mapping between ticks and seconds.
+
+use smf::{self,meta,Chunk,ChunkKind,RawEvent};
+
+///Microseconds per quarter note when the file does not specify a tempo (120 beats per minute).
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+///The tempos that can be set in the GUI, in beats per minute.
+pub const BPM_RANGE: (f64,f64) = (20.0 , 300.0);
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct TempoChange{
+	///Time in ticks.
+	pub time : u32,
+	///Microseconds per quarter note.
+	pub tempo: u32,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct TimeSignature{
+	///Time in ticks.
+	pub time       : u32,
+	///Beats per bar.
+	pub numerator  : u8,
+	///The note value of a beat, e.g. 4 for quarter notes. A power of two.
+	pub denominator: u8,
+}
+
+impl TimeSignature{
+	pub const DEFAULT: TimeSignature = TimeSignature{time: 0,numerator: 4,denominator: 4};
+
+	///Length of a beat in ticks.
+	pub fn beat_length(&self,division: u16) -> u32{
+		(division as u32 * 4 / self.denominator.max(1) as u32).max(1)
+	}
+
+	///Length of a bar in ticks.
+	pub fn bar_length(&self,division: u16) -> u32{
+		self.beat_length(division) * self.numerator.max(1) as u32
+	}
+}
+
+pub fn bpm(tempo: u32) -> f64{
+	60_000_000.0 / tempo as f64
+}
+
+pub fn tempo_from_bpm(bpm: f64) -> u32{
+	(60_000_000.0 / bpm).round() as u32
+}
+
+///The tempo and time signature changes of a song, each ordered by time with at most one change at a time.
+///The default tempo and time signature apply before the first change.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct TempoMap{
+	pub tempos    : Vec<TempoChange>,
+	pub signatures: Vec<TimeSignature>,
+}
+
+impl TempoMap{
+	///Reads the Set Tempo and Time Signature meta events of every track.
+	pub fn from_meta(data: &[u8],chunks: &[Chunk]) -> Self{
+		let mut map = TempoMap::default();
+		for chunk in chunks{
+			if let ChunkKind::Track(_) = chunk.kind{
+				for (time,event) in smf::track_events(data,chunk){
+					match event{
+						RawEvent::Meta{kind: meta::SET_TEMPO,data} if data.len() >= 3 => {
+							let tempo = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+							if tempo > 0{
+								map.set_tempo(time,tempo);
+							}
+						},
+						RawEvent::Meta{kind: meta::TIME_SIGNATURE,data} if data.len() >= 2 && data[1] < 8 => {
+							map.set_signature(time,data[0],1 << data[1]);
+						},
+						_ => (),
+					}
+				}
+			}
+		}
+		map
+	}
+
+	///Microseconds per quarter note at the time.
+	pub fn tempo_at(&self,time: f64) -> u32{
+		self.tempos.iter().rev().find(|change| (change.time as f64) <= time).map_or(DEFAULT_TEMPO,|change| change.tempo)
+	}
+
+	pub fn signature_at(&self,time: f64) -> TimeSignature{
+		self.signatures.iter().rev().find(|signature| (signature.time as f64) <= time).cloned().unwrap_or(TimeSignature::DEFAULT)
+	}
+
+	///The segments of constant tempo as (start time in ticks, tempo), starting at 0.
+	fn segments<'s>(&'s self) -> impl Iterator<Item = (f64,u32)> + 's{
+		let initial = if self.tempos.first().map_or(false,|change| change.time == 0){None}else{Some((0.0 , DEFAULT_TEMPO))};
+		initial.into_iter().chain(self.tempos.iter().map(|change| (change.time as f64 , change.tempo)))
+	}
+
+	///Converts a time in ticks to seconds.
+	pub fn seconds(&self,ticks: f64,division: u16) -> f64{
+		let mut seconds = 0.0;
+		let mut segments = self.segments().peekable();
+		while let Some((start,tempo)) = segments.next(){
+			let end = segments.peek().map_or(ticks,|&(next,_)| next.min(ticks));
+			if end <= start{break}
+			seconds+= (end - start) * tempo as f64 / (division as f64 * 1_000_000.0);
+		}
+		seconds
+	}
+
+	///Converts a time in seconds to ticks.
+	pub fn ticks(&self,seconds: f64,division: u16) -> f64{
+		let mut elapsed = 0.0;
+		let mut segments = self.segments().peekable();
+		while let Some((start,tempo)) = segments.next(){
+			let ticks_per_second = division as f64 * 1_000_000.0 / tempo as f64;
+			match segments.peek(){
+				Some(&(next,_)) if elapsed + (next - start) / ticks_per_second <= seconds => elapsed+= (next - start) / ticks_per_second,
+				_ => return start + (seconds - elapsed) * ticks_per_second,
+			}
+		}
+		0.0
+	}
+
+	///The start of the bar containing the time.
+	pub fn bar_start(&self,time: f64,division: u16) -> u32{
+		let signature = self.signature_at(time);
+		let bar = signature.bar_length(division);
+		signature.time + (time.max(0.0) as u32).saturating_sub(signature.time) / bar * bar
+	}
+
+	///The beats in the range of ticks as their times and whether they begin a bar.
+	///Counting restarts at every time signature change.
+	pub fn beats(&self,division: u16,range: (f64,f64)) -> Vec<(u32,bool)>{
+		let initial = if self.signatures.first().map_or(false,|signature| signature.time == 0){None}else{Some(TimeSignature::DEFAULT)};
+		let signatures: Vec<TimeSignature> = initial.into_iter().chain(self.signatures.iter().cloned()).collect();
+
+		let mut beats = Vec::new();
+		for (i,signature) in signatures.iter().enumerate(){
+			let end = signatures.get(i+1).map_or(range.1,|next| (next.time as f64).min(range.1));
+			let beat = signature.beat_length(division);
+			//Skip the beats before the range
+			let mut index = ((range.0 - signature.time as f64).max(0.0) as u32) / beat;
+			loop{
+				let time = signature.time + index * beat;
+				if time as f64 >= end{break}
+				beats.push((time , index % signature.numerator.max(1) as u32 == 0));
+				index+= 1;
+			}
+		}
+		beats
+	}
+
+	///Sets the tempo from the time, replacing a change at the same time.
+	pub fn set_tempo(&mut self,time: u32,tempo: u32){
+		match self.tempos.binary_search_by_key(&time,|change| change.time){
+			Ok(i)  => self.tempos[i].tempo = tempo,
+			Err(i) => self.tempos.insert(i,TempoChange{time,tempo}),
+		}
+	}
+
+	///Sets the time signature from the time, replacing a change at the same time.
+	pub fn set_signature(&mut self,time: u32,numerator: u8,denominator: u8){
+		let signature = TimeSignature{time,numerator,denominator};
+		match self.signatures.binary_search_by_key(&time,|signature| signature.time){
+			Ok(i)  => self.signatures[i] = signature,
+			Err(i) => self.signatures.insert(i,signature),
+		}
+	}
+}
@@ -0,0 +1,407 @@
+use core::cmp;
+use core::str::FromStr;
+
+use edit::MIN_TONE_LENGTH;
+use key::KeySignature;
+use markers;
+use midi_ext::*;
+use song::Song;
+use tempo::{self,TempoChange,TimeSignature};
+
+///The largest factor that the times can be stretched by.
+pub const MAX_STRETCH: f64 = 100.0;
+
+///A transformation of tones.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Transform{
+	///Moves the pitch by the number of semitones.
+	Transpose(i32),
+	///Multiplies the attack velocities.
+	ScaleVelocity(f64),
+	///Moves the attack velocities towards their mean by the ratio. 0.0 gives every tone the mean velocity, 1.0 leaves them as they are.
+	CompressVelocity(f64),
+	///Randomly offsets the attack velocities by at most the amount in each direction.
+	///The offset is determined by the seed and the tone, so applying it again to the same tones gives the same result.
+	RandomizeVelocity{amount: u8,seed: u32},
+	///Multiplies the start and end times.
+	Stretch(f64),
+	///Mirrors the tones in time so that they are played backwards.
+	Reverse,
+	///Mirrors the pitches around the pivot note.
+	Invert{pivot: u8},
+}
+
+///Properties of the set of tones being transformed that some of the transformations depend on.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Context{
+	///The time span to reverse within.
+	pub span: (u32,u32),
+	pub mean_velocity: f64,
+}
+
+impl Context{
+	pub fn of<'t,I: IntoIterator<Item = &'t Tone>>(tones: I) -> Self{
+		let (mut start,mut end,mut velocity_sum,mut count) = (u32::max_value(),0,0.0,0usize);
+		for tone in tones{
+			start = cmp::min(start,tone.start_time);
+			end   = cmp::max(end,tone.end_time);
+			velocity_sum+= tone.atk_vel as f64;
+			count+= 1;
+		}
+		Context{
+			span: if count == 0{(0,0)}else{(start,end)},
+			mean_velocity: if count == 0{0.0}else{velocity_sum / (count as f64)},
+		}
+	}
+}
+
+///The result of transforming a tone.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Transformed{
+	pub tone: Tone,
+	///Whether the pitch had to be clamped to the note range.
+	pub clamped: bool,
+}
+
+impl Transform{
+	pub fn tone(&self,tone: Tone,context: &Context) -> Transformed{
+		let mut tone = tone;
+		let mut clamped = false;
+		match *self{
+			Transform::Transpose(semitones) => {
+				let note = Into::<u8>::into(tone.note) as i32 + semitones;
+				clamped = note < 0 || note >= NOTES as i32;
+				tone.note = note_from_u8(clamp_u7(note));
+			},
+			Transform::ScaleVelocity(factor) => {
+				tone.atk_vel = clamp_velocity((tone.atk_vel as f64 * factor).round() as i32);
+			},
+			Transform::CompressVelocity(ratio) => {
+				tone.atk_vel = clamp_velocity((context.mean_velocity + (tone.atk_vel as f64 - context.mean_velocity)*ratio).round() as i32);
+			},
+			Transform::RandomizeVelocity{amount,seed} => {
+				let random = hash(&[seed , tone.start_time , Into::<u8>::into(tone.note) as u32 , tone.channel as u32]);
+				let offset = (random % (2*(amount as u32) + 1)) as i32 - (amount as i32);
+				tone.atk_vel = clamp_velocity(tone.atk_vel as i32 + offset);
+			},
+			Transform::Stretch(_) => {
+				tone.start_time = self.time(tone.start_time,context);
+				tone.end_time   = cmp::max(self.time(tone.end_time,context) , tone.start_time.saturating_add(MIN_TONE_LENGTH));
+			},
+			Transform::Reverse => {
+				let (start,end) = (tone.start_time,tone.end_time);
+				tone.start_time = self.time(end,context);
+				tone.end_time   = self.time(start,context);
+			},
+			Transform::Invert{pivot} => {
+				let note = 2*(pivot as i32) - Into::<u8>::into(tone.note) as i32;
+				clamped = note < 0 || note >= NOTES as i32;
+				tone.note = note_from_u8(clamp_u7(note));
+			},
+		}
+		Transformed{tone,clamped}
+	}
+
+	///The time in ticks moved like the times of tones. Only stretching and reversing move times.
+	pub fn time(&self,time: u32,context: &Context) -> u32{
+		match *self{
+			Transform::Stretch(factor) => ((time as f64) * factor.max(0.0)).round() as u32,
+			Transform::Reverse => context.span.0.saturating_add(context.span.1).saturating_sub(time),
+			_ => time,
+		}
+	}
+}
+
+///Applies the transformations in order to a single tone.
+pub fn transform_tone(transforms: &[Transform],tone: Tone,context: &Context) -> Transformed{
+	transforms.iter().fold(Transformed{tone,clamped: false},|result,transform|{
+		let next = transform.tone(result.tone,context);
+		Transformed{tone: next.tone,clamped: result.clamped || next.clamped}
+	})
+}
+
+///Applies the transformations in order to the tones.
+///Returns the number of tones that had their pitches clamped to the note range.
+fn transform_tones(transforms: &[Transform],tones: &mut [Tone],context: &Context) -> usize{
+	let mut clamped = 0;
+	for tone in tones.iter_mut(){
+		let result = transform_tone(transforms,*tone,context);
+		*tone = result.tone;
+		if result.clamped{clamped+= 1;}
+	}
+	clamped
+}
+
+///Applies the transformations in order to every tone in the song, keeping the tracks sorted.
+///Stretching and reversing also move the tempo map, key signatures, lyrics, markers and other events, so that they stay with the tones.
+///The song is reversed within its whole duration rather than within the span of its tones.
+///Returns the number of tones that had their pitches clamped to the note range.
+pub fn transform_song(transforms: &[Transform],song: &mut Song) -> usize{
+	let mut context = Context::of(song.tracks.iter().flat_map(|tones| tones.iter()));
+	context.span = (0,song.duration);
+
+	let mut clamped = 0;
+	for tones in song.tracks.iter_mut(){
+		clamped+= transform_tones(transforms,tones,&context);
+		sort_tones(tones);
+	}
+
+	for transform in transforms{
+		match *transform{
+			Transform::Stretch(_) | Transform::Reverse => (),
+			_ => continue,
+		}
+		let time = |time: u32| transform.time(time,&context);
+
+		for lyric in &mut song.lyrics{lyric.time = time(lyric.time);}
+		song.lyrics.sort_by_key(|lyric| lyric.time);
+		for marker in &mut song.markers{marker.time = time(marker.time);}
+		markers::sort(&mut song.markers);
+		for events in &mut song.other_events{
+			for event in events.iter_mut(){event.0 = time(event.0);}
+			events.sort_by_key(|event| event.0);
+		}
+
+		//Tempos and signatures last until the next change, so when reversing, each one begins where the next one ended
+		if let Transform::Reverse = *transform{
+			let end = context.span.1;
+			reverse_changes(&mut song.tempo.tempos,end,TempoChange{time: 0,tempo: tempo::DEFAULT_TEMPO},|change| &mut change.time);
+			reverse_changes(&mut song.tempo.signatures,end,TimeSignature::DEFAULT,|signature| &mut signature.time);
+			reverse_changes(&mut song.key_signatures,end,KeySignature{time: 0,sharps: 0,minor: false},|key| &mut key.time);
+		}else{
+			for change in &mut song.tempo.tempos{change.time = time(change.time);}
+			for signature in &mut song.tempo.signatures{signature.time = time(signature.time);}
+			for key in &mut song.key_signatures{key.time = time(key.time);}
+		}
+
+		if let Transform::Stretch(factor) = *transform{
+			song.duration = ((song.duration as f64) * factor.max(0.0)).round() as u32;
+		}
+	}
+	song.update_duration();
+	clamped
+}
+
+///Reverses changes ordered by time that each last until the next one, within the time from 0 to the end.
+///The default is what is in effect before the first change.
+fn reverse_changes<T: Copy,F: Fn(&mut T) -> &mut u32>(changes: &mut Vec<T>,end: u32,default: T,time: F){
+	if changes.is_empty(){return}
+	if *time(&mut changes[0]) > 0{
+		changes.insert(0,default);
+	}
+	//Changes from the end on are never in effect
+	let mut starts: Vec<u32> = changes.iter_mut().map(|change| *time(change)).collect();
+	while starts.len() > 1 && *starts.last().unwrap() >= end{
+		starts.pop();
+		changes.pop();
+	}
+
+	for (i,change) in changes.iter_mut().enumerate(){
+		*time(change) = end.saturating_sub(starts.get(i+1).cloned().unwrap_or(end));
+	}
+	changes.reverse();
+}
+
+///Parameters of the transformations adjustable in the GUI.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct TransformSettings{
+	pub transpose        : i32,
+	pub velocity_scale   : f64,
+	pub velocity_compress: f64,
+	pub velocity_random  : u8,
+	pub stretch          : f64,
+	pub reverse          : bool,
+	pub invert           : Option<u8>,
+}
+
+impl Default for TransformSettings{
+	fn default() -> Self{
+		TransformSettings{
+			transpose        : 0,
+			velocity_scale   : 1.0,
+			velocity_compress: 1.0,
+			velocity_random  : 0,
+			stretch          : 1.0,
+			reverse          : false,
+			invert           : None,
+		}
+	}
+}
+
+impl TransformSettings{
+	///The transformations to apply, leaving out the ones that would not change anything.
+	pub fn transforms(&self) -> Vec<Transform>{
+		let mut transforms = Vec::new();
+		if self.transpose != 0            {transforms.push(Transform::Transpose(self.transpose));}
+		if let Some(pivot) = self.invert  {transforms.push(Transform::Invert{pivot});}
+		if self.velocity_scale != 1.0     {transforms.push(Transform::ScaleVelocity(self.velocity_scale));}
+		if self.velocity_compress != 1.0  {transforms.push(Transform::CompressVelocity(self.velocity_compress));}
+		if self.velocity_random != 0      {transforms.push(Transform::RandomizeVelocity{amount: self.velocity_random,seed: 0});}
+		if self.reverse                   {transforms.push(Transform::Reverse);}
+		if self.stretch != 1.0            {transforms.push(Transform::Stretch(self.stretch));}
+		transforms
+	}
+}
+
+///Parses a transformation in the form used on the command line, e.g. `transpose=-12`, `velocity=0.8`, `reverse` or `invert=60`.
+impl FromStr for Transform{
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self,String>{
+		let (name,value) = match s.find('='){
+			Some(i) => (&s[..i] , Some(&s[i+1..])),
+			None    => (s , None),
+		};
+		fn number<T: FromStr>(name: &str,value: Option<&str>) -> Result<T,String>{
+			let value = value.ok_or_else(|| format!("The transformation \"{}\" requires a value (\"{}=<value>\")",name,name))?;
+			value.parse().map_err(|_| format!("Invalid value \"{}\" for the transformation \"{}\"",value,name))
+		}
+
+		match name{
+			"transpose"         => Ok(Transform::Transpose(number(name,value)?)),
+			"velocity"          => Ok(Transform::ScaleVelocity(number(name,value)?)),
+			"compress-velocity" => Ok(Transform::CompressVelocity(number(name,value)?)),
+			"randomize-velocity"=> {
+				//Optionally with a seed: `randomize-velocity=<amount>,<seed>`.
+				let mut parts = value.unwrap_or("").splitn(2,',');
+				let amount = number(name,parts.next().filter(|s| !s.is_empty()))?;
+				let seed   = match parts.next(){Some(seed) => number(name,Some(seed))?,None => 0};
+				Ok(Transform::RandomizeVelocity{amount,seed})
+			},
+			"stretch"           => match number(name,value)?{
+				factor if factor > 0.0 && factor <= MAX_STRETCH => Ok(Transform::Stretch(factor)),
+				_ => Err(format!("The factor of \"{}\" must be greater than 0 and at most {}",name,MAX_STRETCH)),
+			},
+			"reverse"           => Ok(Transform::Reverse),
+			"invert"            => Ok(Transform::Invert{pivot: match value{Some(_) => number(name,value)?,None => 60}}),
+			_ => Err(format!("Unknown transformation \"{}\". Expected one of: transpose, velocity, compress-velocity, randomize-velocity, stretch, reverse, invert",name)),
+		}
+	}
+}
+
+fn clamp_u7(value: i32) -> u8{
+	cmp::min(cmp::max(value,0),127) as u8
+}
+
+///Attack velocities are kept above 0 because a note on with zero velocity is a note off.
+fn clamp_velocity(value: i32) -> u8{
+	cmp::min(cmp::max(value,1),127) as u8
+}
+
+///A simple deterministic hash (FNV-1a) used for randomization.
+fn hash(values: &[u32]) -> u32{
+	let mut hash: u32 = 0x811C9DC5;
+	for value in values{
+		for &byte in &[*value as u8 , (*value >> 8) as u8 , (*value >> 16) as u8 , (*value >> 24) as u8]{
+			hash^= byte as u32;
+			hash = hash.wrapping_mul(0x01000193);
+		}
+	}
+	hash
+}
+
+#[cfg(test)]
+mod tests{
+	use key::KeySignature;
+	use markers::{Marker,MarkerKind};
+	use midi_ext::*;
+	use song::Song;
+	use tempo::{TempoChange,TimeSignature};
+	use super::{transform_song,transform_tone,Context,Transform};
+
+	fn tone(note: u8,start_time: u32,end_time: u32,atk_vel: u8) -> Tone{
+		Tone{note: note_from_u8(note),start_time,end_time,atk_vel,rel_vel: 64,channel: 0,instrument_program: 0,instrument_bank: 0}
+	}
+
+	fn apply(transform: &str,tone: Tone,context: &Context) -> (Tone,bool){
+		let result = transform_tone(&[transform.parse().unwrap()],tone,context);
+		(result.tone,result.clamped)
+	}
+
+	#[test]
+	fn transpose_clamps_to_the_note_range(){
+		let context = Context::of(&[]);
+		assert_eq!(apply("transpose=7",tone(60,0,10,100),&context),(tone(67,0,10,100) , false));
+		assert_eq!(apply("transpose=-12",tone(5,0,10,100),&context),(tone(0,0,10,100) , true));
+		assert_eq!(apply("transpose=12",tone(120,0,10,100),&context),(tone(127,0,10,100) , true));
+	}
+
+	#[test]
+	fn invert_mirrors_around_the_pivot(){
+		let context = Context::of(&[]);
+		assert_eq!(apply("invert",tone(64,0,10,100),&context),(tone(56,0,10,100) , false));
+		assert_eq!(apply("invert=62",tone(60,0,10,100),&context),(tone(64,0,10,100) , false));
+		assert_eq!(apply("invert=100",tone(20,0,10,100),&context),(tone(127,0,10,100) , true));
+	}
+
+	#[test]
+	fn reverse_mirrors_within_the_span(){
+		let tones = [tone(60,10,20,100) , tone(62,20,50,100)];
+		let context = Context::of(&tones);
+		assert_eq!(context.span,(10 , 50));
+		assert_eq!(apply("reverse",tones[0],&context).0,tone(60,40,50,100));
+		assert_eq!(apply("reverse",tones[1],&context).0,tone(62,10,40,100));
+	}
+
+	#[test]
+	fn stretch_keeps_tones_from_overflowing(){
+		let context = Context::of(&[]);
+		assert_eq!(apply("stretch=2",tone(60,10,15,100),&context).0,tone(60,20,30,100));
+		assert_eq!(apply("stretch=0.01",tone(60,10,15,100),&context).0,tone(60,0,1,100));
+
+		let late = Transform::Stretch(100.0).tone(tone(60,::core::u32::MAX - 10,::core::u32::MAX,100),&context).tone;
+		assert_eq!((late.start_time,late.end_time),(::core::u32::MAX , ::core::u32::MAX));
+	}
+
+	#[test]
+	fn parse(){
+		assert_eq!("transpose=-12".parse(),Ok(Transform::Transpose(-12)));
+		assert_eq!("velocity=0.5".parse(),Ok(Transform::ScaleVelocity(0.5)));
+		assert_eq!("compress-velocity=0.25".parse(),Ok(Transform::CompressVelocity(0.25)));
+		assert_eq!("randomize-velocity=10".parse(),Ok(Transform::RandomizeVelocity{amount: 10,seed: 0}));
+		assert_eq!("randomize-velocity=10,7".parse(),Ok(Transform::RandomizeVelocity{amount: 10,seed: 7}));
+		assert_eq!("stretch=1.5".parse(),Ok(Transform::Stretch(1.5)));
+		assert_eq!("reverse".parse(),Ok(Transform::Reverse));
+		assert_eq!("invert".parse(),Ok(Transform::Invert{pivot: 60}));
+
+		for invalid in &["transpose" , "transpose=up" , "randomize-velocity=" , "stretch=0" , "stretch=-1" , "stretch=1000" , "stretch=NaN" , "shuffle"]{
+			assert!(invalid.parse::<Transform>().is_err(),"{}",invalid);
+		}
+	}
+
+	fn song() -> Song{
+		let mut song = Song::empty();
+		song.tracks = vec![vec![tone(60,0,48,100) , tone(64,48,96,100)]];
+		song.duration = 96;
+		song.tempo.tempos = vec![TempoChange{time: 0,tempo: 400_000} , TempoChange{time: 48,tempo: 600_000}];
+		song.tempo.signatures = vec![TimeSignature{time: 24,numerator: 3,denominator: 4}];
+		song.key_signatures = vec![KeySignature{time: 0,sharps: 2,minor: false}];
+		song.markers = vec![Marker{time: 12,kind: MarkerKind::Marker,text: "Verse".to_string()}];
+		song.other_events = vec![vec![(0 , vec![0xB0 , 0x40 , 0x7F]) , (90 , vec![0xB0 , 0x40 , 0x00])]];
+		song
+	}
+
+	#[test]
+	fn stretching_a_song_moves_everything_timed(){
+		let mut song = song();
+		transform_song(&["stretch=2".parse().unwrap()],&mut song);
+		assert_eq!(song.duration,192);
+		assert_eq!(song.tracks[0].iter().map(|tone| (tone.start_time,tone.end_time)).collect::<Vec<_>>(),vec![(0 , 96) , (96 , 192)]);
+		assert_eq!(song.tempo.tempos.iter().map(|change| change.time).collect::<Vec<_>>(),vec![0 , 96]);
+		assert_eq!(song.tempo.signatures[0].time,48);
+		assert_eq!(song.markers[0].time,24);
+		assert_eq!(song.other_events[0].iter().map(|event| event.0).collect::<Vec<_>>(),vec![0 , 180]);
+	}
+
+	#[test]
+	fn reversing_a_song_reverses_the_changes(){
+		let mut song = song();
+		transform_song(&["reverse".parse().unwrap()],&mut song);
+		assert_eq!(song.tracks[0].iter().map(|tone| (tone.start_time,tone.end_time)).collect::<Vec<_>>(),vec![(0 , 48) , (48 , 96)]);
+		assert_eq!(song.tempo.tempos,vec![TempoChange{time: 0,tempo: 600_000} , TempoChange{time: 48,tempo: 400_000}]);
+		assert_eq!(song.tempo.signatures,vec![TimeSignature{time: 0,numerator: 3,denominator: 4} , TimeSignature{time: 72,numerator: 4,denominator: 4}]);
+		assert_eq!(song.key_signatures,vec![KeySignature{time: 0,sharps: 2,minor: false}]);
+		assert_eq!(song.markers[0].time,84);
+		assert_eq!(song.other_events[0],vec![(6 , vec![0xB0 , 0x40 , 0x00]) , (96 , vec![0xB0 , 0x40 , 0x7F])]);
+	}
+}
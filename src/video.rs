@@ -0,0 +1,62 @@
+//Rendering of videos of the piano roll scrolling past a playhead.
+
+use std::io::{self,Write};
+
+use rusttype::Font;
+
+use raster::{self,Image};
+use render::{self,View,KEYBOARD_WIDTH};
+use song::{Filter,Song};
+
+///Where the playhead is in the frames, as a part of the width of the roll from the left.
+pub const PLAYHEAD_POSITION: f64 = 0.25;
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct VideoSettings{
+	///Size of the frames in pixels.
+	pub size : [u32; 2],
+	///Frames per second.
+	pub fps  : u32,
+	///Width of a tick in pixels.
+	pub zoom : f64,
+	///Range of times in ticks.
+	pub range: (f64,f64),
+}
+
+impl VideoSettings{
+	///The times in ticks of the frames, following the tempo map.
+	pub fn frame_times(&self,song: &Song) -> Vec<f64>{
+		let start = song.tempo.seconds(self.range.0,song.division);
+		let end = song.tempo.seconds(self.range.1,song.division);
+		let count = ((end - start) * self.fps as f64).ceil() as usize + 1;
+		(0..count).map(|frame| song.tempo.ticks(start + frame as f64 / self.fps.max(1) as f64,song.division).min(self.range.1)).collect()
+	}
+
+	///The frame with the playhead at the time in ticks.
+	pub fn frame(&self,song: &Song,font: &Font,time: f64) -> Image{
+		let fitted = View::fit(song,self.range,self.size);
+		let span = (self.size[0] as f64 - KEYBOARD_WIDTH).max(1.0) / self.zoom;
+		let view = View{
+			times    : (time - span*PLAYHEAD_POSITION , time + span*(1.0 - PLAYHEAD_POSITION)),
+			notes    : fitted.notes,
+			tone_size: [self.zoom , fitted.tone_size[1]],
+		};
+		raster::rasterize(&render::draw(song,&Filter::default(),&view,Some(time)),font,self.size)
+	}
+}
+
+///Writes the header of a YUV4MPEG2 stream, which has frames of 8 bit Y'CbCr without chroma subsampling.
+pub fn write_y4m_header<W: Write>(settings: &VideoSettings,out: &mut W) -> io::Result<()>{
+	writeln!(out,"YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",settings.size[0],settings.size[1],settings.fps)
+}
+
+///Writes a frame of a YUV4MPEG2 stream. The colors are converted by ITU-R BT.601 in limited range, and drawn over black.
+pub fn write_y4m_frame<W: Write>(image: &Image,out: &mut W) -> io::Result<()>{
+	let pixels: Vec<[f32; 3]> = image.pixels.iter().map(|pixel| [pixel[0]*pixel[3] , pixel[1]*pixel[3] , pixel[2]*pixel[3]]).collect();
+	let plane = |f: &dyn Fn([f32; 3]) -> f32| -> Vec<u8>{pixels.iter().map(|&rgb| f(rgb).round().max(0.0).min(255.0) as u8).collect()};
+
+	out.write_all(b"FRAME\n")?;
+	out.write_all(&plane(&|[r,g,b]|  16.0 + 65.481*r + 128.553*g + 24.966*b))?;
+	out.write_all(&plane(&|[r,g,b]| 128.0 - 37.797*r -  74.203*g + 112.0 *b))?;
+	out.write_all(&plane(&|[r,g,b]| 128.0 + 112.0 *r -  93.786*g - 18.214*b))
+}